@@ -14,9 +14,107 @@ function parseFrag(html: string): string {
     return core.opSync('deno_dom_parse_frag_sync', html) as string;
 }
 
+function parseXml(xml: string): string {
+    return core.opSync('deno_dom_parse_xml_sync', xml) as string;
+}
+
 register(parse, parseFrag);
 
 import * as domApi from './deno-dom/src/api.ts';
 for (const k in domApi) {
     globalThis[k] = domApi[k];
 }
+
+// deno-dom only parses HTML, so XML documents are parsed by a separate op and built here
+const XML_MIME_TYPES = ['text/xml', 'application/xml', 'application/xhtml+xml', 'image/svg+xml'];
+const PARSER_ERROR_NS = 'http://www.mozilla.org/newlayout/xml/parsererror.xml';
+
+// XML node types, see parse_xml in src/xml_parser.rs
+const ELEMENT_NODE = 1;
+const TEXT_NODE = 3;
+const CDATA_SECTION_NODE = 4;
+const COMMENT_NODE = 8;
+
+function createXmlElement(doc: any, qualifiedName: string, namespaceURI: string | null): any {
+    // deno-dom will still uppercase the tag name, which keeps its selector engine working
+    const element = doc.createElement(qualifiedName);
+    const sep = qualifiedName.indexOf(':');
+    Object.defineProperties(element, {
+        namespaceURI: { value: namespaceURI, configurable: true },
+        prefix: { value: sep === -1 ? null : qualifiedName.substr(0, sep), configurable: true },
+        localName: { value: qualifiedName.substr(sep + 1), configurable: true },
+    });
+    return element;
+}
+
+function xmlNodeFromArray(doc: any, data: any[]): any {
+    switch (data[0]) {
+        case ELEMENT_NODE: {
+            const [, name, namespaceURI, attrs, children] = data;
+            const element = createXmlElement(doc, name, namespaceURI);
+            for (const [k, v] of attrs) element.setAttribute(k, v);
+            for (const child of children) {
+                const node = xmlNodeFromArray(doc, child);
+                if (node) element.appendChild(node);
+            }
+            return element;
+        }
+        case TEXT_NODE:
+        case CDATA_SECTION_NODE:
+            // deno-dom has no CDATASection
+            return doc.createTextNode(data[1]);
+        case COMMENT_NODE:
+            return doc.createComment(data[1]);
+        default:
+            // deno-dom has no ProcessingInstruction
+            return null;
+    }
+}
+
+function parseXmlDocument(source: string, mimeType: string): any {
+    const doc = new domApi.Document();
+    Object.defineProperty(doc, 'contentType', { value: mimeType, configurable: true });
+
+    let data;
+    try {
+        data = JSON.parse(parseXml(source));
+    } catch (err) {
+        const error = createXmlElement(doc, 'parsererror', PARSER_ERROR_NS);
+        error.textContent = err.message;
+        doc.appendChild(error);
+        return doc;
+    }
+
+    for (const child of data[1]) {
+        const node = xmlNodeFromArray(doc, child);
+        if (node) doc.appendChild(node);
+    }
+    return doc;
+}
+
+class DOMParser extends domApi.DOMParser {
+    parseFromString(source: string, mimeType: string): any {
+        if (XML_MIME_TYPES.includes(mimeType)) return parseXmlDocument(source, mimeType);
+        return super.parseFromString(source, mimeType);
+    }
+}
+globalThis.DOMParser = DOMParser;
+
+function getElementsByTagNameNS(namespaceURI: string | null, localName: string): any[] {
+    const out = [];
+    const walk = (node: any) => {
+        for (const child of node.childNodes) {
+            if (child.nodeType !== ELEMENT_NODE) continue;
+            if ((namespaceURI === '*' || child.namespaceURI === namespaceURI)
+                && (localName === '*' || child.localName === localName)) {
+                out.push(child);
+            }
+            walk(child);
+        }
+    };
+    walk(this);
+    return out;
+}
+for (const proto of [domApi.Document.prototype, domApi.Element.prototype]) {
+    if (!proto.getElementsByTagNameNS) proto.getElementsByTagNameNS = getElementsByTagNameNS;
+}
@@ -7,6 +7,8 @@ use deno_core::ZeroCopyBuf;
 use deno_dom_core::parse as parse_rs;
 use deno_dom_core::parse_frag as parse_frag_rs;
 
+mod xml_parser;
+
 pub fn init() -> Extension {
     Extension::builder()
         .ops(vec![
@@ -15,6 +17,7 @@ pub fn init() -> Extension {
                 "deno_dom_parse_frag_sync",
                 op_sync(deno_dom_parse_frag_sync),
             ),
+            ("deno_dom_parse_xml_sync", op_sync(deno_dom_parse_xml_sync)),
         ])
         .js(include_js_files! {
             prefix "deno:extensions/deno_dom",
@@ -38,3 +41,11 @@ fn deno_dom_parse_frag_sync(
 ) -> Result<String, AnyError> {
     Ok(parse_frag_rs(data_str))
 }
+
+fn deno_dom_parse_xml_sync(
+    _state: &mut OpState,
+    data_str: String,
+    _zero_copy: Option<ZeroCopyBuf>,
+) -> Result<String, AnyError> {
+    Ok(xml_parser::parse_xml(&data_str)?)
+}
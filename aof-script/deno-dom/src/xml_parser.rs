@@ -0,0 +1,124 @@
+use deno_core::serde_json::{self, json, Value};
+use std::collections::BTreeMap;
+use xml::namespace::{Namespace, NS_EMPTY_URI, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+
+// Node types, same as in the DOM.
+const ELEMENT_NODE: u8 = 1;
+const TEXT_NODE: u8 = 3;
+const CDATA_SECTION_NODE: u8 = 4;
+const PROCESSING_INSTRUCTION_NODE: u8 = 7;
+const COMMENT_NODE: u8 = 8;
+const DOCUMENT_NODE: u8 = 9;
+
+/// Parses an XML document and serializes it to JSON.
+///
+/// Nodes are serialized as arrays:
+///
+/// - document: `[9, children]`
+/// - element: `[1, qualifiedName, namespaceURI | null, [[attrName, attrValue], ...], children]`
+/// - text: `[3, data]`
+/// - CDATA section: `[4, data]`
+/// - processing instruction: `[7, target, data]`
+/// - comment: `[8, data]`
+///
+/// Namespace declarations are included in the attributes as `xmlns` and `xmlns:prefix`.
+pub fn parse_xml(source: &str) -> Result<String, xml::reader::Error> {
+    let reader = EventReader::new_with_config(
+        source.as_bytes(),
+        ParserConfig::new()
+            .trim_whitespace(false)
+            .whitespace_to_characters(true)
+            .cdata_to_characters(false)
+            .coalesce_characters(true)
+            .ignore_comments(false),
+    );
+
+    // stack of open elements; the first entry is the document
+    let mut stack: Vec<(Value, BTreeMap<String, String>, Vec<Value>)> =
+        vec![(Value::Null, Namespace::empty().0, Vec::new())];
+
+    for event in reader {
+        match event? {
+            XmlEvent::StartDocument { .. } | XmlEvent::EndDocument => (),
+            XmlEvent::ProcessingInstruction { name, data } => {
+                let node = json!([PROCESSING_INSTRUCTION_NODE, name, data.unwrap_or_default()]);
+                push_child(&mut stack, node);
+            }
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let parent_ns = &stack.last().unwrap().1;
+
+                let mut attrs = Vec::new();
+                for (prefix, uri) in &namespace.0 {
+                    if prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX {
+                        continue;
+                    }
+                    if parent_ns.get(prefix) == Some(uri) {
+                        continue;
+                    }
+                    if prefix == NS_NO_PREFIX
+                        && uri == NS_EMPTY_URI
+                        && !parent_ns.contains_key(prefix)
+                    {
+                        continue;
+                    }
+                    let attr_name = if prefix == NS_NO_PREFIX {
+                        String::from("xmlns")
+                    } else {
+                        format!("xmlns:{}", prefix)
+                    };
+                    attrs.push(json!([attr_name, uri]));
+                }
+                for attr in attributes {
+                    attrs.push(json!([qualified_name(&attr.name), attr.value]));
+                }
+
+                let namespace_uri = match name.namespace.as_ref().map(|s| &**s) {
+                    Some(NS_EMPTY_URI) | None => Value::Null,
+                    Some(uri) => Value::String(uri.to_string()),
+                };
+                let header = json!([ELEMENT_NODE, qualified_name(&name), namespace_uri, attrs]);
+                stack.push((header, namespace.0, Vec::new()));
+            }
+            XmlEvent::EndElement { .. } => {
+                let (header, _, children) = stack.pop().unwrap();
+                let mut node = match header {
+                    Value::Array(node) => node,
+                    _ => unreachable!(),
+                };
+                node.push(Value::Array(children));
+                push_child(&mut stack, Value::Array(node));
+            }
+            XmlEvent::CData(data) => {
+                push_child(&mut stack, json!([CDATA_SECTION_NODE, data]));
+            }
+            XmlEvent::Comment(data) => {
+                push_child(&mut stack, json!([COMMENT_NODE, data]));
+            }
+            XmlEvent::Characters(data) | XmlEvent::Whitespace(data) => {
+                // text is not allowed outside the root element
+                if stack.len() > 1 {
+                    push_child(&mut stack, json!([TEXT_NODE, data]));
+                }
+            }
+        }
+    }
+
+    let (_, _, children) = stack.pop().unwrap();
+    Ok(serde_json::to_string(&json!([DOCUMENT_NODE, children])).unwrap())
+}
+
+fn push_child(stack: &mut Vec<(Value, BTreeMap<String, String>, Vec<Value>)>, node: Value) {
+    stack.last_mut().unwrap().2.push(node);
+}
+
+fn qualified_name(name: &xml::name::OwnedName) -> String {
+    match &name.prefix {
+        Some(prefix) => format!("{}:{}", prefix, name.local_name),
+        None => name.local_name.clone(),
+    }
+}
@@ -1,14 +1,33 @@
-import { loadSource, loadSourceItem } from "###MODULE###";
+import * as domain from "###MODULE###";
 
 async function run() {
     const req = Deno.core.opSync('aof_get_request');
     let res;
     if (req.type === 'source') {
-        console.info(`Loading source ${req.path}`);
-        res = await loadSource(req.path);
+        if (req.previous && typeof domain.loadSourceIncremental === 'function') {
+            console.info(`Loading source ${req.path} incrementally`);
+            res = await domain.loadSourceIncremental(req.path, req.previous, req.options);
+        } else {
+            console.info(`Loading source ${req.path}`);
+            res = await domain.loadSource(req.path, req.options);
+        }
     } else if (req.type === 'source-item') {
         console.info(`Loading source item ${req.path}`);
-        res = await loadSourceItem(req.path);
+        res = await domain.loadSourceItem(req.path, req.options);
+    } else if (req.type === 'search') {
+        if (typeof domain.searchSources !== 'function') {
+            throw new Error('This domain does not support searching (searchSources is not exported)');
+        }
+        console.info(`Searching for ${JSON.stringify(req.query)}`);
+        res = await domain.searchSources(req.query);
+    } else if (req.type === 'url-patterns') {
+        res = {
+            patterns: domain.urlPatterns || [],
+            match_url: typeof domain.matchUrl === 'function',
+        };
+    } else if (req.type === 'match-url') {
+        console.info(`Matching URL ${req.url}`);
+        res = { path: (await domain.matchUrl(req.url)) ?? null };
     }
     console.info('Script executed successfully');
     if (typeof res !== 'object') throw new Error('Result is not an object; got ' + res);
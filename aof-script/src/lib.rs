@@ -1,11 +1,18 @@
 pub(crate) mod ops;
+mod permission;
+mod result;
 mod rt;
 mod script_ctx;
 
 pub use deno_core::error::AnyError;
 pub use deno_core::url;
 pub use ops::console;
-pub use ops::USER_AGENT;
+pub use ops::{CachedResponse, Cassette, CassetteRequest, CookieJar, FetchMode, USER_AGENT};
+pub use permission::{
+    check_allowed_hosts, is_valid_host_pattern, request_fetch_permission, IpCidr, NetworkPolicy,
+    ALLOWED_HOSTS_MAX,
+};
 pub use reqwest;
+pub use result::*;
 pub use rt::*;
 pub use script_ctx::*;
@@ -1,27 +1,439 @@
-use aof_script::{url, ModLoader, ScriptRt, UnrestrictedContext};
-use futures::StreamExt;
-use std::sync::Arc;
+//! Runs a domain script from the command line, the same way the server does.
+//!
+//! Console output is printed to stderr, and the validated result is printed to stdout as JSON.
 
-async fn run() {
-    let ctx = UnrestrictedContext;
-    let mut mod_loader = ModLoader::new();
+use aof_script::console::{redact_secrets, ConsoleMessage};
+use aof_script::{
+    check_allowed_hosts, is_valid_host_pattern, request_fetch_permission, reqwest, url::Url,
+    validate_search_results, validate_source, validate_source_item, validate_url_match,
+    validate_url_patterns, AofRequest, CachedResponse, Cassette, CassetteRequest, FetchMode,
+    HeapLimitExceeded, InnerScript, IssueLevel, NetworkPolicy, ScriptContext,
+};
+use deno_core::serde_json::{self, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-    let mut source = String::new();
-    std::io::Read::read_to_string(&mut std::io::stdin(), &mut source).unwrap();
+const USAGE: &str = "usage: aof-script-exec [options] <request>
 
-    let mod_spec = "file:///main.js";
-    let mod_spec = url::Url::parse(mod_spec).unwrap();
+requests:
+    --source <path>         load a source
+    --item <path>           load a source item
+    --search <query>        search for sources
+    --url-patterns          list the URL patterns of the domain
+    --match-url <url>       find the source path for a web URL
 
-    mod_loader.insert(mod_spec.clone(), source);
+options:
+    --domain-file <file>    domain script to run (default: read from stdin)
+    --exec-time <seconds>   max script execution time, not counting requests (default: 6)
+    --previous <file>       previous version of the source as JSON, for incremental fetches
+    --options <json>        domain options as a JSON object (default: {})
+    --record <cassette>     record all responses to a cassette file
+    --replay <cassette>     serve responses from a cassette file instead of the network
+    --secret <name>=<value> make a secret available to the script (may be repeated)
+    --allow-host <pattern>  only allow requests to matching hosts, like the domain's allowed hosts
+                            (may be repeated; default: any host)";
 
-    let mut rt = ScriptRt::new(Arc::new(ctx), mod_loader).expect("Failed to initialize runtime");
-    let mut module = rt.eval_module(mod_spec).await.unwrap();
-    rt.run_event_loop().await.unwrap();
-    while let Some(next) = module.next().await {
-        next.unwrap();
+/// Default max script execution time (same as in the default server config).
+const DEFAULT_EXEC_TIME: Duration = Duration::from_secs(6);
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Args {
+    domain_file: Option<PathBuf>,
+    request: AofRequest,
+    previous_file: Option<PathBuf>,
+    exec_time: Duration,
+    fetch_mode: FetchMode,
+    cassette_path: Option<PathBuf>,
+    secrets: HashMap<String, String>,
+    allowed_hosts: Option<Vec<String>>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut domain_file = None;
+        let mut request = None;
+        let mut previous_file = None;
+        let mut options = Map::new();
+        let mut exec_time = DEFAULT_EXEC_TIME;
+        let mut fetch_mode = FetchMode::Live;
+        let mut cassette_path = None;
+        let mut secrets = HashMap::new();
+        let mut allowed_hosts: Option<Vec<String>> = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value", arg))
+            };
+            match &*arg {
+                "--domain-file" => domain_file = Some(PathBuf::from(value()?)),
+                "--source" | "--item" | "--search" | "--url-patterns" | "--match-url" => {
+                    if request.is_some() {
+                        return Err(String::from("only one request may be given"));
+                    }
+                    request = Some(match &*arg {
+                        "--source" => AofRequest::Source {
+                            path: value()?,
+                            previous: None,
+                            options: Map::new(),
+                        },
+                        "--item" => AofRequest::SourceItem {
+                            path: value()?,
+                            options: Map::new(),
+                        },
+                        "--search" => AofRequest::Search { query: value()? },
+                        "--url-patterns" => AofRequest::UrlPatterns,
+                        _ => AofRequest::MatchUrl { url: value()? },
+                    });
+                }
+                "--previous" => previous_file = Some(PathBuf::from(value()?)),
+                "--options" => {
+                    let json = value()?;
+                    options = match serde_json::from_str(&json) {
+                        Ok(Value::Object(map)) => map,
+                        _ => return Err(format!("invalid options {:?}", json)),
+                    };
+                }
+                "--exec-time" => {
+                    let secs = value()?;
+                    exec_time = match secs.parse() {
+                        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                        _ => return Err(format!("invalid execution time {:?}", secs)),
+                    };
+                }
+                "--record" | "--replay" => {
+                    if cassette_path.is_some() {
+                        return Err(String::from("only one cassette may be specified"));
+                    }
+                    fetch_mode = if arg == "--record" {
+                        FetchMode::Record
+                    } else {
+                        FetchMode::Replay
+                    };
+                    cassette_path = Some(PathBuf::from(value()?));
+                }
+                "--secret" => {
+                    let secret = value()?;
+                    match secret.find('=') {
+                        Some(i) if i > 0 => {
+                            secrets.insert(secret[..i].to_string(), secret[i + 1..].to_string());
+                        }
+                        _ => {
+                            return Err(String::from(
+                                "--secret requires a value of the form name=value",
+                            ))
+                        }
+                    }
+                }
+                "--allow-host" => {
+                    let pattern = value()?;
+                    if !is_valid_host_pattern(&pattern) {
+                        return Err(format!("invalid host pattern {:?}", pattern));
+                    }
+                    allowed_hosts.get_or_insert_with(Vec::new).push(pattern);
+                }
+                _ => return Err(format!("unexpected argument {:?}", arg)),
+            }
+        }
+
+        let mut request = match request {
+            Some(request) => request,
+            None => return Err(String::from("a request is required")),
+        };
+        if previous_file.is_some() && !matches!(request, AofRequest::Source { .. }) {
+            return Err(String::from("--previous can only be used with --source"));
+        }
+        match &mut request {
+            AofRequest::Source {
+                options: request_options,
+                ..
+            }
+            | AofRequest::SourceItem {
+                options: request_options,
+                ..
+            } => *request_options = options,
+            _ if !options.is_empty() => {
+                return Err(String::from(
+                    "--options can only be used with --source or --item",
+                ))
+            }
+            _ => (),
+        }
+
+        Ok(Args {
+            domain_file,
+            request,
+            previous_file,
+            exec_time,
+            fetch_mode,
+            cassette_path,
+            secrets,
+            allowed_hosts,
+        })
+    }
+}
+
+/// Tracks script execution time, not counting time spent on requests.
+struct ExecTimer {
+    start_time: Instant,
+    fetch_time: Duration,
+    current_fetch_start: Option<Instant>,
+}
+
+impl ExecTimer {
+    fn script_time(&self) -> Duration {
+        let fetch_time = self.fetch_time
+            + self
+                .current_fetch_start
+                .map_or(Duration::from_secs(0), |start| start.elapsed());
+        self.start_time
+            .elapsed()
+            .checked_sub(fetch_time)
+            .unwrap_or(Duration::from_secs(0))
+    }
+}
+
+/// Execution context for running scripts from the command line.
+struct ExecContext {
+    request: AofRequest,
+    response: Mutex<Option<Value>>,
+    timer: Mutex<ExecTimer>,
+    fetch_mode: FetchMode,
+    cassette_path: Option<PathBuf>,
+    cassette: Mutex<Cassette>,
+    secrets: HashMap<String, String>,
+    allowed_hosts: Option<Vec<String>>,
+}
+
+impl ExecContext {
+    fn new(args: &Args) -> Result<Self, String> {
+        let cassette = match (args.fetch_mode, &args.cassette_path) {
+            (FetchMode::Replay, Some(path)) => {
+                let cassette = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read cassette {:?}: {}", path, e))?;
+                serde_json::from_str(&cassette)
+                    .map_err(|e| format!("failed to parse cassette {:?}: {}", path, e))?
+            }
+            _ => Cassette::default(),
+        };
+
+        let mut request = args.request.clone();
+        if let (AofRequest::Source { previous, .. }, Some(path)) =
+            (&mut request, &args.previous_file)
+        {
+            let data = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read previous source {:?}: {}", path, e))?;
+            *previous = Some(
+                serde_json::from_str(&data)
+                    .map_err(|e| format!("failed to parse previous source {:?}: {}", path, e))?,
+            );
+        }
+
+        Ok(ExecContext {
+            request,
+            response: Mutex::new(None),
+            timer: Mutex::new(ExecTimer {
+                start_time: Instant::now(),
+                fetch_time: Duration::from_secs(0),
+                current_fetch_start: None,
+            }),
+            fetch_mode: args.fetch_mode,
+            cassette_path: args.cassette_path.clone(),
+            cassette: Mutex::new(cassette),
+            secrets: args.secrets.clone(),
+            allowed_hosts: args.allowed_hosts.clone(),
+        })
     }
 }
 
+impl ScriptContext for ExecContext {
+    fn request_permission(&self, _method: &reqwest::Method, url: &Url) -> Result<(), String> {
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            check_allowed_hosts(url, allowed_hosts)?;
+        }
+        request_fetch_permission(url, &NetworkPolicy::default(), |addr| {
+            eprintln!("[warn] Direct access of ip address {:?}", addr);
+        })?;
+        Ok(())
+    }
+
+    fn network_policy(&self) -> Option<NetworkPolicy> {
+        Some(NetworkPolicy::default())
+    }
+
+    fn fetch_did_start(&self) {
+        self.timer.lock().unwrap().current_fetch_start = Some(Instant::now());
+    }
+
+    fn fetch_did_end(&self) {
+        let mut timer = self.timer.lock().unwrap();
+        let start = timer
+            .current_fetch_start
+            .take()
+            .expect("fetch ended without starting");
+        timer.fetch_time += start.elapsed();
+    }
+
+    fn on_console_message(&self, msg: ConsoleMessage) {
+        eprintln!("[JS] {}", msg);
+    }
+
+    fn get_aof_request(&self) -> AofRequest {
+        self.request.clone()
+    }
+
+    fn set_aof_response(&self, data: Value) {
+        *self.response.lock().unwrap() = Some(data);
+    }
+
+    fn fetch_mode(&self) -> FetchMode {
+        self.fetch_mode
+    }
+
+    fn cassette_get(&self, request: &CassetteRequest) -> Result<Option<CachedResponse>, String> {
+        Ok(self.cassette.lock().unwrap().response(request))
+    }
+
+    fn cassette_record(
+        &self,
+        request: CassetteRequest,
+        response: CachedResponse,
+    ) -> Result<(), String> {
+        let path = match &self.cassette_path {
+            Some(path) => path,
+            None => return Err(String::from("no cassette path")),
+        };
+
+        // the cassette is written after every request so that nothing is lost if the script fails
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.record(request, response, usize::MAX);
+        let data = serde_json::to_string_pretty(&*cassette)
+            .map_err(|e| format!("failed to encode cassette: {}", e))?;
+        fs::write(path, data).map_err(|e| format!("failed to write cassette {:?}: {}", path, e))
+    }
+
+    fn secret_get(&self, name: &str) -> Result<Option<String>, String> {
+        Ok(self.secrets.get(name).cloned())
+    }
+
+    fn revealed_secrets(&self) -> Vec<String> {
+        // all secrets are redacted, since they were given on the command line anyway
+        self.secrets.values().cloned().collect()
+    }
+}
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", err);
+    process::exit(1);
+}
+
+/// Kills the process if the script runs for too long, like the server would.
+fn start_watchdog(ctx: Arc<ExecContext>, exec_time: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(WATCHDOG_INTERVAL);
+        if ctx.timer.lock().unwrap().script_time() >= exec_time {
+            fail("script execution took too long (infinite loop?)");
+        }
+    });
+}
+
+fn read_script(domain_file: Option<&Path>) -> Result<(String, String), String> {
+    match domain_file {
+        Some(path) => {
+            let script = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read domain file {:?}: {}", path, e))?;
+            let domain = path
+                .file_stem()
+                .map_or(String::from("domain"), |s| s.to_string_lossy().to_string());
+            Ok((domain, script))
+        }
+        None => {
+            let mut script = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut script)
+                .map_err(|e| format!("failed to read script from stdin: {}", e))?;
+            Ok((String::from("domain"), script))
+        }
+    }
+}
+
+/// Validates the script result like the server would and returns it in the format that would be
+/// stored. Issues are printed to stderr.
+fn validate_response(request: &AofRequest, data: Value) -> Option<Value> {
+    let (data, issues) = match request {
+        AofRequest::Source { .. } => {
+            let (data, issues) = validate_source(data);
+            (data.map(|data| serde_json::to_value(data)), issues)
+        }
+        AofRequest::SourceItem { .. } => {
+            let (data, issues) = validate_source_item(data);
+            (data.map(|data| serde_json::to_value(data)), issues)
+        }
+        AofRequest::Search { .. } => {
+            let (data, issues) = validate_search_results(data);
+            (data.map(|data| serde_json::to_value(data)), issues)
+        }
+        AofRequest::UrlPatterns => {
+            let (data, issues) = validate_url_patterns(data);
+            (data.map(|data| serde_json::to_value(data)), issues)
+        }
+        AofRequest::MatchUrl { .. } => {
+            let (data, issues) = validate_url_match(data);
+            (data.map(|data| serde_json::to_value(data)), issues)
+        }
+    };
+    for issue in issues {
+        match issue.level {
+            IssueLevel::Error => eprintln!("[error] {}", issue),
+            IssueLevel::Warning => eprintln!("[warn] {}", issue),
+        }
+    }
+    data.map(|data| data.expect("failed to serialize result"))
+}
+
+async fn run(args: Args) {
+    let (domain, script) = read_script(args.domain_file.as_deref()).unwrap_or_else(|e| fail(e));
+    let ctx = Arc::new(ExecContext::new(&args).unwrap_or_else(|e| fail(e)));
+
+    let mut script =
+        InnerScript::create(Arc::clone(&ctx) as Arc<dyn ScriptContext>, &domain, &script)
+            .unwrap_or_else(|e| fail(e));
+    start_watchdog(Arc::clone(&ctx), args.exec_time);
+
+    if let Err(err) = script.run().await {
+        if err.is::<HeapLimitExceeded>() {
+            fail("script exceeded its memory limit");
+        }
+        let err = redact_secrets(&err.to_string(), &ctx.revealed_secrets());
+        fail(format!("script execution error: {}", err));
+    }
+
+    let response = match ctx.response.lock().unwrap().take() {
+        Some(response) => response,
+        None => fail("script ended without result"),
+    };
+    let response = match validate_response(&args.request, response) {
+        Some(response) => response,
+        None => fail("script returned an invalid result"),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&response).unwrap());
+}
+
 fn main() {
-    smol::block_on(run());
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    smol::block_on(run(args));
 }
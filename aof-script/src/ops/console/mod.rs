@@ -98,6 +98,39 @@ pub struct ConsoleMessage {
     pub message: Vec<MsgFrag>,
 }
 
+impl ConsoleMessage {
+    /// Replaces all occurrences of the given secret values in this message.
+    pub fn redact(&mut self, secrets: &[String]) {
+        if secrets.is_empty() {
+            return;
+        }
+        for frag in &mut self.message {
+            match frag {
+                MsgFrag::Log(s)
+                | MsgFrag::ClassName(s)
+                | MsgFrag::ErrorTrace(s)
+                | MsgFrag::String(s)
+                | MsgFrag::Symbol(s)
+                | MsgFrag::KeyString(s)
+                | MsgFrag::KeySymbol(s)
+                | MsgFrag::Function(s) => *s = redact_secrets(s, secrets),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Replaces all occurrences of the given secret values in a string.
+pub fn redact_secrets(text: &str, secrets: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets {
+        if !secret.is_empty() {
+            text = text.replace(&**secret, "[secret]");
+        }
+    }
+    text
+}
+
 impl fmt::Display for ConsoleMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.msg_type {
@@ -386,10 +419,13 @@ fn console_msg(ty: MessageType, scope: &mut v8::HandleScope, args: v8::FunctionC
         arg_to_frag(tc_scope, &mut message, args.get(i));
     }
     let state = tc_scope.get_slot::<ConsoleState>().unwrap();
-    state.ctx.on_console_message(ConsoleMessage {
+    let mut msg = ConsoleMessage {
         msg_type: ty,
         message,
-    });
+    };
+    // secrets must never end up in logs
+    msg.redact(&state.ctx.revealed_secrets());
+    state.ctx.on_console_message(msg);
 }
 
 fn console_debug(
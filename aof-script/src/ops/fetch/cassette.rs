@@ -0,0 +1,148 @@
+use super::CachedResponse;
+use deno_core::serde_json;
+use serde::{Deserialize, Serialize};
+
+/// How fetch requests are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FetchMode {
+    /// Requests are sent over the network.
+    #[serde(rename = "live")]
+    Live,
+    /// Requests are sent over the network, and responses are recorded to a cassette.
+    #[serde(rename = "record")]
+    Record,
+    /// Responses are served from a cassette. Requests that were not recorded will fail.
+    #[serde(rename = "replay")]
+    Replay,
+}
+
+impl Default for FetchMode {
+    fn default() -> Self {
+        FetchMode::Live
+    }
+}
+
+/// A request, as far as cassettes are concerned.
+///
+/// Headers are not considered when matching requests, since they may contain cookies or other
+/// values that change between runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CassetteRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default, with = "base64_bytes")]
+    pub body: Vec<u8>,
+}
+
+/// A recorded response. This is a [CachedResponse] with a base64-encoded body, since byte arrays
+/// would take up about four times as much space in JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    status_text: String,
+    url: String,
+    headers: Vec<(String, Vec<u8>)>,
+    #[serde(with = "base64_bytes")]
+    body: Vec<u8>,
+}
+
+impl From<CachedResponse> for RecordedResponse {
+    fn from(this: CachedResponse) -> Self {
+        RecordedResponse {
+            status: this.status,
+            status_text: this.status_text,
+            url: this.url,
+            headers: this.headers,
+            body: this.body,
+        }
+    }
+}
+
+impl From<RecordedResponse> for CachedResponse {
+    fn from(this: RecordedResponse) -> Self {
+        CachedResponse {
+            status: this.status,
+            status_text: this.status_text,
+            url: this.url,
+            headers: this.headers,
+            body: this.body,
+        }
+    }
+}
+
+/// Serializes bytes as a base64 string. Byte arrays are also accepted when deserializing, since
+/// older cassettes were stored like that.
+mod base64_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Bytes {
+            Base64(String),
+            Array(Vec<u8>),
+        }
+
+        match Bytes::deserialize(deserializer)? {
+            Bytes::Base64(data) => base64::decode(&data).map_err(serde::de::Error::custom),
+            Bytes::Array(data) => Ok(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CassetteEntry {
+    request: CassetteRequest,
+    response: RecordedResponse,
+}
+
+/// A list of recorded requests and their responses, for replaying fetches without network access.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    entries: Vec<CassetteEntry>,
+}
+
+impl Cassette {
+    /// Returns the recorded response for a request.
+    pub fn response(&self, request: &CassetteRequest) -> Option<CachedResponse> {
+        self.entries
+            .iter()
+            .find(|entry| entry.request == *request)
+            .map(|entry| entry.response.clone().into())
+    }
+
+    /// Records a response. Replaces any previous response for the same request.
+    ///
+    /// If the JSON-encoded cassette would then be longer than `max_len` bytes, the response is not
+    /// recorded and false is returned.
+    pub fn record(
+        &mut self,
+        request: CassetteRequest,
+        response: CachedResponse,
+        max_len: usize,
+    ) -> bool {
+        let prev_index = self
+            .entries
+            .iter()
+            .position(|entry| entry.request == request);
+        let prev = prev_index.map(|i| self.entries.remove(i));
+        self.entries.push(CassetteEntry {
+            request,
+            response: response.into(),
+        });
+
+        let len = serde_json::to_vec(self).map_or(usize::MAX, |data| data.len());
+        if len > max_len {
+            self.entries.pop();
+            if let (Some(i), Some(prev)) = (prev_index, prev) {
+                self.entries.insert(i, prev);
+            }
+            return false;
+        }
+        true
+    }
+}
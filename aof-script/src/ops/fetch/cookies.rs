@@ -0,0 +1,241 @@
+use deno_core::serde_json;
+use deno_core::url::Url;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Max number of cookies in a jar. Once exceeded, the oldest cookies will be evicted.
+const MAX_COOKIE_COUNT: usize = 256;
+
+/// Max len of a cookie name and value in bytes.
+const MAX_COOKIE_LEN: usize = 4096;
+
+/// A simple cookie jar (roughly following RFC 6265).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    /// Expiry time in seconds since the UNIX epoch. None for session cookies.
+    expires: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url, host: &str) -> bool {
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+
+    /// Parses a Set-Cookie header value. Returns None if the cookie should be ignored.
+    fn parse(header: &str, url: &Url, host: &str, now: u64) -> Option<Cookie> {
+        let mut parts = header.split(';');
+        let (name, value) = {
+            let pair = parts.next()?;
+            let eq = pair.find('=')?;
+            (pair[..eq].trim(), pair[eq + 1..].trim())
+        };
+        if name.is_empty() || name.len() + value.len() > MAX_COOKIE_LEN {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.to_string(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        let mut expires = None;
+
+        for attr in parts {
+            let (key, value) = match attr.find('=') {
+                Some(eq) => (attr[..eq].trim(), attr[eq + 1..].trim()),
+                None => (attr.trim(), ""),
+            };
+            match &*key.to_ascii_lowercase() {
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    // don't allow setting cookies for other sites or top-level domains
+                    if !domain_matches(host, &domain) || !domain.contains('.') {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" => {
+                    if value.starts_with('/') {
+                        cookie.path = value.to_string();
+                    }
+                }
+                "secure" => cookie.secure = true,
+                "max-age" => {
+                    if let Ok(secs) = value.parse::<i64>() {
+                        max_age = Some(secs);
+                    }
+                }
+                "expires" => {
+                    if let Ok(time) = httpdate::parse_http_date(value) {
+                        expires = Some(
+                            time.duration_since(UNIX_EPOCH)
+                                .map(|d| d.as_secs())
+                                .unwrap_or(0),
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        // Max-Age has precedence over Expires
+        cookie.expires = match max_age {
+            Some(secs) if secs <= 0 => Some(0),
+            Some(secs) => Some(now.saturating_add(secs as u64)),
+            None => expires,
+        };
+
+        Some(cookie)
+    }
+}
+
+impl CookieJar {
+    /// Returns the value of the Cookie header for a request to the given URL.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let now = now();
+
+        let mut cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(url, &host))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        // cookies with longer paths should be listed first
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+        let pairs: Vec<_> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Stores cookies from Set-Cookie headers of a response from the given URL.
+    ///
+    /// Returns true if the jar was modified.
+    pub fn store_cookies<'a, I>(&mut self, url: &Url, headers: I) -> bool
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        let now = now();
+
+        let mut modified = false;
+        for header in headers {
+            let cookie = match Cookie::parse(header, url, &host, now) {
+                Some(cookie) => cookie,
+                None => continue,
+            };
+
+            self.cookies.retain(|c| {
+                c.name != cookie.name || c.domain != cookie.domain || c.path != cookie.path
+            });
+            if !cookie.is_expired(now) {
+                self.cookies.push(cookie);
+            }
+            modified = true;
+        }
+
+        if modified {
+            self.cookies.retain(|c| !c.is_expired(now));
+            if self.cookies.len() > MAX_COOKIE_COUNT {
+                let excess = self.cookies.len() - MAX_COOKIE_COUNT;
+                self.cookies.drain(..excess);
+            }
+        }
+
+        modified
+    }
+
+    /// Encodes the jar as JSON. If it would be longer than `max_len` bytes, expired cookies and
+    /// then the oldest cookies are evicted until it fits.
+    pub fn to_json(&mut self, max_len: usize) -> String {
+        let now = now();
+        self.cookies.retain(|c| !c.is_expired(now));
+
+        loop {
+            let json = serde_json::to_string(self).expect("failed to encode cookie jar");
+            if json.len() <= max_len || self.cookies.is_empty() {
+                return json;
+            }
+
+            // cookies are stored in the order they were set, so the oldest ones come first
+            let excess = json.len() - max_len;
+            let mut evicted_len = 0;
+            let mut evicted_count = 0;
+            for cookie in &self.cookies {
+                if evicted_len >= excess {
+                    break;
+                }
+                evicted_len += serde_json::to_string(cookie).map_or(0, |c| c.len() + 1);
+                evicted_count += 1;
+            }
+            self.cookies.drain(..evicted_count);
+        }
+    }
+}
@@ -18,6 +18,19 @@
         'error',
         'manual',
     ];
+    const CACHE_MODES = [
+        'default',
+        'no-store',
+        'reload',
+        'no-cache',
+        'force-cache',
+        'only-if-cached',
+    ];
+    const CREDENTIALS = [
+        'omit',
+        'same-origin',
+        'include',
+    ];
 
     /* globalThis.TextEncoder = class TextEncoder {
         encode(string) {
@@ -402,14 +415,16 @@
         #method = 'GET';
         #headers = new Headers();
         #redirect = 'follow';
+        #credentials = 'same-origin';
+        #cache = 'default';
         #referrer = 'about:client';
         #body;
 
         get cache() {
-            return 'default';
+            return this.#cache;
         }
         get credentials() {
-            return 'same-origin';
+            return this.#credentials;
         }
         get destination() {
             return '';
@@ -453,6 +468,8 @@
                 this.#headers = new Headers(input.headers);
                 this.#body = input.body;
                 this.#redirect = input.redirect;
+                this.#credentials = input.credentials;
+                this.#cache = input.cache;
                 this.#referrer = input.referrer;
             } else if (typeof input === 'string') {
                 this.#url = input;
@@ -498,11 +515,14 @@
                     // not used here
                 }
                 if ('credentials' in init) {
-                    // not used here
-                    console.warn('Ignoring credentials option in fetch (not supported)');
+                    // there is no origin here, so same-origin and include both use the cookie jar
+                    if (!CREDENTIALS.includes(init.credentials)) throw new TypeError('Invalid credentials mode');
+                    this.#credentials = init.credentials;
                 }
                 if ('cache' in init) {
-                    // not used here
+                    // cached responses are always revalidated, so only no-store and reload make a difference
+                    if (!CACHE_MODES.includes(init.cache)) throw new TypeError('Invalid cache mode');
+                    this.#cache = init.cache;
                 }
                 if ('redirect' in init) {
                     if (!REDIRECTS.includes(init.redirect)) throw new TypeError('Invalid redirect mode');
@@ -681,6 +701,8 @@
             headers: [...request.headers],
             redirect: request.redirect,
             referrer: request.referrer,
+            credentials: request.credentials,
+            cache: request.cache,
         }, body);
 
         const headers = new Headers();
@@ -1,20 +1,43 @@
-use crate::OpStateExt;
+use crate::console::{ConsoleMessage, MessageType, MsgFrag};
+use crate::{CtxResource, OpStateExt, ScriptContext};
 use deno_core::error::AnyError;
 use deno_core::include_js_files;
 use deno_core::serde_json::{self, Value};
 use deno_core::url::{self, Url};
 use deno_core::{op_sync, Extension, OpState, ZeroCopyBuf};
+use proxy::{PinningProxy, ProxyFailure};
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
+mod cassette;
+mod cookies;
+mod proxy;
+
+pub use cassette::{Cassette, CassetteRequest, FetchMode};
+pub use cookies::CookieJar;
+
 const MIN_FETCH_TIME: Duration = Duration::from_millis(200);
 pub const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/90.0.4430.72 Safari/537.36";
-const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
 
-const MAX_RESPONSE_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+const MAX_CACHED_RESPONSE_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+/// Request headers that make a request uncacheable.
+///
+/// The HTTP cache is shared between all fetches of a domain, so requests with credentials must not
+/// be cached. Conditional requests made by the script itself are passed through as-is.
+const UNCACHEABLE_REQUEST_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "if-match",
+    "if-modified-since",
+    "if-none-match",
+    "if-range",
+    "if-unmodified-since",
+    "range",
+];
 
 pub fn init() -> Extension {
     Extension::builder()
@@ -40,18 +63,151 @@ enum FetchError {
     InvalidMethod,
     #[error("invalid redirect policy")]
     InvalidRedirectPolicy,
+    #[error("invalid cache mode")]
+    InvalidCacheMode,
     #[error("response is too large")]
     ResponseTooLarge,
+    #[error("cookie jar error: {0}")]
+    Cookies(String),
+    #[error("cassette error: {0}")]
+    Cassette(String),
+    #[error("no recorded response for {0}")]
+    NotRecorded(String),
+    #[error("failed to start proxy: {0}")]
+    Proxy(std::io::Error),
+    #[error("failed to connect: {0}")]
+    Connect(String),
     #[error("request error: {0}")]
     Req(#[from] reqwest::Error),
 }
 
+/// A response stored in the shared HTTP cache or in a cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub url: String,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| std::str::from_utf8(v).ok())
+    }
+}
+
+/// Returns the HTTP cache key of a request.
+///
+/// Since responses may vary by request headers (see the Vary header), all of them are part of the
+/// key. This is stricter than necessary, but scripts usually send the same headers every time.
+fn cache_key(url: &Url, headers: &[(String, String)], referrer: &str) -> String {
+    let mut headers: Vec<_> = headers
+        .iter()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.as_str()))
+        .collect();
+    if !referrer.is_empty() {
+        headers.push(("referer".into(), referrer));
+    }
+    headers.sort();
+
+    let mut key = url.to_string();
+    for (k, v) in headers {
+        key.push('\n');
+        key.push_str(&k);
+        key.push_str(": ");
+        key.push_str(v);
+    }
+    key
+}
+
+/// Returns true if a response with the given headers may be stored in the cache.
+fn is_response_storable(headers: &[(String, Vec<u8>)]) -> bool {
+    let mut has_validator = false;
+    for (k, v) in headers {
+        match &**k {
+            "etag" | "last-modified" => has_validator = true,
+            "cache-control" => {
+                let v = String::from_utf8_lossy(v).to_ascii_lowercase();
+                if v.contains("no-store") || v.contains("private") {
+                    return false;
+                }
+            }
+            "vary" if v.as_slice() == b"*" => return false,
+            _ => (),
+        }
+    }
+    has_validator
+}
+
+#[derive(Serialize)]
+struct Response {
+    status: u16,
+    status_text: String,
+    url: String,
+    redirected: bool,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl From<CachedResponse> for Response {
+    fn from(this: CachedResponse) -> Self {
+        Response {
+            status: this.status,
+            status_text: this.status_text,
+            url: this.url,
+            redirected: false,
+            headers: this.headers,
+            body: this.body,
+        }
+    }
+}
+
 enum RedirectPolicy {
     Follow,
     Error,
     Manual,
 }
 
+/// Returns the cookie jar, loading it from the script context if necessary.
+fn cookie_jar<'a>(
+    state: &'a mut OpState,
+    ctx: &CtxResource,
+) -> Result<&'a mut CookieJar, FetchError> {
+    if state.try_borrow::<CookieJar>().is_none() {
+        let jar = match ctx.cookies_get().map_err(FetchError::Cookies)? {
+            // if the stored jar is broken for some reason, just start over
+            Some(jar) => serde_json::from_str(&jar).unwrap_or_default(),
+            None => CookieJar::default(),
+        };
+        state.put(jar);
+    }
+    Ok(state.borrow_mut::<CookieJar>())
+}
+
+/// Notifies the script context that a fetch has started, and that it has ended once dropped.
+///
+/// This makes sure that the script timer is continued even if the fetch fails.
+struct FetchTimerPause<'a> {
+    ctx: &'a Arc<dyn ScriptContext>,
+}
+
+impl<'a> FetchTimerPause<'a> {
+    fn start(ctx: &'a Arc<dyn ScriptContext>) -> Self {
+        ctx.fetch_did_start();
+        FetchTimerPause { ctx }
+    }
+}
+
+impl<'a> Drop for FetchTimerPause<'a> {
+    fn drop(&mut self) {
+        self.ctx.fetch_did_end();
+    }
+}
+
 /// Implements the fetch operation. Note that this will block the thread!
 fn op_fetch(
     state: &mut OpState,
@@ -65,6 +221,10 @@ fn op_fetch(
         headers: Vec<(String, String)>,
         redirect: String,
         referrer: String,
+        #[serde(default)]
+        credentials: String,
+        #[serde(default)]
+        cache: String,
     }
     let args: Args = serde_json::from_value(args)?;
     let body = match data {
@@ -91,11 +251,40 @@ fn op_fetch(
         _ => Err(FetchError::InvalidRedirectPolicy)?,
     };
 
+    let (use_cache, store_in_cache) = match &*args.cache {
+        "" | "default" | "no-cache" | "force-cache" | "only-if-cached" => (true, true),
+        "reload" => (false, true),
+        "no-store" => (false, false),
+        _ => Err(FetchError::InvalidCacheMode)?,
+    };
+
     let guard = state.script_ctx_arc().map_err(|_| FetchError::NoResource)?;
 
-    guard.fetch_did_start();
+    let timer_pause = FetchTimerPause::start(&guard);
+    let limits = guard.limits();
 
     let url = Url::parse(&args.url).map_err(FetchError::Url)?;
+
+    let fetch_mode = guard.fetch_mode();
+    let cassette_request = match fetch_mode {
+        FetchMode::Live => None,
+        FetchMode::Record | FetchMode::Replay => Some(CassetteRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            body: body.as_ref().to_owned(),
+        }),
+    };
+
+    // replayed requests don't touch the network, the cache, or the cookie jar at all
+    if let (FetchMode::Replay, Some(request)) = (fetch_mode, &cassette_request) {
+        let response = guard.cassette_get(request);
+        drop(timer_pause);
+        let response = response
+            .map_err(FetchError::Cassette)?
+            .ok_or_else(|| FetchError::NotRecorded(format!("{} {}", request.method, url)))?;
+        return Ok(serde_json::to_value(Response::from(response))?);
+    }
+
     guard
         .request_permission(&method, &url)
         .map_err(FetchError::Permission)?;
@@ -104,13 +293,24 @@ fn op_fetch(
     let guard2 = Arc::clone(&**state.script_ctx_arc().map_err(|_| FetchError::NoResource)?);
     let redirect_count = Mutex::new(0);
 
-    let client = reqwest::Client::builder()
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    // connections are made through a proxy that checks the addresses actually being connected to
+    let proxy = match guard.network_policy() {
+        Some(policy) => Some(
+            rt.block_on(PinningProxy::start(policy))
+                .map_err(FetchError::Proxy)?,
+        ),
+        None => None,
+    };
+
+    let mut client = reqwest::Client::builder()
         .user_agent(USER_AGENT)
         .redirect(match redirect_policy {
             RedirectPolicy::Follow => reqwest::redirect::Policy::custom(move |attempt| {
                 let url = attempt.url();
                 // FIXME: does the method stay the same upon redirect?
-                match guard2.request_permission(&method2, &url) {
+                match guard2.request_redirect_permission(&method2, &url) {
                     Ok(()) => {
                         let mut redirect_count = redirect_count.lock().unwrap();
                         *redirect_count += 1;
@@ -128,11 +328,65 @@ fn op_fetch(
                 attempt.error("redirect policy does not allow redirects")
             }),
         })
-        .timeout(REQUEST_TIMEOUT)
-        .build()?;
+        .timeout(limits.request_timeout);
+    if let Some(proxy) = &proxy {
+        // proxied connections must not be reused, since each one is pinned to a single host
+        client = client
+            .proxy(reqwest::Proxy::all(&proxy.url())?)
+            .pool_max_idle_per_host(0);
+    }
+    let client = client.build()?;
+
+    // cookies are only sent to and stored from the initial URL, since redirects are handled by
+    // reqwest internally
+    let use_cookies = args.credentials != "omit";
+    let jar_cookies = if use_cookies {
+        cookie_jar(state, &guard)?.cookie_header(&url)
+    } else {
+        None
+    };
+    let mut headers = args.headers;
+    if let Some(jar_cookies) = jar_cookies {
+        match headers
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case("cookie"))
+        {
+            Some((_, v)) => {
+                v.push_str("; ");
+                v.push_str(&jar_cookies);
+            }
+            None => headers.push(("Cookie".into(), jar_cookies)),
+        }
+    }
+
+    let is_cacheable = method == reqwest::Method::GET
+        && body.is_empty()
+        && !headers.iter().any(|(k, _)| {
+            UNCACHEABLE_REQUEST_HEADERS
+                .iter()
+                .any(|h| k.eq_ignore_ascii_case(h))
+        });
+
+    let cache_key = cache_key(&url, &headers, &args.referrer);
+
+    // a cached response is always revalidated, but will be used if the server responds with 304
+    let cached = if is_cacheable && use_cache {
+        // cache errors are logged by the server and shouldn't make the fetch fail
+        guard.http_cache_get(&cache_key).unwrap_or(None)
+    } else {
+        None
+    };
+    if let Some(cached) = &cached {
+        if let Some(etag) = cached.header("etag") {
+            headers.push(("If-None-Match".into(), etag.into()));
+        }
+        if let Some(last_modified) = cached.header("last-modified") {
+            headers.push(("If-Modified-Since".into(), last_modified.into()));
+        }
+    }
 
-    let mut req = client.request(method, url);
-    for (k, v) in args.headers {
+    let mut req = client.request(method, url.clone());
+    for (k, v) in headers {
         req = req.header(reqwest::header::HeaderName::from_bytes(k.as_bytes())?, v);
     }
 
@@ -144,23 +398,13 @@ fn op_fetch(
         req = req.body(body.as_ref().to_owned());
     }
 
-    #[derive(Serialize)]
-    struct Response {
-        status: u16,
-        status_text: String,
-        url: String,
-        redirected: bool,
-        headers: Vec<(String, Vec<u8>)>,
-        body: Vec<u8>,
-    }
-
-    async fn do_req(r: reqwest::RequestBuilder) -> Result<Response, FetchError> {
+    async fn do_req(r: reqwest::RequestBuilder, max_size: usize) -> Result<Response, FetchError> {
         use futures::StreamExt;
 
         let response = r.send().await?;
         if response
             .content_length()
-            .map_or(false, |l| l > MAX_RESPONSE_SIZE as u64)
+            .map_or(false, |l| l > max_size as u64)
         {
             Err(FetchError::ResponseTooLarge)?;
         }
@@ -185,7 +429,7 @@ fn op_fetch(
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
             res_len += chunk.len();
-            if res_len > MAX_RESPONSE_SIZE {
+            if res_len > max_size {
                 Err(FetchError::ResponseTooLarge)?;
             }
             chunks.push(chunk);
@@ -203,16 +447,92 @@ fn op_fetch(
 
     let end_time = Instant::now() + MIN_FETCH_TIME;
 
-    let response = {
-        let mut rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(do_req(req))?
+    let response = match rt.block_on(do_req(req, limits.max_response_size)) {
+        // the proxy closes the connection if it can't connect, so find out why
+        Err(FetchError::Req(err)) => {
+            let failure = match (&proxy, err.url()) {
+                (Some(proxy), Some(url)) => proxy.failure(url),
+                _ => None,
+            };
+            match failure {
+                Some(ProxyFailure::Denied(reason)) => Err(FetchError::Permission(reason))?,
+                Some(ProxyFailure::Connect(err)) => Err(FetchError::Connect(err))?,
+                None => Err(FetchError::Req(err))?,
+            }
+        }
+        response => response?,
     };
 
     if let Some(time_left) = end_time.checked_duration_since(Instant::now()) {
         sleep(time_left);
     }
 
-    guard.fetch_did_end();
+    drop(timer_pause);
+
+    if use_cookies {
+        let set_cookies: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|(k, _)| k == "set-cookie")
+            .map(|(_, v)| String::from_utf8_lossy(v).to_string())
+            .collect();
+        let jar = cookie_jar(state, &guard)?;
+        if jar.store_cookies(&url, set_cookies.iter().map(|s| &**s)) {
+            // other fetches of the domain may have changed the persisted jar in the meantime, so
+            // only the new cookies are sent to be merged into it
+            guard
+                .cookies_store(&url, set_cookies)
+                .map_err(FetchError::Cookies)?;
+        }
+    }
+
+    let response = match cached {
+        Some(cached) if response.status == 304 => Response::from(cached),
+        _ => {
+            if is_cacheable
+                && store_in_cache
+                && response.status == 200
+                && response.body.len() <= MAX_CACHED_RESPONSE_SIZE
+                && is_response_storable(&response.headers)
+            {
+                let cached = CachedResponse {
+                    status: response.status,
+                    status_text: response.status_text.clone(),
+                    url: response.url.clone(),
+                    // cookies are stored in the cookie jar of the domain that made the request
+                    headers: response
+                        .headers
+                        .iter()
+                        .filter(|(k, _)| k != "set-cookie")
+                        .cloned()
+                        .collect(),
+                    body: response.body.clone(),
+                };
+                let _ = guard.http_cache_set(&cache_key, cached);
+            }
+            response
+        }
+    };
+
+    if let Some(request) = cassette_request {
+        let recorded = CachedResponse {
+            status: response.status,
+            status_text: response.status_text.clone(),
+            url: response.url.clone(),
+            headers: response.headers.clone(),
+            body: response.body.clone(),
+        };
+        // the request itself succeeded, so this shouldn't make the fetch fail
+        if let Err(err) = guard.cassette_record(request, recorded) {
+            guard.on_console_message(ConsoleMessage {
+                msg_type: MessageType::Warn,
+                message: vec![MsgFrag::Log(format!(
+                    "response to {} was not recorded: {}",
+                    url, err
+                ))],
+            });
+        }
+    }
 
     Ok(serde_json::to_value(response)?)
 }
@@ -0,0 +1,232 @@
+//! A local HTTP proxy that pins connections to checked addresses.
+//!
+//! reqwest resolves host names by itself, so checking the addresses of a host before making a
+//! request does not guarantee that the request is sent to one of them (e.g. with DNS rebinding).
+//! Instead, requests are sent through this proxy, which resolves each host once, checks the
+//! addresses against the network policy, and then connects to one of the checked addresses.
+
+use crate::NetworkPolicy;
+use deno_core::url::{Host, Position, Url};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+
+/// Max size of a request head sent to the proxy.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+enum ProxyError {
+    /// The connection is not allowed by the network policy.
+    Denied(String),
+    /// Connecting to the requested host failed.
+    Connect(io::Error),
+    Io(io::Error),
+}
+
+/// Why the proxy could not connect to a requested host.
+#[derive(Debug, Clone)]
+pub(super) enum ProxyFailure {
+    /// The connection is not allowed by the network policy.
+    Denied(String),
+    /// Connecting to the host failed.
+    Connect(String),
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(err: io::Error) -> Self {
+        ProxyError::Io(err)
+    }
+}
+
+/// Failures of connections to requested hosts, along with the host and port of each.
+type Failures = Arc<Mutex<Vec<(String, ProxyFailure)>>>;
+
+pub(super) struct PinningProxy {
+    addr: SocketAddr,
+    failures: Failures,
+}
+
+impl PinningProxy {
+    /// Starts the proxy on the current tokio runtime. It stops when the runtime is dropped.
+    pub(super) async fn start(policy: NetworkPolicy) -> io::Result<Self> {
+        let mut listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let failures: Failures = Arc::new(Mutex::new(Vec::new()));
+
+        let policy = Arc::new(policy);
+        let failures2 = Arc::clone(&failures);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let policy = Arc::clone(&policy);
+                let failures = Arc::clone(&failures2);
+                tokio::spawn(async move {
+                    let _ = handle_conn(stream, &policy, &failures).await;
+                });
+            }
+        });
+
+        Ok(PinningProxy { addr, failures })
+    }
+
+    /// Returns the proxy URL to be passed to reqwest.
+    pub(super) fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Returns why the last failed connection to the host of a URL failed, if one did.
+    pub(super) fn failure(&self, url: &Url) -> Option<ProxyFailure> {
+        let target = host_port(url);
+        self.failures
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(t, _)| *t == target)
+            .map(|(_, failure)| failure.clone())
+    }
+}
+
+/// Returns the host and port of a URL, which identify the connection a request is sent over.
+fn host_port(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or(""),
+        url.port_or_known_default().unwrap_or(80)
+    )
+}
+
+/// Reads the request head, and returns it along with its length (the buffer may already contain
+/// some of the body).
+async fn read_head(stream: &mut TcpStream) -> io::Result<(Vec<u8>, usize)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok((buf, pos + 4));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head is too large",
+            ));
+        }
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..len]);
+    }
+}
+
+/// Resolves the URL host, checks all of its addresses, and connects to one of them.
+async fn connect(url: &Url, policy: &NetworkPolicy) -> Result<TcpStream, ProxyError> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Domain(domain)) => {
+            policy.check_host(domain).map_err(ProxyError::Denied)?;
+            let addrs: Vec<_> = lookup_host((domain, port))
+                .await
+                .map_err(ProxyError::Connect)?
+                .collect();
+            for addr in &addrs {
+                policy
+                    .check_addr(Some(domain), addr.ip())
+                    .map_err(ProxyError::Denied)?;
+            }
+            addrs
+        }
+        Some(Host::Ipv4(addr)) => {
+            policy
+                .check_addr(None, addr.into())
+                .map_err(ProxyError::Denied)?;
+            vec![SocketAddr::new(addr.into(), port)]
+        }
+        Some(Host::Ipv6(addr)) => {
+            policy
+                .check_addr(None, addr.into())
+                .map_err(ProxyError::Denied)?;
+            vec![SocketAddr::new(addr.into(), port)]
+        }
+        None => return Err(ProxyError::Denied(String::from("URL has no host"))),
+    };
+
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "host has no addresses");
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(ProxyError::Connect(last_err))
+}
+
+/// Handles a single proxy connection, which is either a CONNECT tunnel (for HTTPS) or a plain
+/// HTTP request in absolute form.
+///
+/// If connecting to the requested host fails, the failure is added to `failures`.
+async fn handle_conn(
+    mut client: TcpStream,
+    policy: &NetworkPolicy,
+    failures: &Failures,
+) -> Result<(), ProxyError> {
+    let (head, head_len) = read_head(&mut client).await?;
+    let line_len = head.windows(2).position(|w| w == b"\r\n").unwrap_or(0);
+    let line = String::from_utf8_lossy(&head[..line_len]).to_string();
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) => (method, target, version),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request line").into()),
+    };
+
+    let is_connect = method == "CONNECT";
+    let url = if is_connect {
+        Url::parse(&format!("https://{}", target))
+    } else {
+        Url::parse(target)
+    };
+    let url = match url {
+        Ok(url) if is_connect || url.scheme() == "http" => url,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request target").into()),
+    };
+
+    // on failure, the connection is closed without a response, so that the failure can't be
+    // mistaken for a response from the host
+    let mut upstream = match connect(&url, policy).await {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            let failure = match &err {
+                ProxyError::Denied(reason) => ProxyFailure::Denied(reason.clone()),
+                ProxyError::Connect(err) => ProxyFailure::Connect(err.to_string()),
+                ProxyError::Io(err) => ProxyFailure::Connect(err.to_string()),
+            };
+            failures.lock().unwrap().push((host_port(&url), failure));
+            return Err(err);
+        }
+    };
+
+    if is_connect {
+        client
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+        upstream.write_all(&head[head_len..]).await?;
+    } else {
+        // servers expect requests in origin form
+        let line = format!("{} {} {}", method, &url[Position::BeforePath..], version);
+        upstream.write_all(line.as_bytes()).await?;
+        upstream.write_all(&head[line_len..]).await?;
+    }
+
+    let (mut client_read, mut client_write) = client.split();
+    let (mut upstream_read, mut upstream_write) = upstream.split();
+    let to_upstream = async {
+        tokio::io::copy(&mut client_read, &mut upstream_write).await?;
+        upstream_write.shutdown().await
+    };
+    let to_client = async {
+        tokio::io::copy(&mut upstream_read, &mut client_write).await?;
+        client_write.shutdown().await
+    };
+    tokio::try_join!(to_upstream, to_client)?;
+    Ok(())
+}
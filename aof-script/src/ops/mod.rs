@@ -7,8 +7,10 @@ use std::sync::Arc;
 mod aof_req;
 pub mod console;
 mod fetch;
+mod secrets;
+mod storage;
 
-pub use fetch::USER_AGENT;
+pub use fetch::{CachedResponse, Cassette, CassetteRequest, CookieJar, FetchMode, USER_AGENT};
 
 pub fn init() -> Vec<Extension> {
     let init = Extension::builder()
@@ -26,6 +28,8 @@ pub fn init() -> Vec<Extension> {
         deno_dom::init(),
         aof_req::init(),
         fetch::init(),
+        storage::init(),
+        secrets::init(),
         init,
     ]
 }
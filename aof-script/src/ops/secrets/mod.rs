@@ -0,0 +1,42 @@
+use crate::OpStateExt;
+use deno_core::error::AnyError;
+use deno_core::include_js_files;
+use deno_core::serde_json::{self, Value};
+use deno_core::{op_sync, Extension, OpState, ZeroCopyBuf};
+use serde::Deserialize;
+use thiserror::Error;
+
+pub fn init() -> Extension {
+    Extension::builder()
+        .ops(vec![("aof_secret_get", op_sync(op_secret_get))])
+        .js(include_js_files! {
+            prefix "deno:aof/secrets",
+            "secrets.js",
+        })
+        .build()
+}
+
+#[derive(Debug, Error)]
+enum SecretError {
+    #[error("failed to acquire secrets resource")]
+    NoResource,
+    #[error("secrets error: {0}")]
+    Secret(String),
+}
+
+fn op_secret_get(
+    state: &mut OpState,
+    args: Value,
+    _data: Option<ZeroCopyBuf>,
+) -> Result<Value, AnyError> {
+    #[derive(Deserialize)]
+    struct Args {
+        name: String,
+    }
+    let args: Args = serde_json::from_value(args)?;
+    let ctx = state
+        .script_ctx_arc()
+        .map_err(|_| SecretError::NoResource)?;
+    let value = ctx.secret_get(&args.name).map_err(SecretError::Secret)?;
+    Ok(value.map_or(Value::Null, Value::String))
+}
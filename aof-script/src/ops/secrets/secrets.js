@@ -0,0 +1,10 @@
+{
+    // Secrets (e.g. login credentials) stored by the user the script is running for.
+    // Only available in fetches requested by that user. Values are redacted from console output.
+    globalThis.aofSecrets = Object.freeze({
+        get(name) {
+            if (typeof name !== 'string') throw new TypeError('Secret name must be a string');
+            return Deno.core.opSync('aof_secret_get', { name });
+        },
+    });
+}
@@ -0,0 +1,80 @@
+use crate::OpStateExt;
+use deno_core::error::AnyError;
+use deno_core::include_js_files;
+use deno_core::serde_json::{self, Value};
+use deno_core::{op_sync, Extension, OpState, ZeroCopyBuf};
+use serde::Deserialize;
+use thiserror::Error;
+
+pub fn init() -> Extension {
+    Extension::builder()
+        .ops(vec![
+            ("aof_storage_get", op_sync(op_storage_get)),
+            ("aof_storage_set", op_sync(op_storage_set)),
+            ("aof_storage_delete", op_sync(op_storage_delete)),
+        ])
+        .js(include_js_files! {
+            prefix "deno:aof/storage",
+            "storage.js",
+        })
+        .build()
+}
+
+#[derive(Debug, Error)]
+enum StorageError {
+    #[error("failed to acquire storage resource")]
+    NoResource,
+    #[error("storage error: {0}")]
+    Storage(String),
+}
+
+#[derive(Deserialize)]
+struct KeyArgs {
+    key: String,
+}
+
+fn op_storage_get(
+    state: &mut OpState,
+    args: Value,
+    _data: Option<ZeroCopyBuf>,
+) -> Result<Value, AnyError> {
+    let args: KeyArgs = serde_json::from_value(args)?;
+    let ctx = state
+        .script_ctx_arc()
+        .map_err(|_| StorageError::NoResource)?;
+    let value = ctx.storage_get(&args.key).map_err(StorageError::Storage)?;
+    Ok(value.unwrap_or(Value::Null))
+}
+
+fn op_storage_set(
+    state: &mut OpState,
+    args: Value,
+    _data: Option<ZeroCopyBuf>,
+) -> Result<Value, AnyError> {
+    #[derive(Deserialize)]
+    struct Args {
+        key: String,
+        value: Value,
+    }
+    let args: Args = serde_json::from_value(args)?;
+    let ctx = state
+        .script_ctx_arc()
+        .map_err(|_| StorageError::NoResource)?;
+    ctx.storage_set(&args.key, args.value)
+        .map_err(StorageError::Storage)?;
+    Ok(Value::Null)
+}
+
+fn op_storage_delete(
+    state: &mut OpState,
+    args: Value,
+    _data: Option<ZeroCopyBuf>,
+) -> Result<Value, AnyError> {
+    let args: KeyArgs = serde_json::from_value(args)?;
+    let ctx = state
+        .script_ctx_arc()
+        .map_err(|_| StorageError::NoResource)?;
+    ctx.storage_delete(&args.key)
+        .map_err(StorageError::Storage)?;
+    Ok(Value::Null)
+}
@@ -0,0 +1,23 @@
+{
+    function checkKey(key) {
+        if (typeof key !== 'string') throw new TypeError('Storage key must be a string');
+        return key;
+    }
+
+    // Persistent key-value storage that is shared between all runs of the same domain.
+    // Values must be JSON-serializable.
+    globalThis.aofStorage = Object.freeze({
+        get(key) {
+            return Deno.core.opSync('aof_storage_get', { key: checkKey(key) });
+        },
+        set(key, value) {
+            if (value === undefined) throw new TypeError('Cannot store undefined; use delete instead');
+            // round-trip through JSON so that non-serializable values fail here and not in the op
+            value = JSON.parse(JSON.stringify(value));
+            Deno.core.opSync('aof_storage_set', { key: checkKey(key), value });
+        },
+        delete(key) {
+            Deno.core.opSync('aof_storage_delete', { key: checkKey(key) });
+        },
+    });
+}
@@ -0,0 +1,510 @@
+use deno_core::url::{self, Url};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// Returns true if the address is globally reachable.
+///
+/// Reserved and special-purpose ranges are not considered global, even if some of them might be.
+fn is_global(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => is_global_v4(addr),
+        IpAddr::V6(addr) => is_global_v6(addr),
+    }
+}
+
+fn is_global_v4(addr: Ipv4Addr) -> bool {
+    let [a, b, c, _] = addr.octets();
+    !(a == 0 // "this network"
+        || a == 10 // private
+        || a == 127 // loopback
+        || (a == 100 && b & 0xc0 == 64) // shared address space (CGNAT)
+        || (a == 169 && b == 254) // link-local
+        || (a == 172 && b & 0xf0 == 16) // private
+        || (a == 192 && b == 0 && c == 0) // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 2) // documentation
+        || (a == 192 && b == 88 && c == 99) // 6to4 relay anycast
+        || (a == 192 && b == 168) // private
+        || (a == 198 && b & 0xfe == 18) // benchmarking
+        || (a == 198 && b == 51 && c == 100) // documentation
+        || (a == 203 && b == 0 && c == 113) // documentation
+        || a >= 224) // multicast, reserved, and broadcast
+}
+
+fn is_global_v6(addr: Ipv6Addr) -> bool {
+    if let Some(addr) = embedded_ipv4(addr) {
+        return is_global_v4(addr);
+    }
+    let s = addr.segments();
+    !(s[0..6] == [0; 6] // unspecified, loopback, and IPv4-compatible
+        || (s[0] == 0x100 && s[1..4] == [0; 3]) // discard-only
+        || (s[0] == 0x2001 && s[1] < 0x200) // IETF protocol assignments
+        || (s[0] == 0x2001 && s[1] == 0xdb8) // documentation
+        || s[0] & 0xfe00 == 0xfc00 // unique local
+        || s[0] & 0xffc0 == 0xfe80 // link-local
+        || s[0] & 0xffc0 == 0xfec0 // site-local
+        || s[0] & 0xff00 == 0xff00) // multicast
+}
+
+/// Returns the IPv4 address that an IPv6 address will end up at, if it's an IPv4-mapped, NAT64,
+/// or 6to4 address.
+fn embedded_ipv4(addr: Ipv6Addr) -> Option<Ipv4Addr> {
+    let from_segments = |hi: u16, lo: u16| Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo));
+    match addr.segments() {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] => Some(from_segments(hi, lo)),
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(from_segments(hi, lo)),
+        [0x2002, hi, lo, ..] => Some(from_segments(hi, lo)),
+        _ => None,
+    }
+}
+
+/// Returns the address that will actually be accessed, so that IPv4 ranges also apply to IPv6
+/// addresses that end up at an IPv4 address.
+fn canonical_ip(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => embedded_ipv4(v6).map_or(addr, IpAddr::V4),
+        addr => addr,
+    }
+}
+
+/// A range of IP addresses in CIDR notation, such as `10.0.0.0/8` or `fd00::/8`.
+///
+/// A single address without a prefix length is also accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+fn addr_bits(addr: IpAddr) -> (u128, u8) {
+    match addr {
+        IpAddr::V4(addr) => (u32::from(addr).into(), 32),
+        IpAddr::V6(addr) => (u128::from(addr), 128),
+    }
+}
+
+impl IpCidr {
+    /// Returns true if the address is in this range.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (net, net_len) = addr_bits(self.addr);
+        let (addr, addr_len) = addr_bits(addr);
+        net_len == addr_len
+            && (self.prefix_len == 0 || (net ^ addr) >> (net_len - self.prefix_len) == 0)
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(2, '/');
+        let addr_part = parts.next().unwrap_or("");
+        let addr = IpAddr::from_str(addr_part)
+            .map_err(|_| format!("invalid IP address {:?}", addr_part))?;
+        let max_len = addr_bits(addr).1;
+        let prefix_len = match parts.next() {
+            Some(len) => match len.parse() {
+                Ok(len) if len <= max_len => len,
+                _ => return Err(format!("invalid prefix length in {:?}", s)),
+            },
+            None => max_len,
+        };
+        Ok(IpCidr { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl From<IpCidr> for String {
+    fn from(this: IpCidr) -> String {
+        this.to_string()
+    }
+}
+
+/// Restrictions on the hosts and addresses that outgoing requests may access.
+///
+/// By default, only global IP addresses may be accessed.
+/// Host names use the same patterns as allowed hosts (see [is_valid_host_pattern]).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkPolicy {
+    /// Address ranges that may be accessed even if they're not global.
+    #[serde(default)]
+    pub allow: Vec<IpCidr>,
+    /// Address ranges that may never be accessed.
+    #[serde(default)]
+    pub deny: Vec<IpCidr>,
+    /// Hosts whose addresses may be accessed even if they're not global.
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+    /// Hosts that may never be accessed.
+    #[serde(default)]
+    pub deny_hosts: Vec<String>,
+}
+
+impl NetworkPolicy {
+    /// Checks whether a host name may be accessed at all.
+    pub fn check_host(&self, host: &str) -> Result<(), String> {
+        let host = normalize_host(host);
+        if self.deny_hosts.iter().any(|p| host_matches(&host, p)) {
+            return Err(format!("accessing {:?} is not allowed", host));
+        }
+        Ok(())
+    }
+
+    /// Checks whether an address may be accessed. `host` is the host name that resolved to the
+    /// address, if any.
+    ///
+    /// Denied ranges take precedence over everything else.
+    pub fn check_addr(&self, host: Option<&str>, addr: IpAddr) -> Result<(), String> {
+        let addr = canonical_ip(addr);
+        if self.deny.iter().any(|net| net.contains(addr)) {
+            return Err(format!("accessing {} is not allowed", addr));
+        }
+        let host_allowed = host.map_or(false, |host| {
+            let host = normalize_host(host);
+            self.allow_hosts.iter().any(|p| host_matches(&host, p))
+        });
+        if host_allowed || self.allow.iter().any(|net| net.contains(addr)) || is_global(addr) {
+            Ok(())
+        } else {
+            Err(format!("accessing {} is not allowed", addr))
+        }
+    }
+}
+
+/// Checks whether a script may access a URL, and returns the address the request should be sent
+/// to.
+///
+/// Only HTTP(S) URLs that resolve to addresses allowed by the network policy are allowed.
+/// Since resolving the host again may yield a different address (e.g. with DNS rebinding), the
+/// connection should be made to the returned address.
+/// `on_direct_ip_access` is called if the URL host is an IP address.
+pub fn request_fetch_permission<F>(
+    url: &Url,
+    policy: &NetworkPolicy,
+    on_direct_ip_access: F,
+) -> Result<SocketAddr, String>
+where
+    F: FnOnce(IpAddr),
+{
+    match url.scheme() {
+        "http" | "https" => (),
+        scheme => return Err(format!("URL scheme {:?} not allowed", scheme)),
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            policy.check_host(domain)?;
+            let addrs: Vec<_> = match (domain, port).to_socket_addrs() {
+                Ok(addrs) => addrs.collect(),
+                Err(err) => return Err(format!("could not resolve host {:?}: {}", domain, err)),
+            };
+            for addr in &addrs {
+                policy
+                    .check_addr(Some(domain), addr.ip())
+                    .map_err(|err| format!("error resolving host {:?}: {}", domain, err))?;
+            }
+            match addrs.first() {
+                Some(addr) => Ok(*addr),
+                None => Err(format!("could not resolve host {:?}", domain)),
+            }
+        }
+        Some(url::Host::Ipv4(addr)) => {
+            policy.check_addr(None, addr.into())?;
+            on_direct_ip_access(addr.into());
+            Ok(SocketAddr::new(addr.into(), port))
+        }
+        Some(url::Host::Ipv6(addr)) => {
+            policy.check_addr(None, addr.into())?;
+            on_direct_ip_access(addr.into());
+            Ok(SocketAddr::new(addr.into(), port))
+        }
+        None => Err(String::from("URL has no host")),
+    }
+}
+
+/// Max number of patterns in a list of allowed hosts.
+pub const ALLOWED_HOSTS_MAX: usize = 64;
+
+/// Returns true if the string is a valid allowed host pattern: either a host name, or `*.`
+/// followed by a host name to allow all of its subdomains.
+pub fn is_valid_host_pattern(pattern: &str) -> bool {
+    let host = pattern.strip_prefix("*.").unwrap_or(pattern);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns true if a normalized host name matches a host pattern (see [check_allowed_hosts]).
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(parent) => host
+            .strip_suffix(parent)
+            .map_or(false, |sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+/// Checks whether the host of a URL matches one of the allowed host patterns.
+///
+/// Patterns match host names exactly (ignoring case), except that `*.example.com` matches all
+/// subdomains of `example.com` (but not `example.com` itself).
+pub fn check_allowed_hosts(url: &Url, allowed_hosts: &[String]) -> Result<(), String> {
+    let host = match url.host_str() {
+        Some(host) => normalize_host(host),
+        None => return Err(String::from("URL has no host")),
+    };
+
+    if allowed_hosts
+        .iter()
+        .any(|pattern| host_matches(&host, pattern))
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "accessing {:?} is not allowed: host is not in the domain's allowed hosts",
+            host
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let cases = [
+            ("0.0.0.0/0", "255.255.255.255", true),
+            ("0.0.0.0/0", "::", false),
+            ("::/0", "ffff::1", true),
+            ("::/0", "0.0.0.0", false),
+            ("10.0.0.0/8", "10.255.255.255", true),
+            ("10.0.0.0/8", "11.0.0.0", false),
+            ("10.1.2.3/32", "10.1.2.3", true),
+            ("10.1.2.3/32", "10.1.2.4", false),
+            ("10.1.2.3", "10.1.2.3", true),
+            ("10.1.2.3", "10.1.2.2", false),
+            ("fd00::/8", "fdff::1", true),
+            ("fd00::/8", "fe00::1", false),
+            ("fd00::1/128", "fd00::1", true),
+            ("fd00::1/128", "fd00::2", false),
+            // IPv4-mapped addresses are canonicalized by the network policy, not here
+            ("10.0.0.0/8", "::ffff:10.0.0.1", false),
+            ("::ffff:10.0.0.0/104", "::ffff:10.0.0.1", true),
+        ];
+        for (cidr, addr, expected) in &cases {
+            let cidr: IpCidr = cidr.parse().unwrap();
+            assert_eq!(cidr.contains(ip(addr)), *expected, "{} in {}", addr, cidr);
+        }
+    }
+
+    #[test]
+    fn cidr_parse() {
+        let cases = [
+            ("10.0.0.0/8", Some("10.0.0.0/8")),
+            ("10.0.0.1", Some("10.0.0.1/32")),
+            ("0.0.0.0/0", Some("0.0.0.0/0")),
+            ("10.0.0.0/33", None),
+            ("::1", Some("::1/128")),
+            ("::/128", Some("::/128")),
+            ("::/129", None),
+            ("10.0.0.0/", None),
+            ("10.0.0.0/-1", None),
+            ("example.com/8", None),
+        ];
+        for (s, expected) in &cases {
+            let parsed = s.parse::<IpCidr>().ok().map(|cidr| cidr.to_string());
+            assert_eq!(parsed.as_deref(), *expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn global_addrs() {
+        let cases = [
+            ("8.8.8.8", true),
+            ("0.1.2.3", false),
+            ("10.0.0.1", false),
+            ("100.64.0.1", false),
+            ("100.127.255.255", false),
+            ("100.128.0.1", true),
+            ("127.0.0.1", false),
+            ("169.254.169.254", false),
+            ("172.16.0.1", false),
+            ("172.31.255.255", false),
+            ("172.32.0.1", true),
+            ("192.0.2.1", false),
+            ("192.88.99.1", false),
+            ("192.168.1.1", false),
+            ("198.18.0.1", false),
+            ("198.20.0.1", true),
+            ("224.0.0.1", false),
+            ("255.255.255.255", false),
+            ("2606:4700::1111", true),
+            ("::", false),
+            ("::1", false),
+            ("::10.0.0.1", false),
+            ("100::1", false),
+            ("2001:db8::1", false),
+            ("fc00::1", false),
+            ("fd12:3456::1", false),
+            ("fe80::1", false),
+            ("fec0::1", false),
+            ("ff02::1", false),
+            // IPv4-mapped
+            ("::ffff:8.8.8.8", true),
+            ("::ffff:127.0.0.1", false),
+            // NAT64
+            ("64:ff9b::8.8.8.8", true),
+            ("64:ff9b::a00:1", false),
+            ("64:ff9b::169.254.169.254", false),
+            // 6to4
+            ("2002:808:808::1", true),
+            ("2002:a00:1::1", false),
+            ("2002:7f00:1::", false),
+        ];
+        for (addr, expected) in &cases {
+            assert_eq!(is_global(ip(addr)), *expected, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn embedded_ipv4_addrs() {
+        let cases = [
+            ("::ffff:10.0.0.1", Some("10.0.0.1")),
+            ("64:ff9b::10.0.0.1", Some("10.0.0.1")),
+            ("2002:a00:1::", Some("10.0.0.1")),
+            ("2002:a00:1:ffff:ffff:ffff:ffff:ffff", Some("10.0.0.1")),
+            ("::10.0.0.1", None),
+            ("64:ff9b:1::10.0.0.1", None),
+            ("2003:a00:1::", None),
+            ("::1", None),
+        ];
+        for (addr, expected) in &cases {
+            let addr: Ipv6Addr = addr.parse().unwrap();
+            let expected = expected.map(|e| e.parse::<Ipv4Addr>().unwrap());
+            assert_eq!(embedded_ipv4(addr), expected, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn policy_checks_embedded_ipv4() {
+        let policy = NetworkPolicy {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.1.0.0/16".parse().unwrap(), "8.8.8.8".parse().unwrap()],
+            ..Default::default()
+        };
+        let cases = [
+            ("10.0.0.1", true),
+            ("::ffff:10.0.0.1", true),
+            ("64:ff9b::10.0.0.1", true),
+            ("10.1.0.1", false),
+            ("::ffff:10.1.0.1", false),
+            ("2002:a01:1::", false),
+            ("8.8.8.8", false),
+            ("64:ff9b::8.8.8.8", false),
+            ("8.8.4.4", true),
+            ("::ffff:192.168.0.1", false),
+        ];
+        for (addr, expected) in &cases {
+            let res = policy.check_addr(None, ip(addr));
+            assert_eq!(res.is_ok(), *expected, "{}: {:?}", addr, res);
+        }
+    }
+
+    #[test]
+    fn host_patterns() {
+        let cases = [
+            ("example.com", "example.com", true),
+            ("example.com", "Example.COM", true),
+            ("www.example.com", "example.com", false),
+            ("example.com", "*.example.com", false),
+            ("www.example.com", "*.example.com", true),
+            ("a.b.example.com", "*.example.com", true),
+            ("evil-example.com", "*.example.com", false),
+            ("evil-example.com", "example.com", false),
+            ("example.com.evil.com", "*.example.com", false),
+            (".example.com", "*.example.com", false),
+        ];
+        for (host, pattern, expected) in &cases {
+            assert_eq!(
+                host_matches(host, pattern),
+                *expected,
+                "{} against {}",
+                host,
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        let allowed = vec![
+            String::from("example.com"),
+            String::from("*.cdn.example.com"),
+        ];
+        let cases = [
+            ("https://example.com/a", true),
+            ("https://EXAMPLE.com./a", true),
+            ("http://example.com:8080/", true),
+            ("https://img.cdn.example.com/a.png", true),
+            ("https://cdn.example.com/", false),
+            ("https://www.example.com/", false),
+            ("https://evil-example.com/", false),
+            ("https://example.com.evil.com/", false),
+            ("https://93.184.216.34/", false),
+        ];
+        for (url, expected) in &cases {
+            let res = check_allowed_hosts(&Url::parse(url).unwrap(), &allowed);
+            assert_eq!(res.is_ok(), *expected, "{}: {:?}", url, res);
+        }
+    }
+
+    #[test]
+    fn host_pattern_validity() {
+        let cases = [
+            ("example.com", true),
+            ("*.example.com", true),
+            ("localhost", true),
+            ("", false),
+            ("*.", false),
+            ("*", false),
+            ("a.*.example.com", false),
+            ("**.example.com", false),
+            ("example..com", false),
+            ("exa mple.com", false),
+        ];
+        for (pattern, expected) in &cases {
+            assert_eq!(is_valid_host_pattern(pattern), *expected, "{:?}", pattern);
+        }
+    }
+}
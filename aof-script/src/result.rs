@@ -0,0 +1,747 @@
+//! Data returned by domain scripts, and validation thereof.
+
+use deno_core::serde_json::{self, Map, Value};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Source data output from a script.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceFetchData {
+    #[serde(default)]
+    pub last_updated: Option<String>,
+    pub tags: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub items: Vec<SourceMetaItem>,
+    #[serde(default)]
+    pub item_data: BTreeMap<String, SourceItemFetchData>,
+    /// When the source is expected to update next (RFC 3339).
+    #[serde(default)]
+    pub next_update: Option<String>,
+    /// Times of the week at which the source usually updates.
+    #[serde(default)]
+    pub update_schedule: Vec<ScheduledUpdate>,
+    /// If true, the source will not update anymore.
+    #[serde(default)]
+    pub completed: bool,
+}
+
+/// A time of the week at which a source usually updates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledUpdate {
+    /// Day of the week (`mon` through `sun`).
+    pub weekday: String,
+    /// Time of day in UTC (`HH:MM`). If absent, the source may update at any time that day.
+    #[serde(default)]
+    pub time: Option<String>,
+}
+
+/// Source item data output from a script.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceItemFetchData {
+    #[serde(default)]
+    pub last_updated: Option<String>,
+    pub tags: BTreeMap<String, Value>,
+}
+
+/// An item in the item list of a source.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceMetaItem {
+    pub path: String,
+    #[serde(rename = "virtual", default)]
+    pub is_virtual: bool,
+    #[serde(default)]
+    pub tags: BTreeMap<String, Value>,
+}
+
+/// A source found by searching a domain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResult {
+    pub path: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// URL patterns of a domain, used to find sources for web URLs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UrlPatterns {
+    /// Regular expressions matching URLs of sources.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// If true, the script exports `matchUrl` to turn matching URLs into source paths.
+    #[serde(default)]
+    pub match_url: bool,
+}
+
+/// The source path for a web URL, as returned by `matchUrl`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UrlMatch {
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// The previously stored version of a source, passed to incremental source fetches.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreviousSource {
+    pub last_updated: Option<String>,
+    pub tags: BTreeMap<String, Value>,
+    pub items: Vec<SourceMetaItem>,
+}
+
+/// Valid values of [ScheduledUpdate::weekday].
+pub const WEEKDAYS: &[&str] = &["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Parses a [ScheduledUpdate::time] into hours and minutes.
+pub fn parse_schedule_time(time: &str) -> Option<(u32, u32)> {
+    let mut parts = time.splitn(2, ':');
+    let hour = parts.next()?;
+    let minute = parts.next()?;
+    let is_two_digits = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_two_digits(hour) || !is_two_digits(minute) {
+        return None;
+    }
+    let hour: u32 = hour.parse().ok()?;
+    let minute: u32 = minute.parse().ok()?;
+    if hour < 24 && minute < 60 {
+        Some((hour, minute))
+    } else {
+        None
+    }
+}
+
+/// Tags that must be strings if present.
+const STRING_TAGS: &[&str] = &["title", "canonical_url", "contents"];
+
+/// Tags that must be maps of strings if present.
+const STRING_MAP_TAGS: &[&str] = &["preface", "appendix", "description"];
+
+/// Severity of a [ResultIssue].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueLevel {
+    /// The result is invalid and will be rejected.
+    Error,
+    /// The result is valid, but probably not what the script intended.
+    Warning,
+}
+
+/// A problem found in a script result.
+#[derive(Debug, Clone)]
+pub struct ResultIssue {
+    pub level: IssueLevel,
+    /// Location in the result, e.g. `items[12].path`. Empty for the result itself.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ResultIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "result: {}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Validates a source returned by a script.
+///
+/// Returns the parsed source if there were no errors, and all issues that were found.
+pub fn validate_source(data: Value) -> (Option<SourceFetchData>, Vec<ResultIssue>) {
+    let mut v = Validator::default();
+    v.source(&data);
+    v.finish(data)
+}
+
+/// Validates a source item returned by a script.
+///
+/// Returns the parsed source item if there were no errors, and all issues that were found.
+pub fn validate_source_item(data: Value) -> (Option<SourceItemFetchData>, Vec<ResultIssue>) {
+    let mut v = Validator::default();
+    v.source_item("", &data);
+    v.finish(data)
+}
+
+/// Validates search results returned by a script.
+///
+/// Returns the parsed results if there were no errors, and all issues that were found.
+pub fn validate_search_results(data: Value) -> (Option<Vec<SearchResult>>, Vec<ResultIssue>) {
+    let mut v = Validator::default();
+    v.search_results(&data);
+    v.finish(data)
+}
+
+/// Validates URL patterns returned by a script.
+///
+/// Returns the parsed patterns if there were no errors, and all issues that were found.
+pub fn validate_url_patterns(data: Value) -> (Option<UrlPatterns>, Vec<ResultIssue>) {
+    let mut v = Validator::default();
+    v.url_patterns(&data);
+    v.finish(data)
+}
+
+/// Validates a URL match returned by a script.
+///
+/// Returns the parsed match if there were no errors, and all issues that were found.
+pub fn validate_url_match(data: Value) -> (Option<UrlMatch>, Vec<ResultIssue>) {
+    let mut v = Validator::default();
+    v.url_match(&data);
+    v.finish(data)
+}
+
+fn field_path(parent: &str, name: &str) -> String {
+    let is_ident = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_ident {
+        format!("{}[{:?}]", parent, name)
+    } else if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", parent, name)
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    issues: Vec<ResultIssue>,
+}
+
+impl Validator {
+    fn error(&mut self, path: &str, message: impl Into<String>) {
+        self.issues.push(ResultIssue {
+            level: IssueLevel::Error,
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    fn warn(&mut self, path: &str, message: impl Into<String>) {
+        self.issues.push(ResultIssue {
+            level: IssueLevel::Warning,
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    fn has_errors(&self) -> bool {
+        self.issues.iter().any(|i| i.level == IssueLevel::Error)
+    }
+
+    fn finish<T: DeserializeOwned>(mut self, data: Value) -> (Option<T>, Vec<ResultIssue>) {
+        if self.has_errors() {
+            return (None, self.issues);
+        }
+        match serde_json::from_value(data) {
+            Ok(data) => (Some(data), self.issues),
+            Err(err) => {
+                // should have been caught above
+                self.error("", err.to_string());
+                (None, self.issues)
+            }
+        }
+    }
+
+    /// Checks that the value is an object and warns about unknown fields.
+    fn object<'a>(
+        &mut self,
+        path: &str,
+        value: &'a Value,
+        fields: &[&str],
+    ) -> Option<&'a Map<String, Value>> {
+        let object = match value {
+            Value::Object(object) => object,
+            value => {
+                self.error(
+                    path,
+                    format!("expected an object, got {}", type_name(value)),
+                );
+                return None;
+            }
+        };
+        for key in object.keys() {
+            if !fields.contains(&&**key) {
+                self.warn(&field_path(path, key), "unknown field; ignored");
+            }
+        }
+        Some(object)
+    }
+
+    fn last_updated(&mut self, path: &str, object: &Map<String, Value>) {
+        match object.get("last_updated") {
+            None | Some(Value::Null) | Some(Value::String(_)) => (),
+            Some(value) => self.error(
+                &field_path(path, "last_updated"),
+                format!("expected a string or null, got {}", type_name(value)),
+            ),
+        }
+    }
+
+    fn tags(&mut self, path: &str, value: &Value) {
+        let tags = match value {
+            Value::Object(tags) => tags,
+            value => {
+                self.error(
+                    path,
+                    format!("expected an object, got {}", type_name(value)),
+                );
+                return;
+            }
+        };
+
+        for (key, value) in tags {
+            let tag_path = field_path(path, key);
+            if STRING_TAGS.contains(&&**key) && !value.is_string() {
+                self.error(
+                    &tag_path,
+                    format!("expected a string, got {}", type_name(value)),
+                );
+            } else if STRING_MAP_TAGS.contains(&&**key) {
+                match value {
+                    Value::Object(map) => {
+                        for (key, value) in map {
+                            if !value.is_string() {
+                                self.warn(
+                                    &field_path(&tag_path, key),
+                                    format!("expected a string, got {}", type_name(value)),
+                                );
+                            }
+                        }
+                    }
+                    value => self.warn(
+                        &tag_path,
+                        format!("expected an object, got {}", type_name(value)),
+                    ),
+                }
+            }
+        }
+    }
+
+    fn source(&mut self, value: &Value) {
+        let object = match self.object(
+            "",
+            value,
+            &[
+                "last_updated",
+                "tags",
+                "items",
+                "item_data",
+                "next_update",
+                "update_schedule",
+                "completed",
+            ],
+        ) {
+            Some(object) => object,
+            None => return,
+        };
+
+        self.last_updated("", object);
+        match object.get("tags") {
+            Some(tags) => self.tags("tags", tags),
+            None => self.error("tags", "missing"),
+        }
+
+        let mut paths = HashSet::new();
+        match object.get("items") {
+            None => (),
+            Some(Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    let path = format!("items[{}]", i);
+                    if let Some(item_path) = self.meta_item(&path, item) {
+                        if !paths.insert(item_path) {
+                            self.error(&field_path(&path, "path"), "duplicate");
+                        }
+                    }
+                }
+            }
+            Some(value) => self.error(
+                "items",
+                format!("expected an array, got {}", type_name(value)),
+            ),
+        }
+
+        match object.get("item_data") {
+            None => (),
+            Some(Value::Object(item_data)) => {
+                for (key, item) in item_data {
+                    let path = format!("item_data[{:?}]", key);
+                    self.source_item(&path, item);
+                    if !paths.contains(&**key) {
+                        self.warn(&path, "path is not in items; ignored");
+                    }
+                }
+            }
+            Some(value) => self.error(
+                "item_data",
+                format!("expected an object, got {}", type_name(value)),
+            ),
+        }
+
+        self.schedule(object);
+    }
+
+    fn schedule(&mut self, object: &Map<String, Value>) {
+        match object.get("next_update") {
+            None | Some(Value::Null) => (),
+            Some(Value::String(s)) if chrono::DateTime::parse_from_rfc3339(s).is_ok() => (),
+            Some(Value::String(s)) => self.error(
+                "next_update",
+                format!("invalid date {:?} (expected RFC 3339)", s),
+            ),
+            Some(value) => self.error(
+                "next_update",
+                format!("expected a string or null, got {}", type_name(value)),
+            ),
+        }
+        match object.get("completed") {
+            None | Some(Value::Bool(_)) => (),
+            Some(value) => self.error(
+                "completed",
+                format!("expected a boolean, got {}", type_name(value)),
+            ),
+        }
+
+        let schedule = match object.get("update_schedule") {
+            None => return,
+            Some(Value::Array(schedule)) => schedule,
+            Some(value) => {
+                self.error(
+                    "update_schedule",
+                    format!("expected an array, got {}", type_name(value)),
+                );
+                return;
+            }
+        };
+        for (i, entry) in schedule.iter().enumerate() {
+            let path = format!("update_schedule[{}]", i);
+            let object = match self.object(&path, entry, &["weekday", "time"]) {
+                Some(object) => object,
+                None => continue,
+            };
+
+            let weekday_path = field_path(&path, "weekday");
+            match object.get("weekday") {
+                Some(Value::String(s)) if WEEKDAYS.contains(&&**s) => (),
+                Some(Value::String(s)) => self.error(
+                    &weekday_path,
+                    format!(
+                        "invalid weekday {:?} (expected one of {})",
+                        s,
+                        WEEKDAYS.join(", ")
+                    ),
+                ),
+                Some(value) => self.error(
+                    &weekday_path,
+                    format!("expected a string, got {}", type_name(value)),
+                ),
+                None => self.error(&weekday_path, "missing"),
+            }
+            let time_path = field_path(&path, "time");
+            match object.get("time") {
+                None | Some(Value::Null) => (),
+                Some(Value::String(s)) if parse_schedule_time(s).is_some() => (),
+                Some(Value::String(s)) => {
+                    self.error(&time_path, format!("invalid time {:?} (expected HH:MM)", s))
+                }
+                Some(value) => self.error(
+                    &time_path,
+                    format!("expected a string or null, got {}", type_name(value)),
+                ),
+            }
+        }
+    }
+
+    /// Validates an entry of the item list and returns its path.
+    fn meta_item<'a>(&mut self, path: &str, value: &'a Value) -> Option<&'a str> {
+        let object = self.object(path, value, &["path", "virtual", "tags"])?;
+
+        match object.get("virtual") {
+            None | Some(Value::Bool(_)) => (),
+            Some(value) => self.error(
+                &field_path(path, "virtual"),
+                format!("expected a boolean, got {}", type_name(value)),
+            ),
+        }
+        if let Some(tags) = object.get("tags") {
+            self.tags(&field_path(path, "tags"), tags);
+        }
+
+        let item_path = field_path(path, "path");
+        match object.get("path") {
+            Some(Value::String(s)) if s.trim().is_empty() => {
+                self.error(&item_path, "must not be empty");
+                None
+            }
+            Some(Value::String(s)) => Some(s),
+            Some(value) => {
+                self.error(
+                    &item_path,
+                    format!("expected a string, got {}", type_name(value)),
+                );
+                None
+            }
+            None => {
+                self.error(&item_path, "missing");
+                None
+            }
+        }
+    }
+
+    fn search_results(&mut self, value: &Value) {
+        let results = match value {
+            Value::Array(results) => results,
+            value => {
+                self.error("", format!("expected an array, got {}", type_name(value)));
+                return;
+            }
+        };
+
+        let mut paths = HashSet::new();
+        for (i, result) in results.iter().enumerate() {
+            let path = format!("[{}]", i);
+            let object = match self.object(&path, result, &["path", "title", "description"]) {
+                Some(object) => object,
+                None => continue,
+            };
+
+            let result_path = field_path(&path, "path");
+            match object.get("path") {
+                Some(Value::String(s)) if s.trim().is_empty() => {
+                    self.error(&result_path, "must not be empty")
+                }
+                Some(Value::String(s)) => {
+                    if !paths.insert(s) {
+                        self.warn(&result_path, "duplicate");
+                    }
+                }
+                Some(value) => self.error(
+                    &result_path,
+                    format!("expected a string, got {}", type_name(value)),
+                ),
+                None => self.error(&result_path, "missing"),
+            }
+            match object.get("title") {
+                Some(Value::String(_)) => (),
+                Some(value) => self.error(
+                    &field_path(&path, "title"),
+                    format!("expected a string, got {}", type_name(value)),
+                ),
+                None => self.error(&field_path(&path, "title"), "missing"),
+            }
+            match object.get("description") {
+                None | Some(Value::Null) | Some(Value::String(_)) => (),
+                Some(value) => self.error(
+                    &field_path(&path, "description"),
+                    format!("expected a string or null, got {}", type_name(value)),
+                ),
+            }
+        }
+    }
+
+    fn url_patterns(&mut self, value: &Value) {
+        let object = match self.object("", value, &["patterns", "match_url"]) {
+            Some(object) => object,
+            None => return,
+        };
+
+        match object.get("patterns") {
+            None => (),
+            Some(Value::Array(patterns)) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if !pattern.is_string() {
+                        self.error(
+                            &format!("patterns[{}]", i),
+                            format!("expected a string, got {}", type_name(pattern)),
+                        );
+                    }
+                }
+            }
+            Some(value) => self.error(
+                "patterns",
+                format!("expected an array, got {}", type_name(value)),
+            ),
+        }
+        match object.get("match_url") {
+            None | Some(Value::Bool(_)) => (),
+            Some(value) => self.error(
+                "match_url",
+                format!("expected a boolean, got {}", type_name(value)),
+            ),
+        }
+    }
+
+    fn url_match(&mut self, value: &Value) {
+        let object = match self.object("", value, &["path"]) {
+            Some(object) => object,
+            None => return,
+        };
+
+        match object.get("path") {
+            None | Some(Value::Null) => (),
+            Some(Value::String(s)) if s.trim().is_empty() => {
+                self.error("path", "must not be empty")
+            }
+            Some(Value::String(_)) => (),
+            Some(value) => self.error(
+                "path",
+                format!("expected a string or null, got {}", type_name(value)),
+            ),
+        }
+    }
+
+    fn source_item(&mut self, path: &str, value: &Value) {
+        let object = match self.object(path, value, &["last_updated", "tags"]) {
+            Some(object) => object,
+            None => return,
+        };
+
+        self.last_updated(path, object);
+        let tags_path = field_path(path, "tags");
+        match object.get("tags") {
+            Some(tags) => self.tags(&tags_path, tags),
+            None => self.error(&tags_path, "missing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deno_core::serde_json::json;
+
+    #[test]
+    fn schedule_times() {
+        let cases = [
+            ("00:00", Some((0, 0))),
+            ("09:05", Some((9, 5))),
+            ("23:59", Some((23, 59))),
+            ("24:00", None),
+            ("12:60", None),
+            ("+1:00", None),
+            ("01:+1", None),
+            ("-1:00", None),
+            ("1:00", None),
+            ("01:0", None),
+            ("001:00", None),
+            ("01:00:00", None),
+            ("0100", None),
+            ("", None),
+        ];
+        for (time, expected) in &cases {
+            assert_eq!(parse_schedule_time(time), *expected, "{:?}", time);
+        }
+    }
+
+    #[test]
+    fn field_paths() {
+        let cases = [
+            ("", "tags", "tags"),
+            ("items[0]", "path", "items[0].path"),
+            ("tags", "canonical_url", "tags.canonical_url"),
+            ("tags", "a-b", "tags[\"a-b\"]"),
+            ("tags", "1a", "tags[\"1a\"]"),
+            ("tags", "", "tags[\"\"]"),
+            ("", "a b", "[\"a b\"]"),
+        ];
+        for (parent, name, expected) in &cases {
+            assert_eq!(field_path(parent, name), *expected);
+        }
+    }
+
+    /// Returns the levels and paths of the issues found in a source, sorted by path.
+    fn source_issues(data: Value) -> Vec<(IssueLevel, String)> {
+        let (_, issues) = validate_source(data);
+        let mut issues: Vec<_> = issues.into_iter().map(|i| (i.level, i.path)).collect();
+        issues.sort_by(|a, b| a.1.cmp(&b.1));
+        issues
+    }
+
+    #[test]
+    fn source_issue_paths() {
+        use IssueLevel::{Error, Warning};
+
+        let cases = vec![
+            (json!({ "tags": {} }), vec![]),
+            (json!([]), vec![(Error, "")]),
+            (json!({}), vec![(Error, "tags")]),
+            (
+                json!({ "tags": {}, "extra": 1, "odd key": 1 }),
+                vec![(Warning, "[\"odd key\"]"), (Warning, "extra")],
+            ),
+            (
+                json!({ "tags": { "title": 1, "preface": { "en": 1 } } }),
+                vec![(Warning, "tags.preface.en"), (Error, "tags.title")],
+            ),
+            (
+                json!({ "tags": {}, "items": [{ "path": "a" }, { "path": "a" }, {}] }),
+                vec![(Error, "items[1].path"), (Error, "items[2].path")],
+            ),
+            (
+                json!({
+                    "tags": {},
+                    "items": [{ "path": "a" }],
+                    "item_data": { "a": { "tags": { "title": 1 } }, "b c": { "tags": {} } },
+                }),
+                vec![
+                    (Error, "item_data[\"a\"].tags.title"),
+                    (Warning, "item_data[\"b c\"]"),
+                ],
+            ),
+            (
+                json!({
+                    "tags": {},
+                    "update_schedule": [
+                        { "weekday": "mon", "time": "24:00" },
+                        { "weekday": "Mon", "time": "+1:00" },
+                        { "time": null },
+                    ],
+                }),
+                vec![
+                    (Error, "update_schedule[0].time"),
+                    (Error, "update_schedule[1].weekday"),
+                    (Error, "update_schedule[1].time"),
+                    (Error, "update_schedule[2].weekday"),
+                ],
+            ),
+        ];
+        for (data, expected) in cases {
+            let mut expected: Vec<_> = expected
+                .into_iter()
+                .map(|(level, path)| (level, String::from(path)))
+                .collect();
+            expected.sort_by(|a, b| a.1.cmp(&b.1));
+            assert_eq!(source_issues(data.clone()), expected, "{}", data);
+        }
+    }
+
+    #[test]
+    fn search_result_issue_paths() {
+        let (results, issues) = validate_search_results(json!([
+            { "path": "a", "title": "A" },
+            { "path": "a", "title": "A" },
+            { "path": " ", "title": 1 },
+        ]));
+        assert!(results.is_none());
+        let paths: Vec<_> = issues.iter().map(|i| (i.level, &*i.path)).collect();
+        assert_eq!(
+            paths,
+            vec![
+                (IssueLevel::Warning, "[1].path"),
+                (IssueLevel::Error, "[2].path"),
+                (IssueLevel::Error, "[2].title"),
+            ]
+        );
+    }
+}
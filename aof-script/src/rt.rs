@@ -4,7 +4,7 @@ use deno_core::url::{self, Url};
 use deno_core::v8;
 use deno_core::{JsRuntime, ModuleLoader, ModuleSource, ModuleSpecifier, OpState, RuntimeOptions};
 use futures::FutureExt;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -16,21 +16,31 @@ use futures::channel::mpsc::Receiver;
 use smol::stream::StreamExt;
 
 const INITIAL_HEAP_SIZE: usize = 0;
-const MAX_HEAP_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+/// Extra heap space V8 is given once to terminate a script that reached the heap limit.
+const HEAP_TERMINATION_MARGIN: usize = 32 * 1024 * 1024; // 32 MiB
 
 const MODULE_SOURCE_PREFIX: &str = "aof://";
+const MODULE_LIBRARY_SCHEME: &str = "aof-domain";
+const MAX_LIBRARY_COUNT: usize = 32;
 const MODULE_SOURCE_EXEC_NAME: &str = "script_exec";
+const MODULE_STD_SPEC: &str = "aof:std";
 const MODULE_REPLACE_STR: &str = "###MODULE###";
 const SCRIPT_PREFIX: &str = r#"var Deno = null;"#;
 
+/// Returned when a script was terminated because it ran out of memory.
+#[derive(Debug, Error)]
+#[error("script exceeded its memory limit")]
+pub struct HeapLimitExceeded;
+
 pub struct ScriptRt {
     runtime: JsRuntime,
+    heap_limit_reached: Rc<Cell<bool>>,
 }
 
 impl ScriptRt {
     pub fn new(ctx: Arc<dyn ScriptContext>, mod_loader: ModLoader) -> Result<Self, AnyError> {
         let create_params =
-            v8::CreateParams::default().heap_limits(INITIAL_HEAP_SIZE, MAX_HEAP_SIZE);
+            v8::CreateParams::default().heap_limits(INITIAL_HEAP_SIZE, ctx.limits().max_heap_size);
 
         let mod_loader: Rc<dyn deno_core::ModuleLoader> = Rc::new(mod_loader);
 
@@ -45,10 +55,38 @@ impl ScriptRt {
             v8_platform: None,
         });
 
+        // without this, V8 would just crash the process once the heap is full
+        let heap_limit_reached = Rc::new(Cell::new(false));
+        {
+            let heap_limit_reached = Rc::clone(&heap_limit_reached);
+            let isolate_handle = runtime.v8_isolate().thread_safe_handle();
+            runtime.add_near_heap_limit_callback(move |current_limit, _| {
+                // V8 may call this repeatedly while terminating, but the heap must only grow once
+                if heap_limit_reached.replace(true) {
+                    return current_limit;
+                }
+                isolate_handle.terminate_execution();
+                // give V8 some room to terminate the script
+                current_limit + HEAP_TERMINATION_MARGIN
+            });
+        }
+
         crate::script_ctx::init_rt(&mut runtime, Arc::clone(&ctx));
         crate::ops::init_rt(&mut runtime, ctx)?;
 
-        Ok(ScriptRt { runtime })
+        Ok(ScriptRt {
+            runtime,
+            heap_limit_reached,
+        })
+    }
+
+    /// Replaces the error with [HeapLimitExceeded] if the script was terminated for that reason.
+    pub fn map_error(&self, err: AnyError) -> AnyError {
+        if self.heap_limit_reached.get() {
+            HeapLimitExceeded.into()
+        } else {
+            err
+        }
     }
 
     pub async fn eval_module(
@@ -56,13 +94,18 @@ impl ScriptRt {
         module: Url,
     ) -> Result<Receiver<Result<(), AnyError>>, AnyError> {
         let module = ModuleSpecifier::from(module);
-        let mod_id = self.runtime.load_module(&module, None).await?;
+        let mod_id = match self.runtime.load_module(&module, None).await {
+            Ok(mod_id) => mod_id,
+            Err(err) => return Err(self.map_error(err)),
+        };
         Ok(self.runtime.mod_evaluate(mod_id))
     }
 
     pub async fn run_event_loop(&mut self) -> Result<(), AnyError> {
-        self.runtime.run_event_loop().await?;
-        Ok(())
+        match self.runtime.run_event_loop().await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.map_error(err)),
+        }
     }
 }
 
@@ -93,6 +136,10 @@ impl InnerScript {
 
         mod_loader.insert(mod_spec.clone(), script);
         mod_loader.insert(exec_spec.clone(), exec_source);
+        mod_loader.insert(
+            Url::parse(MODULE_STD_SPEC).unwrap(),
+            include_str!("std.js").into(),
+        );
 
         let rt = ScriptRt::new(ctx, mod_loader)?;
 
@@ -103,28 +150,77 @@ impl InnerScript {
     }
 
     pub async fn run(&mut self) -> Result<(), AnyError> {
-        let mut module = self.rt.eval_module(self.exec_module.clone()).await.unwrap();
-        self.rt.run_event_loop().await.unwrap();
+        let mut module = self.rt.eval_module(self.exec_module.clone()).await?;
+        self.rt.run_event_loop().await?;
         while let Some(next) = module.next().await {
-            next.unwrap();
+            next.map_err(|err| self.rt.map_error(err))?;
         }
         Ok(())
     }
 }
 
 pub struct ModLoader {
-    modules: HashMap<Url, String>,
+    modules: RefCell<HashMap<Url, String>>,
+    /// Maps library modules to the module that first imported them.
+    importers: RefCell<HashMap<Url, Url>>,
 }
 
 impl ModLoader {
     pub fn new() -> Self {
         ModLoader {
-            modules: HashMap::new(),
+            modules: RefCell::new(HashMap::new()),
+            importers: RefCell::new(HashMap::new()),
         }
     }
 
     pub fn insert(&mut self, url: Url, source: String) {
-        self.modules.insert(url, source);
+        self.modules.get_mut().insert(url, source);
+    }
+
+    /// Resolves an `aof-domain:<id>` import, loading the library script from the script context.
+    fn resolve_library(
+        &self,
+        state: &OpState,
+        url: Url,
+        referrer: &str,
+    ) -> Result<ModuleSpecifier, ModLoadError> {
+        let referrer = Url::parse(referrer).ok();
+
+        // walk up the import chain to check if this would create a cycle
+        {
+            let importers = self.importers.borrow();
+            let mut current = referrer.as_ref();
+            while let Some(module) = current {
+                if *module == url {
+                    return Err(ModLoadError::ImportCycle(url));
+                }
+                current = importers.get(module);
+            }
+        }
+
+        if !self.modules.borrow().contains_key(&url) {
+            if self.importers.borrow().len() >= MAX_LIBRARY_COUNT {
+                return Err(ModLoadError::TooManyLibraries);
+            }
+
+            let ctx = state
+                .script_ctx_arc()
+                .map_err(|_| ModLoadError::NoContext)?;
+            let script = ctx
+                .load_library(url.path())
+                .map_err(|err| ModLoadError::Library(url.clone(), err))?;
+            let script = String::from(SCRIPT_PREFIX) + &script;
+            self.modules.borrow_mut().insert(url.clone(), script);
+        }
+
+        if let Some(referrer) = referrer {
+            self.importers
+                .borrow_mut()
+                .entry(url.clone())
+                .or_insert(referrer);
+        }
+
+        Ok(ModuleSpecifier::from(url))
     }
 }
 
@@ -132,17 +228,28 @@ impl ModLoader {
 enum ModLoadError {
     #[error("module “{0}” not found")]
     NotFound(Url),
+    #[error("could not import “{0}”: {1}")]
+    Library(Url, String),
+    #[error("could not import “{0}”: import cycle")]
+    ImportCycle(Url),
+    #[error("too many imported libraries")]
+    TooManyLibraries,
+    #[error("failed to acquire script context")]
+    NoContext,
 }
 
 impl ModuleLoader for ModLoader {
     fn resolve(
         &self,
-        _: Rc<RefCell<OpState>>,
+        state: Rc<RefCell<OpState>>,
         specifier: &str,
         referrer: &str,
         _: bool,
     ) -> Result<ModuleSpecifier, AnyError> {
         match Url::parse(specifier) {
+            Ok(url) if url.scheme() == MODULE_LIBRARY_SCHEME => {
+                Ok(self.resolve_library(&state.borrow(), url, referrer)?)
+            }
             Ok(url) => Ok(ModuleSpecifier::from(url)),
             Err(url::ParseError::RelativeUrlWithoutBase) => {
                 let base = Url::parse(referrer)?;
@@ -160,7 +267,7 @@ impl ModuleLoader for ModLoader {
         _: bool,
     ) -> Pin<Box<dyn Future<Output = Result<ModuleSource, AnyError>>>> {
         let url = specifier.clone();
-        let module = self.modules.get(&url).map(Clone::clone);
+        let module = self.modules.borrow().get(&url).map(Clone::clone);
 
         async move {
             match module {
@@ -1,11 +1,35 @@
 use crate::ops::console::ConsoleMessage;
+use crate::{CachedResponse, CassetteRequest, FetchMode, NetworkPolicy, PreviousSource};
+use deno_core::serde_json::{Map, Value};
 use deno_core::url::Url;
 use deno_core::{JsRuntime, OpState, Resource};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Resource limits of a script.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScriptLimits {
+    /// Max size of the V8 heap in bytes.
+    pub max_heap_size: usize,
+    /// Timeout for a single request.
+    pub request_timeout: Duration,
+    /// Max size of a single response body in bytes.
+    pub max_response_size: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        ScriptLimits {
+            max_heap_size: 256 * 1024 * 1024, // 256 MiB
+            request_timeout: Duration::from_secs(20),
+            max_response_size: 256 * 1024 * 1024, // 256 MiB
+        }
+    }
+}
 
 /// The execution context of a script.
 pub trait ScriptContext: Send + Sync {
@@ -14,6 +38,30 @@ pub trait ScriptContext: Send + Sync {
         Ok(())
     }
 
+    /// Requests permission to follow a redirect to a URL.
+    ///
+    /// This is called by the HTTP client while the request is in progress, so it should fail
+    /// rather than block for a long time. By default, this is the same as
+    /// [ScriptContext::request_permission].
+    fn request_redirect_permission(
+        &self,
+        method: &reqwest::Method,
+        url: &Url,
+    ) -> Result<(), String> {
+        self.request_permission(method, url)
+    }
+
+    /// Returns the network policy that every connection made by a fetch is checked against.
+    /// If None, connections are not checked (only [ScriptContext::request_permission] is).
+    fn network_policy(&self) -> Option<NetworkPolicy> {
+        None
+    }
+
+    /// Returns the resource limits of the script.
+    fn limits(&self) -> ScriptLimits {
+        ScriptLimits::default()
+    }
+
     /// Notifies the script context that a fetch operation has started.
     /// This may be used to pause any timer limiting script execution time.
     fn fetch_did_start(&self) {}
@@ -30,15 +78,104 @@ pub trait ScriptContext: Send + Sync {
 
     /// Sets the response to the current request.
     fn set_aof_response(&self, _data: deno_core::serde_json::Value) {}
+
+    /// Returns a value from the persistent storage of the current domain.
+    fn storage_get(&self, _key: &str) -> Result<Option<deno_core::serde_json::Value>, String> {
+        Err(String::from("storage is not available in this context"))
+    }
+
+    /// Sets a value in the persistent storage of the current domain.
+    fn storage_set(&self, _key: &str, _value: deno_core::serde_json::Value) -> Result<(), String> {
+        Err(String::from("storage is not available in this context"))
+    }
+
+    /// Deletes a value from the persistent storage of the current domain.
+    fn storage_delete(&self, _key: &str) -> Result<(), String> {
+        Err(String::from("storage is not available in this context"))
+    }
+
+    /// Returns a secret that the user the script is running for has stored for the current
+    /// domain.
+    fn secret_get(&self, _name: &str) -> Result<Option<String>, String> {
+        Err(String::from("secrets are not available in this context"))
+    }
+
+    /// Returns the values of all secrets returned by [ScriptContext::secret_get] so far, so that
+    /// they can be redacted from console output.
+    fn revealed_secrets(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the persisted cookie jar of the current domain, JSON-encoded.
+    fn cookies_get(&self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    /// Stores cookies from the Set-Cookie headers of a response from the given URL in the
+    /// persisted cookie jar of the current domain.
+    fn cookies_store(&self, _url: &Url, _set_cookies: Vec<String>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns a response from the HTTP cache of the current domain. The key contains the URL and
+    /// the request headers.
+    fn http_cache_get(&self, _key: &str) -> Result<Option<CachedResponse>, String> {
+        Ok(None)
+    }
+
+    /// Stores a response in the HTTP cache of the current domain.
+    fn http_cache_set(&self, _key: &str, _response: CachedResponse) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns how fetch requests should be handled.
+    fn fetch_mode(&self) -> FetchMode {
+        FetchMode::Live
+    }
+
+    /// Returns a recorded response from the cassette (in [FetchMode::Replay]).
+    fn cassette_get(&self, _request: &CassetteRequest) -> Result<Option<CachedResponse>, String> {
+        Err(String::from("cassettes are not available in this context"))
+    }
+
+    /// Records a response to the cassette (in [FetchMode::Record]).
+    fn cassette_record(
+        &self,
+        _request: CassetteRequest,
+        _response: CachedResponse,
+    ) -> Result<(), String> {
+        Err(String::from("cassettes are not available in this context"))
+    }
+
+    /// Returns the script of a library domain imported by the current domain.
+    fn load_library(&self, _id: &str) -> Result<String, String> {
+        Err(String::from("libraries are not available in this context"))
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum AofRequest {
     #[serde(rename = "source")]
-    Source { path: String },
+    Source {
+        path: String,
+        /// The previous version of the source, if there is one.
+        previous: Option<PreviousSource>,
+        /// Domain options of the user the source is fetched for.
+        options: Map<String, Value>,
+    },
     #[serde(rename = "source-item")]
-    SourceItem { path: String },
+    SourceItem {
+        path: String,
+        /// Domain options of the user the source item is fetched for.
+        options: Map<String, Value>,
+    },
+    #[serde(rename = "search")]
+    Search { query: String },
+    #[serde(rename = "url-patterns")]
+    UrlPatterns,
+    #[serde(rename = "match-url")]
+    MatchUrl { url: String },
 }
 
 const RNAME_CTX: &str = "aof_ctx";
@@ -104,6 +241,8 @@ impl ScriptContext for UnrestrictedContext {
     fn get_aof_request(&self) -> AofRequest {
         AofRequest::Source {
             path: String::new(),
+            previous: None,
+            options: Map::new(),
         }
     }
 }
@@ -0,0 +1,171 @@
+// Standard module for domain scripts, available as `aof:std`.
+
+async function fetchOk(url, init) {
+    const res = await fetch(url, init);
+    if (!res.ok) throw new Error(`Failed to fetch ${url}: got ${res.status} ${res.statusText}`);
+    return res;
+}
+
+/**
+ * Fetches a URL and parses the response as HTML.
+ * Throws if the response status is not 2xx.
+ */
+export async function loadHtml(url, init = {}) {
+    const res = await fetchOk(url, init);
+    const text = await res.text();
+    console.debug('Got response HTML, parsing');
+    const doc = new DOMParser().parseFromString(text, 'text/html');
+    console.debug('Successfully parsed document');
+    return doc;
+}
+
+/**
+ * Fetches a URL and parses the response as XML.
+ * Throws if the response status is not 2xx or if the XML is malformed.
+ */
+export async function loadXml(url, init = {}) {
+    const res = await fetchOk(url, init);
+    const text = await res.text();
+    const doc = new DOMParser().parseFromString(text, 'application/xml');
+    const error = doc.querySelector('parsererror');
+    if (error) throw new Error(`Failed to parse XML from ${url}: ${error.textContent}`);
+    return doc;
+}
+
+/**
+ * Fetches a URL and parses the response as JSON.
+ * Throws if the response status is not 2xx.
+ */
+export async function loadJson(url, init = {}) {
+    const res = await fetchOk(url, init);
+    return JSON.parse(await res.text());
+}
+
+/**
+ * Resolves a possibly relative URL against a base URL.
+ * Returns null if the URL is empty or invalid.
+ */
+export function resolveURL(url, base) {
+    if (url === null || url === undefined) return null;
+    url = url.toString().trim();
+    if (!url) return null;
+    try {
+        return new URL(url, base).toString();
+    } catch {
+        return null;
+    }
+}
+
+/**
+ * Parses a date and returns it as an RFC 3339 string, or null if it is invalid.
+ * Accepts Date objects, timestamps in milliseconds, and anything `Date` can parse.
+ */
+export function parseDate(input) {
+    if (input === null || input === undefined || input === '') return null;
+    const date = input instanceof Date
+        ? input
+        : new Date(typeof input === 'string' ? input.trim() : input);
+    if (!Number.isFinite(date.getTime())) return null;
+    return date.toISOString();
+}
+
+/**
+ * Cleans up text content: normalizes whitespace (including non-breaking spaces) to single spaces
+ * and trims the result.
+ */
+export function cleanText(text) {
+    if (text === null || text === undefined) return '';
+    return text.toString().replace(/\s+/g, ' ').trim();
+}
+
+/**
+ * Returns the cleaned-up text content of a node, or an empty string if the node is null.
+ */
+export function textOf(node) {
+    return cleanText(node?.textContent);
+}
+
+/**
+ * Parses a `srcset` attribute into a list of `{ url, descriptor }` entries.
+ */
+export function parseSrcset(srcset) {
+    const entries = [];
+    if (!srcset) return entries;
+    // roughly follows the algorithm in the HTML spec: URLs may contain commas, so candidates are
+    // only split at commas after whitespace or at the end of a URL
+    let rest = srcset;
+    while (true) {
+        rest = rest.replace(/^[\s,]+/, '');
+        if (!rest) break;
+        let url = rest.match(/^\S+/)[0];
+        rest = rest.substr(url.length);
+        let descriptor = '';
+        if (url.endsWith(',')) {
+            url = url.replace(/,+$/, '');
+        } else {
+            const end = rest.indexOf(',');
+            descriptor = (end === -1 ? rest : rest.substr(0, end)).trim();
+            rest = end === -1 ? '' : rest.substr(end + 1);
+        }
+        entries.push({ url, descriptor });
+    }
+    return entries;
+}
+
+/**
+ * Returns a `srcset` attribute with all URLs resolved against the given base URL.
+ */
+export function resolveSrcset(srcset, base) {
+    return parseSrcset(srcset)
+        .map(({ url, descriptor }) => {
+            const resolved = resolveURL(url, base) || url;
+            return descriptor ? `${resolved} ${descriptor}` : resolved;
+        })
+        .join(', ');
+}
+
+/**
+ * Returns the (resolved) URL of the largest candidate in a `srcset` attribute, or null.
+ */
+export function largestSrcsetURL(srcset, base) {
+    let best = null;
+    let bestSize = -1;
+    for (const { url, descriptor } of parseSrcset(srcset)) {
+        const size = parseFloat(descriptor) || 1;
+        if (size > bestSize) {
+            best = url;
+            bestSize = size;
+        }
+    }
+    return best && resolveURL(best, base);
+}
+
+/**
+ * Resolves all links and image sources in a node against the given base URL, in place.
+ *
+ * Lazy-loaded images (with the source in a `data-src` attribute or similar) are fixed up too.
+ */
+export function resolveLinks(node, base) {
+    for (const anchor of node.querySelectorAll('a[href]')) {
+        const href = resolveURL(anchor.getAttribute('href'), base);
+        if (href) anchor.setAttribute('href', href);
+    }
+    for (const img of node.querySelectorAll('img')) {
+        for (const attr of ['data-src', 'data-lazy-src', 'data-original']) {
+            if (img.hasAttribute(attr)) {
+                img.setAttribute('src', img.getAttribute(attr));
+                img.removeAttribute(attr);
+            }
+        }
+        if (img.hasAttribute('data-srcset')) {
+            img.setAttribute('srcset', img.getAttribute('data-srcset'));
+            img.removeAttribute('data-srcset');
+        }
+
+        const src = resolveURL(img.getAttribute('src'), base);
+        if (src) img.setAttribute('src', src);
+    }
+    for (const elem of node.querySelectorAll('[srcset]')) {
+        elem.setAttribute('srcset', resolveSrcset(elem.getAttribute('srcset'), base));
+    }
+}
@@ -3,20 +3,9 @@
 //
 // Source paths should look like `/123456` where 123456 is the work ID (easily found in the URL).
 
-async function loadHtml(url, headers = {}) {
-    const res = await fetch(url, { headers });
-    if (!res.ok) throw new Error('Failed to fetch: got ' + res.status + ' ' + res.statusText);
-    const text = await res.text();
-    console.debug('Got response HTML, parsing');
-    const dp = new DOMParser();
-    const doc = dp.parseFromString(text, 'text/html');
-    console.debug('Successfully parsed document');
-    return doc;
-}
+import { loadHtml, resolveURL } from 'aof:std';
 
-function resolveURL(url, base) {
-    return new URL(url, base).toString();
-}
+const HEADERS = { 'Cookie': 'view_adult=true' };
 
 export async function loadSource(path) {
     const id = path.substr(1);
@@ -24,7 +13,7 @@ export async function loadSource(path) {
 
     const canonicalURL = `https://archiveofourown.org/works/${id}?view_adult=true`;
 
-    const doc = await loadHtml(canonicalURL, { 'Cookie': 'view_adult=true' });
+    const doc = await loadHtml(canonicalURL, { headers: HEADERS });
     const titleNode = doc.querySelector('#workskin .preface h2.title');
     if (!titleNode) throw new Error('No title node on page. Bad HTML?');
 
@@ -105,7 +94,7 @@ export async function loadSource(path) {
 
         // no chapter index; use /navigate
         const navURL = `https://archiveofourown.org/works/${id}/navigate`;
-        const doc = await loadHtml(navURL, { 'Cookie': 'view_adult=true' });
+        const doc = await loadHtml(navURL, { headers: HEADERS });
 
         console.debug('Reading items');
         for (const node of doc.querySelectorAll('.chapter.index li > a')) {
@@ -134,7 +123,7 @@ export async function loadSourceItem(path) {
 
     const canonicalURL = `https://archiveofourown.org/chapters/${id}?view_adult=true`;
 
-    const doc = await loadHtml(canonicalURL, { 'Cookie': 'view_adult=true' });
+    const doc = await loadHtml(canonicalURL, { headers: HEADERS });
 
     let chapterNode = doc.querySelector('#chapters > .chapter')
     if (!chapterNode) chapterNode = doc.querySelector('#chapters');
@@ -3,29 +3,7 @@
 //
 // Source paths should look like `/https/example.com` - basically just the hostname.
 
-async function loadHtml(url, headers = {}) {
-    const res = await fetch(url, { headers });
-    if (!res.ok) throw new Error('Failed to fetch: got ' + res.status + ' ' + res.statusText);
-    const text = await res.text();
-    console.debug('Got response HTML, parsing');
-    const dp = new DOMParser();
-    const doc = dp.parseFromString(text, 'text/html');
-    console.debug('Successfully parsed document');
-    return doc;
-}
-
-function resolveURL(url, base) {
-    return new URL(url, base).toString();
-}
-
-function resolveLinksInContainer(node, url) {
-    for (const anchor of node.querySelectorAll('a')) {
-        anchor.setAttribute('href', resolveURL(anchor.getAttribute('href'), url));
-    }
-    for (const img of node.querySelectorAll('img')) {
-        img.setAttribute('src', resolveURL(img.getAttribute('src'), url));
-    }
-}
+import { loadHtml, resolveLinks, resolveURL } from 'aof:std';
 
 export async function loadSource(path) {
     const pathParts = path.substr(1).split('/');
@@ -111,13 +89,13 @@ export async function loadSourceItem(path) {
     const doc = await loadHtml(canonicalURL);
     const comicBody = doc.querySelector('#cc-comicbody');
     if (!comicBody) throw new Error('no comic body');
-    resolveLinksInContainer(comicBody);
+    resolveLinks(comicBody, canonicalURL);
 
     const newsArea = doc.querySelector('.cc-newsarea');
     const tagline = doc.querySelector('.cc-tagline');
 
-    if (newsArea) resolveLinksInContainer(newsArea, canonicalURL);
-    if (tagline) resolveLinksInContainer(tagline, canonicalURL);
+    if (newsArea) resolveLinks(newsArea, canonicalURL);
+    if (tagline) resolveLinks(tagline, canonicalURL);
 
     let contents = `
 <style>
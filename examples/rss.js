@@ -14,17 +14,14 @@ export async function loadSource(path) {
     const url = protocol + '://' + host + '/' + pathParts.slice(2).join('/');
 
     const res = await fetch(url);
-    let rawXml = await res.text();
-
-    // HACK to make link work without xml
-    rawXml = rawXml
-        .replace(/<link>/g, '<xlink>')
-        .replace(/<\/link>/g, '</xlink>');
+    const rawXml = await res.text();
 
     console.log('Parsing XML');
 
     const dp = new DOMParser();
-    const document = dp.parseFromString(rawXml, 'text/html'); // XML not supported yet
+    const document = dp.parseFromString(rawXml, 'application/xml');
+    const errorNode = document.querySelector('parsererror');
+    if (errorNode) throw new Error('could not parse XML: ' + errorNode.textContent);
     const channelNode = document.querySelector('rss channel');
     if (!channelNode) throw new Error('could not find <rss> <channel>');
 
@@ -33,7 +30,7 @@ export async function loadSource(path) {
     };
 
     const titleNode = channelNode.querySelector('title');
-    const linkNode = channelNode.querySelector('xlink');
+    const linkNode = channelNode.querySelector('link');
     const descNode = channelNode.querySelector('description');
     if (titleNode) tags.title = titleNode.textContent;
     if (linkNode) tags.canonical_url = linkNode.textContent;
@@ -50,7 +47,7 @@ export async function loadSource(path) {
 
         const titleNode = item.querySelector('title');
         const descNode = item.querySelector('description');
-        const linkNode = item.querySelector('xlink');
+        const linkNode = item.querySelector('link');
         const pubDateNode = item.querySelector('pubDate');
         if (titleNode) data.tags.title = titleNode.textContent;
         if (linkNode) data.tags.canonical_url = linkNode.textContent;
@@ -8,27 +8,7 @@
 // - open the <head> tag at the top and scroll to find the meta tags
 // - some of them will have URLs like `tapastic://series/123456/info` - take the ID from there
 
-async function loadHtml(url, headers = {}) {
-    const res = await fetch(url, { headers });
-    if (!res.ok) throw new Error('Failed to fetch: got ' + res.status + ' ' + res.statusText);
-    const text = await res.text();
-    console.debug('Got response HTML, parsing');
-    const dp = new DOMParser();
-    const doc = dp.parseFromString(text, 'text/html');
-    console.debug('Successfully parsed document');
-    return doc;
-}
-
-async function loadJson(url, headers = {}) {
-    const res = await fetch(url, { headers });
-    if (!res.ok) throw new Error('Failed to fetch: got ' + res.status + ' ' + res.statusText);
-    const text = await res.text();
-    return JSON.parse(text);
-}
-
-function resolveURL(url, base) {
-    return new URL(url, base).toString();
-}
+import { loadHtml, loadJson, resolveURL } from 'aof:std';
 
 export async function loadSource(path) {
     const id = path.substr(1);
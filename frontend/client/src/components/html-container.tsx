@@ -72,7 +72,18 @@ export class HtmlContainer extends PureComponent<HtmlContainer.Props> {
             anchor.rel = 'nofollow noreferrer';
         });
 
+        // resources archived by the server are served from there, everything else goes through camo
+        const getArchivedUrl = (node: HTMLElement) => {
+            const hash = node.dataset.aofArchived;
+            return hash && /^[0-9a-f]+$/.test(hash) ? api(`resources/archived/${hash}`) : null;
+        };
+
         doc.querySelectorAll('link').forEach(link => {
+            const archivedUrl = getArchivedUrl(link);
+            if (archivedUrl) {
+                link.href = archivedUrl;
+                return;
+            }
             try {
                 const srcUrl = new URL(link.href);
                 if (['http:', 'https:'].includes(srcUrl.protocol)) {
@@ -103,9 +114,14 @@ export class HtmlContainer extends PureComponent<HtmlContainer.Props> {
                 try {
                     const srcUrl = new URL(image.src);
                     if (['http:', 'https:'].includes(srcUrl.protocol)) {
-                        const s = encodeURIComponent(srcUrl.toString());
-                        const r = this.props.referrer ? encodeURIComponent(this.props.referrer) : null;
-                        image.src = api(`resources/camo?url=${s}` + (r ? `&referrer=${r}` : ''));
+                        const archivedUrl = getArchivedUrl(image);
+                        if (archivedUrl) {
+                            image.src = archivedUrl;
+                        } else {
+                            const s = encodeURIComponent(srcUrl.toString());
+                            const r = this.props.referrer ? encodeURIComponent(this.props.referrer) : null;
+                            image.src = api(`resources/camo?url=${s}` + (r ? `&referrer=${r}` : ''));
+                        }
 
                         image.addEventListener('click', () => {
                             let parent: Node | null = image;
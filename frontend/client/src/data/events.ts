@@ -1,5 +1,6 @@
 import { cache } from './cache';
 import {
+    DOMAIN,
     IFetchResult,
     join, Key,
     parseUri,
@@ -62,6 +63,10 @@ const events = {
             cache.insert(SOURCES_LIST_USER, list);
         }
     },
+    subscribed_domain_did_update: (data: { domain: string }) => {
+        // force reload
+        cache.delete(join(DOMAIN, data.domain));
+    },
 } as { [k: string]: (data: any) => void };
 
 export function handleEvent(name: string, data: unknown) {
@@ -104,4 +104,13 @@ export default {
         // TODO: delete when events impl
         cache.delete(paths.DOMAINS_LIST_USER);
     },
+    confirm_allowed_hosts: async ({ id }) => {
+        const res = await req<IResult>('user_confirm_domain_allowed_hosts', { id });
+        if (!res.success) {
+            throw new Error(get(`data.domains.${res.error}`));
+        }
+
+        // force reload
+        cache.delete(join(paths.DOMAIN, id));
+    },
 } as paths.PDomainsType;
@@ -85,6 +85,8 @@ export interface IDomain {
     name: string,
     description: string,
     is_public: boolean,
+    allowed_hosts?: string[] | null,
+    allowed_hosts_confirmed?: boolean,
     editable: boolean,
 }
 
@@ -98,6 +100,7 @@ export type PDomainsType = {
     delete: Fn<typeof DOMAIN_DELETE>,
     subscribe: Fn<typeof DOMAIN_SUBSCRIBE>,
     unsubscribe: Fn<typeof DOMAIN_UNSUBSCRIBE>,
+    confirm_allowed_hosts: Fn<typeof DOMAIN_CONFIRM_ALLOWED_HOSTS>,
 };
 export const DOMAINS_LIST_USER: Key<DomainId[]> = 'aof://domains/list_user';
 export const DOMAINS_LIST_PUBLIC: Key<DomainId[]> = 'aof://domains/list_public';
@@ -118,6 +121,7 @@ export const DOMAIN_UPDATE: FnLoad<{
 export const DOMAIN_DELETE: FnLoad<{ id: DomainId }, void> = 'aof://domains/delete';
 export const DOMAIN_SUBSCRIBE: FnLoad<{ id: DomainId }, void> = 'aof://domains/subscribe';
 export const DOMAIN_UNSUBSCRIBE: FnLoad<{ id: DomainId }, void> = 'aof://domains/unsubscribe';
+export const DOMAIN_CONFIRM_ALLOWED_HOSTS: FnLoad<{ id: DomainId }, void> = 'aof://domains/confirm_allowed_hosts';
 
 export type SourceUri = string[];
 export function parseUri(uri: string): SourceUri {
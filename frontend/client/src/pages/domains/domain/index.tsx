@@ -13,7 +13,7 @@ import {
     DOMAIN_UPDATE,
     join,
     load,
-    lazyLoad, DOMAIN_DELETE, DOMAIN_SUBSCRIBE, DOMAIN_UNSUBSCRIBE
+    lazyLoad, DOMAIN_DELETE, DOMAIN_SUBSCRIBE, DOMAIN_UNSUBSCRIBE, DOMAIN_CONFIRM_ALLOWED_HOSTS
 } from '../../../data';
 import { Checkbox, Progress, TaskButton, TextField } from 'uikit';
 import get from '../../../locale';
@@ -84,13 +84,28 @@ export default class DomainPage extends PureComponent<PageProps> {
                             {get('pages.domain.delete.title')}
                         </TaskButton>
                     )}
-                    {!editable && <AddDomainButton id={route.domain.id} />}
+                    {!editable && (
+                        <AddDomainButton
+                            id={route.domain.id}
+                            allowedHosts={domain ? domain.allowed_hosts : null} />
+                    )}
                 </div>
             );
 
             return (
                 <div class="domain-page">
                     {actions}
+                    {domain && domain.allowed_hosts_confirmed === false && (
+                        <div class="domain-page-hosts-notice">
+                            <div>{get('pages.domain.allowed_hosts.changed')}</div>
+                            <AllowedHosts hosts={domain.allowed_hosts} />
+                            <TaskButton run={async () => {
+                                await load(DOMAIN_CONFIRM_ALLOWED_HOSTS, { id: route.domain.id });
+                            }}>
+                                {get('pages.domain.allowed_hosts.confirm')}
+                            </TaskButton>
+                        </div>
+                    )}
                     <Domain id={route.domain.id} large />
                     <DomainScript id={route.domain.id} />
 
@@ -131,31 +146,73 @@ function DeleteDialog({
     );
 }
 
-function AddDomainButton({ id }: { id: string }) {
-    return connect(DOMAINS_LIST_USER, view => {
-        const list = view.get();
-        let run = async () => {};
-        let contents: any = <Progress />;
-        if (list) {
-            if (list.includes(id)) {
-                run = async () => {
-                    await load(DOMAIN_UNSUBSCRIBE, { id });
-                };
-                contents = get('pages.domain.unsubscribe');
-            } else {
-                run = async () => {
-                    await load(DOMAIN_SUBSCRIBE, { id });
-                };
-                contents = get('pages.domain.subscribe');
+function AllowedHosts({ hosts }: { hosts?: string[] | null }) {
+    if (!hosts) {
+        return <div class="domain-allowed-hosts is-any">{get('pages.domain.allowed_hosts.any')}</div>;
+    }
+    if (!hosts.length) {
+        return <div class="domain-allowed-hosts is-none">{get('pages.domain.allowed_hosts.none')}</div>;
+    }
+    return (
+        <ul class="domain-allowed-hosts">
+            {hosts.map(host => <li key={host}>{host}</li>)}
+        </ul>
+    );
+}
+
+class AddDomainButton extends PureComponent<{ id: string, allowedHosts?: string[] | null }> {
+    state = {
+        confirmOpen: false,
+    };
+
+    render({ id, allowedHosts }: { id: string, allowedHosts?: string[] | null }) {
+        return connect(DOMAINS_LIST_USER, view => {
+            const list = view.get();
+            let run = async () => {};
+            let contents: any = <Progress />;
+            if (list) {
+                if (list.includes(id)) {
+                    run = async () => {
+                        await load(DOMAIN_UNSUBSCRIBE, { id });
+                    };
+                    contents = get('pages.domain.unsubscribe');
+                } else {
+                    // show the hosts the script may access before subscribing
+                    run = async () => {
+                        this.setState({ confirmOpen: true });
+                    };
+                    contents = get('pages.domain.subscribe');
+                }
             }
-        }
 
-        return (
-            <TaskButton run={run}>
-                {contents}
-            </TaskButton>
-        );
-    });
+            const onClose = () => this.setState({ confirmOpen: false });
+
+            return (
+                <div class="domain-subscribe-button">
+                    <TaskButton run={run}>
+                        {contents}
+                    </TaskButton>
+                    <Dialog
+                        open={this.state.confirmOpen}
+                        onClose={onClose}
+                        destroy={<TaskButton run={async () => onClose()}>
+                            {get('pages.domain.allowed_hosts.cancel')}
+                        </TaskButton>}
+                        confirm={<TaskButton run={async () => {
+                            await load(DOMAIN_SUBSCRIBE, { id });
+                            onClose();
+                        }}>
+                            {get('pages.domain.subscribe')}
+                        </TaskButton>}>
+                        <DialogContents>
+                            <div>{get('pages.domain.allowed_hosts.description')}</div>
+                            <AllowedHosts hosts={allowedHosts} />
+                        </DialogContents>
+                    </Dialog>
+                </div>
+            );
+        });
+    }
 }
 
 function DomainEditor({ edit, onChange }: { edit: any, onChange: (e: any) => void }) {
@@ -0,0 +1 @@
+drop table domain_storage;
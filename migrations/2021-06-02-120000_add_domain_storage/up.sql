@@ -0,0 +1,7 @@
+create table domain_storage (
+    id integer primary key,
+    domain varchar not null,
+    storage_key varchar not null,
+    storage_value text not null,
+    unique (domain, storage_key)
+);
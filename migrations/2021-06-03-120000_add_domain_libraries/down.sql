@@ -0,0 +1,19 @@
+-- alter table source_domains drop column is_library;
+pragma foreign_keys=off;
+begin transaction;
+create table source_domains2 (
+    id integer primary key,
+    domain varchar not null unique,
+    abbrev varchar not null collate nocase,
+    name varchar not null collate nocase,
+    description text not null,
+    owner_id integer not null,
+    is_public boolean not null,
+    script text not null
+);
+insert into source_domains2(id, domain, abbrev, name, description, owner_id, is_public, script)
+select id, domain, abbrev, name, description, owner_id, is_public, script from source_domains;
+drop table source_domains;
+alter table source_domains2 rename to source_domains;
+commit;
+pragma foreign_keys=on;
@@ -0,0 +1 @@
+alter table source_domains add is_library boolean not null default false;
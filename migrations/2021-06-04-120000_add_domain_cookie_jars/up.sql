@@ -0,0 +1,5 @@
+create table domain_cookie_jars (
+    id integer primary key,
+    domain varchar not null unique,
+    cookies text not null
+);
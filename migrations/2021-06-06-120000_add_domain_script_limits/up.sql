@@ -0,0 +1,4 @@
+alter table source_domains add limit_exec_time integer;
+alter table source_domains add limit_request_timeout integer;
+alter table source_domains add limit_response_size integer;
+alter table source_domains add limit_heap_size integer;
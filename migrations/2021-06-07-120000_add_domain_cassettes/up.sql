@@ -0,0 +1,6 @@
+alter table source_domains add fetch_mode varchar not null default 'live';
+create table domain_cassettes (
+    id integer primary key,
+    domain varchar not null unique,
+    cassette text not null
+);
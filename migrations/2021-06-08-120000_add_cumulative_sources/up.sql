@@ -0,0 +1,10 @@
+alter table source_domains add cumulative boolean not null default false;
+alter table source_domains add cumulative_max_items integer;
+alter table source_domains add cumulative_max_age integer;
+create table cumulative_source_items (
+    id integer primary key,
+    source_uri varchar not null,
+    path varchar not null,
+    date_added varchar not null,
+    unique(source_uri, path)
+);
@@ -0,0 +1 @@
+drop table domain_url_patterns;
@@ -0,0 +1,5 @@
+create table domain_url_patterns (
+    id integer primary key,
+    domain varchar not null unique,
+    patterns text not null
+);
@@ -0,0 +1,17 @@
+-- alter table source_versions drop column schedule;
+pragma foreign_keys=off;
+begin transaction;
+create table source_versions2 (
+    id integer primary key,
+    uri varchar not null,
+    hash varchar not null unique,
+    metadata blob not null,
+    date_updated varchar,
+    items blob not null
+);
+insert into source_versions2(id, uri, hash, metadata, date_updated, items)
+select id, uri, hash, metadata, date_updated, items from source_versions;
+drop table source_versions;
+alter table source_versions2 rename to source_versions;
+commit;
+pragma foreign_keys=on;
@@ -0,0 +1 @@
+alter table source_versions add schedule blob;
@@ -0,0 +1,12 @@
+create table source_fetch_history (
+    id integer primary key,
+    source_uri varchar not null,
+    date varchar not null,
+    initiator_id integer,
+    success boolean not null,
+    duration integer not null,
+    version_hash varchar,
+    log blob not null
+);
+create index source_fetch_history_source_uri on source_fetch_history (source_uri);
+create index source_fetch_history_date on source_fetch_history (date);
@@ -0,0 +1,8 @@
+create table user_domain_secrets (
+    id integer primary key,
+    user_id integer not null,
+    domain varchar not null,
+    name varchar not null,
+    value blob not null,
+    unique(user_id, domain, name)
+);
@@ -0,0 +1,13 @@
+create table domain_options (
+    id integer primary key,
+    domain varchar not null unique,
+    options text not null
+);
+create table user_domain_options (
+    id integer primary key,
+    user_id integer not null,
+    domain varchar not null,
+    options text not null,
+    unique(user_id, domain)
+);
+alter table source_versions add options_hash varchar;
@@ -0,0 +1,28 @@
+-- alter table source_domains drop column allowed_hosts;
+pragma foreign_keys=off;
+begin transaction;
+create table source_domains2 (
+    id integer primary key,
+    domain varchar not null unique,
+    abbrev varchar not null collate nocase,
+    name varchar not null collate nocase,
+    description text not null,
+    owner_id integer not null,
+    is_public boolean not null,
+    script text not null,
+    is_library boolean not null default false,
+    limit_exec_time integer,
+    limit_request_timeout integer,
+    limit_response_size integer,
+    limit_heap_size integer,
+    fetch_mode varchar not null default 'live',
+    cumulative boolean not null default false,
+    cumulative_max_items integer,
+    cumulative_max_age integer
+);
+insert into source_domains2(id, domain, abbrev, name, description, owner_id, is_public, script, is_library, limit_exec_time, limit_request_timeout, limit_response_size, limit_heap_size, fetch_mode, cumulative, cumulative_max_items, cumulative_max_age)
+select id, domain, abbrev, name, description, owner_id, is_public, script, is_library, limit_exec_time, limit_request_timeout, limit_response_size, limit_heap_size, fetch_mode, cumulative, cumulative_max_items, cumulative_max_age from source_domains;
+drop table source_domains;
+alter table source_domains2 rename to source_domains;
+commit;
+pragma foreign_keys=on;
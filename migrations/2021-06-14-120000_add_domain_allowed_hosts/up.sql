@@ -0,0 +1 @@
+alter table source_domains add allowed_hosts text;
@@ -0,0 +1,20 @@
+drop table domain_script_revisions;
+-- alter table source_versions drop column script_revision;
+pragma foreign_keys=off;
+begin transaction;
+create table source_versions2 (
+    id integer primary key,
+    uri varchar not null,
+    hash varchar not null unique,
+    metadata blob not null,
+    date_updated varchar,
+    items blob not null,
+    schedule blob,
+    options_hash varchar
+);
+insert into source_versions2(id, uri, hash, metadata, date_updated, items, schedule, options_hash)
+select id, uri, hash, metadata, date_updated, items, schedule, options_hash from source_versions;
+drop table source_versions;
+alter table source_versions2 rename to source_versions;
+commit;
+pragma foreign_keys=on;
@@ -0,0 +1,13 @@
+create table domain_script_revisions (
+    id integer primary key,
+    domain varchar not null,
+    date varchar not null,
+    author_id integer not null,
+    message varchar not null,
+    script text not null
+);
+create index domain_script_revisions_domain on domain_script_revisions (domain);
+-- existing scripts become the first revision of their domain
+insert into domain_script_revisions (domain, date, author_id, message, script)
+select domain, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), owner_id, '', script from source_domains;
+alter table source_versions add script_revision integer;
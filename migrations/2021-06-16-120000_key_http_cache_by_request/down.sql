@@ -0,0 +1,8 @@
+drop table http_cache;
+create table http_cache (
+    id integer primary key,
+    url varchar not null unique,
+    response blob not null,
+    date_stored varchar not null
+);
+create index http_cache_date_stored on http_cache (date_stored);
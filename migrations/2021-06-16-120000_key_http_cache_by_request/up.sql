@@ -0,0 +1,12 @@
+-- cached responses are now keyed by domain and request headers, so existing ones can't be used
+drop table http_cache;
+create table http_cache (
+    id integer primary key,
+    domain varchar not null,
+    cache_key varchar not null,
+    response blob not null,
+    size integer not null,
+    date_stored varchar not null,
+    unique (domain, cache_key)
+);
+create index http_cache_date_stored on http_cache (date_stored);
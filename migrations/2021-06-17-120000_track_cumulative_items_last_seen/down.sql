@@ -0,0 +1,11 @@
+create table cumulative_source_items_old (
+    id integer primary key,
+    source_uri varchar not null,
+    path varchar not null,
+    date_added varchar not null,
+    unique(source_uri, path)
+);
+insert into cumulative_source_items_old (id, source_uri, path, date_added)
+select id, source_uri, path, date_added from cumulative_source_items;
+drop table cumulative_source_items;
+alter table cumulative_source_items_old rename to cumulative_source_items;
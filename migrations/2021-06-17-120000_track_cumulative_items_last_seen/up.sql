@@ -0,0 +1,4 @@
+alter table cumulative_source_items add last_seen varchar not null default '';
+update cumulative_source_items set last_seen = date_added;
+-- items that are still part of the source are marked with the newest generation
+alter table cumulative_source_items add generation integer not null default 0;
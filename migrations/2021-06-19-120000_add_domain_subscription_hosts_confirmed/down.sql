@@ -0,0 +1,15 @@
+-- alter table user_source_domain_subscriptions drop column allowed_hosts_confirmed;
+pragma foreign_keys=off;
+begin transaction;
+create table user_source_domain_subscriptions2 (
+    id integer primary key,
+    user_id integer not null,
+    domain varchar not null,
+    unique (user_id, domain)
+);
+insert into user_source_domain_subscriptions2(id, user_id, domain)
+select id, user_id, domain from user_source_domain_subscriptions;
+drop table user_source_domain_subscriptions;
+alter table user_source_domain_subscriptions2 rename to user_source_domain_subscriptions;
+commit;
+pragma foreign_keys=on;
@@ -0,0 +1 @@
+alter table user_source_domain_subscriptions add allowed_hosts_confirmed boolean not null default 1;
@@ -0,0 +1,16 @@
+-- alter table user_source_domain_subscriptions drop column confirmed_allowed_hosts;
+pragma foreign_keys=off;
+begin transaction;
+create table user_source_domain_subscriptions2 (
+    id integer primary key,
+    user_id integer not null,
+    domain varchar not null,
+    allowed_hosts_confirmed boolean not null default 1,
+    unique (user_id, domain)
+);
+insert into user_source_domain_subscriptions2(id, user_id, domain, allowed_hosts_confirmed)
+select id, user_id, domain, allowed_hosts_confirmed from user_source_domain_subscriptions;
+drop table user_source_domain_subscriptions;
+alter table user_source_domain_subscriptions2 rename to user_source_domain_subscriptions;
+commit;
+pragma foreign_keys=on;
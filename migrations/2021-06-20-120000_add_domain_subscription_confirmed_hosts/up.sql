@@ -0,0 +1 @@
+alter table user_source_domain_subscriptions add confirmed_allowed_hosts text;
@@ -0,0 +1,16 @@
+-- alter table source_item_versions drop column secrets_user_id;
+pragma foreign_keys=off;
+begin transaction;
+create table source_item_versions2 (
+    id integer primary key,
+    uri varchar not null,
+    hash varchar not null unique,
+    date_updated varchar,
+    data blob not null
+);
+insert into source_item_versions2(id, uri, hash, date_updated, data)
+select id, uri, hash, date_updated, data from source_item_versions;
+drop table source_item_versions;
+alter table source_item_versions2 rename to source_item_versions;
+commit;
+pragma foreign_keys=on;
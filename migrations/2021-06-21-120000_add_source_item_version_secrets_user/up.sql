@@ -0,0 +1 @@
+alter table source_item_versions add secrets_user_id integer;
@@ -0,0 +1,21 @@
+-- alter table source_fetch_history drop column read_secrets;
+pragma foreign_keys=off;
+begin transaction;
+create table source_fetch_history2 (
+    id integer primary key,
+    source_uri varchar not null,
+    date varchar not null,
+    initiator_id integer,
+    success boolean not null,
+    duration integer not null,
+    version_hash varchar,
+    log blob not null
+);
+insert into source_fetch_history2(id, source_uri, date, initiator_id, success, duration, version_hash, log)
+select id, source_uri, date, initiator_id, success, duration, version_hash, log from source_fetch_history;
+drop table source_fetch_history;
+alter table source_fetch_history2 rename to source_fetch_history;
+create index source_fetch_history_source_uri on source_fetch_history (source_uri);
+create index source_fetch_history_date on source_fetch_history (date);
+commit;
+pragma foreign_keys=on;
@@ -0,0 +1 @@
+alter table source_fetch_history add read_secrets boolean not null default 0;
@@ -1,10 +1,11 @@
 use crate::config::Config;
-use crate::data::sources::canonicalize_uri;
+use crate::data::sources::{canonicalize_uri, SourceSchedule};
 use crate::data::DataError;
 use crate::fetcher::Fetcher;
 use crate::state::{SharedData, State};
 use aof_script::url::Url;
-use chrono::{DateTime, NaiveDate, Utc};
+use aof_script::{parse_schedule_time, WEEKDAYS};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -14,6 +15,9 @@ use std::time::Duration;
 
 const PHASE_OFFSET_TIME: Duration = Duration::from_secs(3);
 
+/// How long after a scheduled update time a source is considered due for fetching, in hours.
+const RELEASE_WINDOW_HOURS: i64 = 6;
+
 fn get_cycle_sleep() -> Duration {
     Duration::from_secs(
         Config::shared()
@@ -94,8 +98,13 @@ pub fn start(state: Arc<State>) {
 /// Contains a weight for when a source last updated to predict when it will next update.
 /// The number ranges from 0 to 65535 where 0 is least and 65535 most recent.
 /// The enum variants indicate order of magnitude.
+///
+/// Sources whose script provides scheduling hints are instead `Completed` if they will not update
+/// anymore, `Scheduled` if their next update is not due yet, or `Hour(0xffff)` if it is.
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 enum UpdateProjection {
+    Completed,
+    Scheduled,
     Week(u16),
     Day(u16),
     Hour(u16),
@@ -107,6 +116,8 @@ impl UpdateProjection {
             UpdateProjection::Hour(_) => 1.,
             UpdateProjection::Day(k) => (1. - *k as f64 / 65535. / 7.).max(0.4),
             UpdateProjection::Week(k) => (0.4 - *k as f64 / 65535. / 7.).max(0.07),
+            UpdateProjection::Scheduled => 0.1,
+            UpdateProjection::Completed => 0.02,
         }
     }
 }
@@ -307,11 +318,23 @@ impl AutoFetcher {
 
 fn get_item_up(state: &SharedData, source: &str) -> Result<UpdateProjection, DataError> {
     let data = state.lock();
-    let date_updated = data
+    let version = data
         .latest_user_source_version(&source)?
         .map(|hash| data.source_by_hash(&hash))
         .transpose()?
+        .flatten();
+
+    let schedule = version
+        .as_ref()
+        .map(|version| version.schedule().ok())
         .flatten()
+        .flatten();
+    if let Some(up) = schedule.as_ref().map(get_schedule_up).flatten() {
+        return Ok(up);
+    }
+
+    let date_updated = version
+        .as_ref()
         .map(|source| source.date_updated().map(|s| s.to_string()))
         .flatten()
         .map(|s| parse_date(&s))
@@ -353,6 +376,60 @@ fn get_item_up(state: &SharedData, source: &str) -> Result<UpdateProjection, Dat
     Ok(up)
 }
 
+/// Returns the update projection for a source from the script's scheduling hints, or None if
+/// they are not useful (e.g. the expected update time has long passed).
+fn get_schedule_up(schedule: &SourceSchedule) -> Option<UpdateProjection> {
+    if schedule.completed {
+        return Some(UpdateProjection::Completed);
+    }
+
+    let now = Utc::now();
+    let window = chrono::Duration::hours(RELEASE_WINDOW_HOURS);
+    let next_update = schedule
+        .next_update
+        .as_ref()
+        .map(|date| DateTime::parse_from_rfc3339(date).ok())
+        .flatten()
+        .map(|date| date.with_timezone(&Utc));
+
+    if let Some(next_update) = next_update {
+        if next_update > now {
+            return Some(UpdateProjection::Scheduled);
+        } else if now - next_update < window {
+            return Some(UpdateProjection::Hour(0xffff));
+        }
+    }
+
+    if schedule.update_schedule.is_empty() {
+        return None;
+    }
+    for entry in &schedule.update_schedule {
+        let weekday = match WEEKDAYS.iter().position(|day| *day == entry.weekday) {
+            Some(weekday) => weekday as i64,
+            None => continue,
+        };
+        // entries without a time may update at any time that day
+        let (hour, minute, window) = match entry.time.as_ref() {
+            Some(time) => match parse_schedule_time(time) {
+                Some((hour, minute)) => (hour, minute, window),
+                None => continue,
+            },
+            None => (0, 0, chrono::Duration::days(1) + window),
+        };
+
+        // most recent occurrence of this entry
+        let days_back = (now.weekday().num_days_from_monday() as i64 - weekday + 7) % 7;
+        let mut last = (now.date() - chrono::Duration::days(days_back)).and_hms(hour, minute, 0);
+        if last > now {
+            last = last - chrono::Duration::weeks(1);
+        }
+        if now - last < window {
+            return Some(UpdateProjection::Hour(0xffff));
+        }
+    }
+    Some(UpdateProjection::Scheduled)
+}
+
 enum Date {
     Date(NaiveDate),
     Time(DateTime<Utc>),
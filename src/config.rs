@@ -1,5 +1,7 @@
+use aof_script::NetworkPolicy;
 use lazy_static::lazy_static;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::sync::{RwLock, RwLockReadGuard};
@@ -17,6 +19,46 @@ pub struct AutoFetcherConfig {
     pub major_interval: u64,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    pub requests: u64,
+    pub interval: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(flatten)]
+    pub default: RateLimit,
+    #[serde(default)]
+    pub hosts: HashMap<String, RateLimit>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScriptLimitValues {
+    pub exec_time: u64,
+    pub request_timeout: u64,
+    pub response_size: u64,
+    pub heap_size: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ScriptLimitsConfig {
+    pub default: ScriptLimitValues,
+    pub max: Option<ScriptLimitValues>,
+}
+
+#[derive(Deserialize)]
+pub struct ScriptWorkerConfig {
+    pub count: usize,
+    pub max_jobs: usize,
+}
+
+#[derive(Deserialize)]
+pub struct FetchHistoryConfig {
+    pub max_entries: u64,
+    pub max_age: u64,
+}
+
 #[derive(Default, Deserialize)]
 pub struct Config {
     pub bind_addr: String,
@@ -24,6 +66,11 @@ pub struct Config {
     pub private_key: String,
     pub base_path: String,
     pub auto_fetcher: Option<AutoFetcherConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub script_limits: Option<ScriptLimitsConfig>,
+    pub script_workers: Option<ScriptWorkerConfig>,
+    pub fetch_history: Option<FetchHistoryConfig>,
+    pub network: Option<NetworkPolicy>,
 }
 
 #[derive(Debug, Error)]
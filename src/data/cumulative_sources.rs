@@ -0,0 +1,86 @@
+use super::{schema, Data, DataError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+impl Data {
+    /// Returns the dates at which items of a cumulative source were last returned by the script,
+    /// by item path.
+    pub fn cumulative_item_dates(
+        &self,
+        source_uri: &str,
+    ) -> Result<HashMap<String, DateTime<Utc>>, DataError> {
+        use schema::cumulative_source_items::dsl;
+
+        let items = dsl::cumulative_source_items
+            .filter(dsl::source_uri.eq(source_uri))
+            .select((dsl::path, dsl::last_seen))
+            .get_results::<(String, String)>(&self.conn)?;
+
+        Ok(items
+            .into_iter()
+            .filter_map(|(path, date)| {
+                let date = DateTime::parse_from_rfc3339(&date).ok()?;
+                Some((path, date.with_timezone(&Utc)))
+            })
+            .collect())
+    }
+
+    /// Sets the items of a cumulative source, given as (path, was returned by the script).
+    /// Items that are not in the list are forgotten, and items that were returned by the script
+    /// are marked as seen at the given date.
+    pub fn set_cumulative_items(
+        &self,
+        source_uri: &str,
+        items: &[(&str, bool)],
+        date: DateTime<Utc>,
+    ) -> Result<(), DataError> {
+        use schema::cumulative_source_items::dsl;
+
+        let date = date.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        self.conn.transaction::<_, DataError, _>(|| {
+            // items are marked with a new generation and the rest is deleted afterwards, since
+            // listing all paths in a single query could exceed the SQLite variable limit
+            let generation = dsl::cumulative_source_items
+                .filter(dsl::source_uri.eq(source_uri))
+                .select(diesel::dsl::max(dsl::generation))
+                .first::<Option<i32>>(&self.conn)?
+                .map_or(0, |generation| generation.wrapping_add(1));
+
+            for (path, was_returned) in items {
+                diesel::insert_or_ignore_into(dsl::cumulative_source_items)
+                    .values((
+                        dsl::source_uri.eq(source_uri),
+                        dsl::path.eq(path),
+                        dsl::date_added.eq(&date),
+                        dsl::last_seen.eq(&date),
+                        dsl::generation.eq(generation),
+                    ))
+                    .execute(&self.conn)?;
+
+                let item = dsl::cumulative_source_items
+                    .filter(dsl::source_uri.eq(source_uri))
+                    .filter(dsl::path.eq(path));
+                if *was_returned {
+                    diesel::update(item)
+                        .set((dsl::generation.eq(generation), dsl::last_seen.eq(&date)))
+                        .execute(&self.conn)?;
+                } else {
+                    diesel::update(item)
+                        .set(dsl::generation.eq(generation))
+                        .execute(&self.conn)?;
+                }
+            }
+
+            diesel::delete(
+                dsl::cumulative_source_items
+                    .filter(dsl::source_uri.eq(source_uri))
+                    .filter(dsl::generation.ne(generation)),
+            )
+            .execute(&self.conn)?;
+
+            Ok(())
+        })
+    }
+}
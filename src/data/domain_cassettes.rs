@@ -0,0 +1,49 @@
+use super::{schema, Data, DataError};
+use diesel::prelude::*;
+
+/// Max len of a (JSON-encoded) cassette in bytes.
+///
+/// This is a bit less than the max protocol message size, so that cassettes can always be
+/// downloaded and uploaded by clients.
+pub const CASSETTE_MAX_LEN: usize = 1_000_000;
+
+impl Data {
+    /// Returns the JSON-encoded cassette of a domain.
+    pub fn domain_cassette(&self, domain: &str) -> Result<Option<String>, DataError> {
+        use schema::domain_cassettes::dsl;
+
+        let res = dsl::domain_cassettes
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::cassette)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Replaces the JSON-encoded cassette of a domain.
+    ///
+    /// Returns false if the cassette is too large to be stored.
+    pub fn domain_cassette_set(&self, domain: &str, cassette: &str) -> Result<bool, DataError> {
+        use schema::domain_cassettes::dsl;
+
+        if cassette.len() > CASSETTE_MAX_LEN {
+            return Ok(false);
+        }
+
+        diesel::replace_into(dsl::domain_cassettes)
+            .values((dsl::domain.eq(domain), dsl::cassette.eq(cassette)))
+            .execute(&self.conn)?;
+
+        Ok(true)
+    }
+
+    /// Deletes the cassette of a domain.
+    pub fn domain_cassette_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_cassettes::dsl;
+
+        diesel::delete(dsl::domain_cassettes.filter(dsl::domain.eq(domain))).execute(&self.conn)?;
+
+        Ok(())
+    }
+}
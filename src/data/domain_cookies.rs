@@ -0,0 +1,43 @@
+use super::{schema, Data, DataError};
+use diesel::prelude::*;
+
+/// Max len of a (JSON-encoded) cookie jar in bytes.
+pub const COOKIE_JAR_MAX_LEN: usize = 1_048_576;
+
+impl Data {
+    /// Returns the JSON-encoded cookie jar of a domain.
+    pub fn domain_cookie_jar(&self, domain: &str) -> Result<Option<String>, DataError> {
+        use schema::domain_cookie_jars::dsl;
+
+        let res = dsl::domain_cookie_jars
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::cookies)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Replaces the JSON-encoded cookie jar of a domain.
+    ///
+    /// The jar should be at most [COOKIE_JAR_MAX_LEN] bytes long.
+    pub fn domain_cookie_jar_set(&self, domain: &str, cookies: &str) -> Result<(), DataError> {
+        use schema::domain_cookie_jars::dsl;
+
+        diesel::replace_into(dsl::domain_cookie_jars)
+            .values((dsl::domain.eq(domain), dsl::cookies.eq(cookies)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes all cookies of a domain.
+    pub fn domain_cookie_jar_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_cookie_jars::dsl;
+
+        diesel::delete(dsl::domain_cookie_jars.filter(dsl::domain.eq(domain)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use super::{schema, Data, DataError};
+use crate::data::users::UserId;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+
+/// Max number of options a domain may declare.
+pub const OPTIONS_MAX: usize = 32;
+
+/// Max len of an option name in bytes.
+pub const OPTION_NAME_MAX_LEN: usize = 64;
+
+/// Max len of a string option value in bytes.
+pub const OPTION_VALUE_MAX_LEN: usize = 1024;
+
+/// Option values by name, passed to scripts as an object.
+///
+/// This is a BTreeMap so that the encoding (and hence the hash) of equal values is always the same.
+pub type DomainOptionValues = BTreeMap<String, Value>;
+
+/// Type of a domain option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DomainOptionType {
+    #[serde(rename = "bool")]
+    Bool,
+    #[serde(rename = "number")]
+    Number,
+    #[serde(rename = "string")]
+    String,
+    /// One of a fixed set of strings.
+    #[serde(rename = "choice")]
+    Choice,
+}
+
+/// An option declared by a domain, which users may set for themselves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainOption {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub option_type: DomainOptionType,
+    pub default: Value,
+    /// Allowed values of a `choice` option.
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+impl DomainOption {
+    /// Returns true if the value is valid for this option.
+    fn accepts(&self, value: &Value) -> bool {
+        match (self.option_type, value) {
+            (DomainOptionType::Bool, Value::Bool(_)) => true,
+            (DomainOptionType::Number, Value::Number(_)) => true,
+            (DomainOptionType::String, Value::String(s)) => s.len() <= OPTION_VALUE_MAX_LEN,
+            (DomainOptionType::Choice, Value::String(s)) => self.choices.contains(s),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DomainOptionsError {
+    #[error("too many options")]
+    TooMany,
+    #[error("invalid option name")]
+    InvalidName,
+    #[error("duplicate option name")]
+    DuplicateName,
+    #[error("invalid default value")]
+    InvalidDefault,
+    #[error("invalid option value")]
+    InvalidValue,
+    #[error("no such option")]
+    UnknownOption,
+    #[error(transparent)]
+    Data(#[from] DataError),
+}
+
+/// Returns the hash of a set of option values, which is used to tell apart source versions
+/// fetched with different options.
+///
+/// Returns None if there are no options, so that domains without options are unaffected.
+pub fn options_hash(values: &DomainOptionValues) -> Option<String> {
+    if values.is_empty() {
+        return None;
+    }
+    let encoded = serde_json::to_string(values).expect("failed to encode option values");
+    Some(hex::encode(Sha256::digest(encoded.as_bytes())))
+}
+
+fn validate_options(options: &[DomainOption]) -> Result<(), DomainOptionsError> {
+    if options.len() > OPTIONS_MAX {
+        return Err(DomainOptionsError::TooMany);
+    }
+    let mut names = HashSet::new();
+    for option in options {
+        if option.name.is_empty() || option.name.len() > OPTION_NAME_MAX_LEN {
+            return Err(DomainOptionsError::InvalidName);
+        }
+        if !names.insert(&option.name) {
+            return Err(DomainOptionsError::DuplicateName);
+        }
+        if !option.accepts(&option.default) {
+            return Err(DomainOptionsError::InvalidDefault);
+        }
+    }
+    Ok(())
+}
+
+impl Data {
+    /// Returns the options declared by a domain.
+    pub fn domain_options(&self, domain: &str) -> Result<Vec<DomainOption>, DataError> {
+        use schema::domain_options::dsl;
+
+        let res = dsl::domain_options
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::options)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(match res {
+            Some(options) => serde_json::from_str(&options).unwrap_or_else(|err| {
+                error!("Failed to decode options of domain {}: {}", domain, err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        })
+    }
+
+    /// Sets the options declared by a domain.
+    ///
+    /// Values users have stored for options that no longer exist are kept, but ignored.
+    pub fn domain_options_set(
+        &self,
+        domain: &str,
+        options: &[DomainOption],
+    ) -> Result<(), DomainOptionsError> {
+        use schema::domain_options::dsl;
+
+        validate_options(options)?;
+
+        if options.is_empty() {
+            self.domain_options_clear(domain)?;
+            return Ok(());
+        }
+
+        let encoded = serde_json::to_string(options).expect("failed to encode domain options");
+        diesel::replace_into(dsl::domain_options)
+            .values((dsl::domain.eq(domain), dsl::options.eq(encoded)))
+            .execute(&self.conn)
+            .map_err(DataError::from)?;
+
+        Ok(())
+    }
+
+    /// Deletes the options of a domain.
+    pub fn domain_options_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_options::dsl;
+
+        diesel::delete(dsl::domain_options.filter(dsl::domain.eq(domain))).execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Returns the option values a user has stored for a domain, which may include values for
+    /// options that no longer exist.
+    pub fn user_domain_option_values(
+        &self,
+        user_id: UserId,
+        domain: &str,
+    ) -> Result<DomainOptionValues, DataError> {
+        use schema::user_domain_options::dsl;
+
+        let res = dsl::user_domain_options
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::options)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(match res {
+            Some(values) => serde_json::from_str(&values).unwrap_or_else(|err| {
+                error!(
+                    "Failed to decode options of user {} for domain {}: {}",
+                    user_id, domain, err
+                );
+                DomainOptionValues::new()
+            }),
+            None => DomainOptionValues::new(),
+        })
+    }
+
+    /// Sets the option values of a user for a domain. Options that aren't given use their default
+    /// value.
+    pub fn user_domain_option_values_set(
+        &self,
+        user_id: UserId,
+        domain: &str,
+        values: &DomainOptionValues,
+    ) -> Result<(), DomainOptionsError> {
+        use schema::user_domain_options::dsl;
+
+        let options = self.domain_options(domain)?;
+        for (name, value) in values {
+            match options.iter().find(|option| &option.name == name) {
+                Some(option) if option.accepts(value) => (),
+                Some(_) => return Err(DomainOptionsError::InvalidValue),
+                None => return Err(DomainOptionsError::UnknownOption),
+            }
+        }
+
+        if values.is_empty() {
+            diesel::delete(
+                dsl::user_domain_options
+                    .filter(dsl::user_id.eq(user_id))
+                    .filter(dsl::domain.eq(domain)),
+            )
+            .execute(&self.conn)
+            .map_err(DataError::from)?;
+            return Ok(());
+        }
+
+        let encoded = serde_json::to_string(values).expect("failed to encode option values");
+        diesel::replace_into(dsl::user_domain_options)
+            .values((
+                dsl::user_id.eq(user_id),
+                dsl::domain.eq(domain),
+                dsl::options.eq(encoded),
+            ))
+            .execute(&self.conn)
+            .map_err(DataError::from)?;
+
+        Ok(())
+    }
+
+    /// Returns the option values that scripts should be given when fetching for a user, or the
+    /// defaults if there is no user.
+    ///
+    /// Stored values that are no longer valid (because the domain's options changed) are replaced
+    /// with the default value.
+    pub fn effective_domain_options(
+        &self,
+        user_id: Option<UserId>,
+        domain: &str,
+    ) -> Result<DomainOptionValues, DataError> {
+        let options = self.domain_options(domain)?;
+        if options.is_empty() {
+            return Ok(DomainOptionValues::new());
+        }
+        let mut user_values = match user_id {
+            Some(user_id) => self.user_domain_option_values(user_id, domain)?,
+            None => DomainOptionValues::new(),
+        };
+
+        let mut values = DomainOptionValues::new();
+        for option in options {
+            let value = match user_values.remove(&option.name) {
+                Some(value) if option.accepts(&value) => value,
+                _ => option.default,
+            };
+            values.insert(option.name, value);
+        }
+        Ok(values)
+    }
+
+    /// Deletes all users' option values for a domain.
+    pub fn domain_option_values_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::user_domain_options::dsl;
+
+        diesel::delete(dsl::user_domain_options.filter(dsl::domain.eq(domain)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes all option values of a user.
+    pub fn user_option_values_clear(&self, user_id: UserId) -> Result<(), DataError> {
+        use schema::user_domain_options::dsl;
+
+        diesel::delete(dsl::user_domain_options.filter(dsl::user_id.eq(user_id)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+}
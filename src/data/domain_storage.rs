@@ -0,0 +1,113 @@
+use super::{schema, Data, DataError};
+use diesel::prelude::*;
+use thiserror::Error;
+
+/// Max len of a storage key in bytes.
+pub const STORAGE_KEY_MAX_LEN: usize = 256;
+
+/// Max len of a single (JSON-encoded) storage value in bytes.
+pub const STORAGE_VALUE_MAX_LEN: usize = 65_536;
+
+/// Max total size of all keys and values of a single domain in bytes.
+pub const STORAGE_DOMAIN_MAX_SIZE: usize = 1_048_576;
+
+#[derive(Debug, Error)]
+pub enum DomainStorageError {
+    #[error("key is empty")]
+    KeyEmpty,
+    #[error("key is too long")]
+    KeyTooLong,
+    #[error("value is too large")]
+    ValueTooLarge,
+    #[error("domain storage quota exceeded")]
+    QuotaExceeded,
+    #[error(transparent)]
+    Data(#[from] DataError),
+}
+
+impl Data {
+    /// Returns a value from a domain's storage.
+    pub fn domain_storage_get(&self, domain: &str, key: &str) -> Result<Option<String>, DataError> {
+        use schema::domain_storage::dsl;
+
+        let res = dsl::domain_storage
+            .filter(dsl::domain.eq(domain))
+            .filter(dsl::storage_key.eq(key))
+            .select(dsl::storage_value)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Sets a value in a domain's storage, replacing any previous value.
+    pub fn domain_storage_set(
+        &self,
+        domain: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), DomainStorageError> {
+        use schema::domain_storage::dsl;
+
+        if key.is_empty() {
+            return Err(DomainStorageError::KeyEmpty);
+        }
+        if key.len() > STORAGE_KEY_MAX_LEN {
+            return Err(DomainStorageError::KeyTooLong);
+        }
+        if value.len() > STORAGE_VALUE_MAX_LEN {
+            return Err(DomainStorageError::ValueTooLarge);
+        }
+
+        let stored = self.conn.transaction::<_, DataError, _>(|| {
+            let others: Vec<(String, String)> = dsl::domain_storage
+                .filter(dsl::domain.eq(domain))
+                .filter(dsl::storage_key.ne(key))
+                .select((dsl::storage_key, dsl::storage_value))
+                .get_results(&self.conn)?;
+
+            let used: usize = others.iter().map(|(k, v)| k.len() + v.len()).sum();
+            if used + key.len() + value.len() > STORAGE_DOMAIN_MAX_SIZE {
+                return Ok(false);
+            }
+
+            diesel::replace_into(dsl::domain_storage)
+                .values((
+                    dsl::domain.eq(domain),
+                    dsl::storage_key.eq(key),
+                    dsl::storage_value.eq(value),
+                ))
+                .execute(&self.conn)?;
+
+            Ok(true)
+        })?;
+
+        if !stored {
+            return Err(DomainStorageError::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// Deletes a value from a domain's storage.
+    pub fn domain_storage_delete(&self, domain: &str, key: &str) -> Result<(), DataError> {
+        use schema::domain_storage::dsl;
+
+        diesel::delete(
+            dsl::domain_storage
+                .filter(dsl::domain.eq(domain))
+                .filter(dsl::storage_key.eq(key)),
+        )
+        .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes all values in a domain's storage.
+    pub fn domain_storage_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_storage::dsl;
+
+        diesel::delete(dsl::domain_storage.filter(dsl::domain.eq(domain))).execute(&self.conn)?;
+
+        Ok(())
+    }
+}
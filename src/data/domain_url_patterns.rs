@@ -0,0 +1,38 @@
+use super::{schema, Data, DataError};
+use diesel::prelude::*;
+
+impl Data {
+    /// Returns the cached JSON-encoded URL patterns of a domain.
+    pub fn domain_url_patterns(&self, domain: &str) -> Result<Option<String>, DataError> {
+        use schema::domain_url_patterns::dsl;
+
+        let res = dsl::domain_url_patterns
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::patterns)
+            .first::<String>(&self.conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Caches the JSON-encoded URL patterns of a domain.
+    pub fn domain_url_patterns_set(&self, domain: &str, patterns: &str) -> Result<(), DataError> {
+        use schema::domain_url_patterns::dsl;
+
+        diesel::replace_into(dsl::domain_url_patterns)
+            .values((dsl::domain.eq(domain), dsl::patterns.eq(patterns)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes the cached URL patterns of a domain, e.g. because its script changed.
+    pub fn domain_url_patterns_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_url_patterns::dsl;
+
+        diesel::delete(dsl::domain_url_patterns.filter(dsl::domain.eq(domain)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+}
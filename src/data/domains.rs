@@ -1,6 +1,12 @@
 use super::{models, schema, Data, DataError};
+use crate::data::script_revisions::REVISION_MESSAGE_MAX_LEN;
 use crate::data::users::UserId;
+use crate::session::protocol;
+use crate::session::users::{DispatchUserEvent, UserMgrDispatchEvent};
+use aof_script::FetchMode;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -42,6 +48,33 @@ fn gen_domain_id() -> String {
         .collect()
 }
 
+/// Script limits set by a domain. Limits that are None use the server default.
+///
+/// Times are in seconds and sizes are in MiB.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DomainScriptLimits {
+    #[serde(default)]
+    pub exec_time: Option<u64>,
+    #[serde(default)]
+    pub request_timeout: Option<u64>,
+    #[serde(default)]
+    pub response_size: Option<u64>,
+    #[serde(default)]
+    pub heap_size: Option<u64>,
+}
+
+/// Settings for domains whose sources accumulate items across fetches.
+///
+/// Items that are no longer returned by the script are kept, unless there are more than
+/// `max_items` items or they were added more than `max_age` seconds ago.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CumulativeSettings {
+    #[serde(default)]
+    pub max_items: Option<u64>,
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
 #[derive(Debug, Error)]
 pub enum UpdateDomainError {
     #[error("abbrev is too short")]
@@ -56,6 +89,8 @@ pub enum UpdateDomainError {
     DescriptionTooLong,
     #[error("script is too long")]
     ScriptTooLong,
+    #[error("revision message is too long")]
+    MessageTooLong,
     #[error(transparent)]
     Data(#[from] DataError),
 }
@@ -79,6 +114,23 @@ impl Data {
         Ok(res.map(DomainSnapshot::from))
     }
 
+    /// Returns a domain along with the id of its current script revision.
+    ///
+    /// Both are read in one transaction, so the revision always belongs to the returned script.
+    pub fn domain_by_domain_id_with_script_revision(
+        &self,
+        id: &str,
+    ) -> Result<Option<(DomainSnapshot, Option<i32>)>, DataError> {
+        self.conn.transaction::<_, DataError, _>(|| {
+            let domain = match self.domain_by_domain_id(id)? {
+                Some(domain) => domain,
+                None => return Ok(None),
+            };
+            let revision = self.current_script_revision(id)?;
+            Ok(Some((domain, revision)))
+        })
+    }
+
     /// Returns true if the given domain id is currently taken.
     fn is_domain_id_taken(&self, name: &str) -> Result<bool, DataError> {
         use schema::source_domains::dsl;
@@ -128,16 +180,29 @@ impl Data {
             owner_id: &owner_id,
             is_public: &false,
             script: DEFAULT_SCRIPT,
+            is_library: &false,
         };
-        diesel::insert_into(schema::source_domains::table)
-            .values(&domain)
-            .execute(&self.conn)
-            .map_err(DataError::from)?;
+        self.conn.transaction::<_, DataError, _>(|| {
+            diesel::insert_into(schema::source_domains::table)
+                .values(&domain)
+                .execute(&self.conn)?;
+            self.add_script_revision(&id, owner_id, "", DEFAULT_SCRIPT)?;
+            Ok(())
+        })?;
         Ok(id)
     }
 
     pub fn delete_domain(&self, domain: &DomainSnapshot) -> Result<(), DataError> {
         diesel::delete(&domain.inner).execute(&self.conn)?;
+        self.domain_storage_clear(domain.id())?;
+        self.domain_cookie_jar_clear(domain.id())?;
+        self.domain_cassette_clear(domain.id())?;
+        self.http_cache_clear(domain.id())?;
+        self.domain_url_patterns_clear(domain.id())?;
+        self.domain_secrets_clear(domain.id())?;
+        self.domain_options_clear(domain.id())?;
+        self.domain_option_values_clear(domain.id())?;
+        self.domain_script_revisions_clear(domain.id())?;
         Ok(())
     }
 
@@ -169,6 +234,85 @@ impl Data {
         Ok(())
     }
 
+    /// Returns false if the user is subscribed to the domain, but has not confirmed its allowed
+    /// hosts since they were widened.
+    pub fn user_domain_allowed_hosts_confirmed(
+        &self,
+        user_id: UserId,
+        domain: &DomainSnapshot,
+    ) -> Result<bool, DataError> {
+        use schema::user_source_domain_subscriptions::dsl;
+
+        let confirmed = dsl::user_source_domain_subscriptions
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain.id()))
+            .select(dsl::allowed_hosts_confirmed)
+            .first::<bool>(&self.conn)
+            .optional()?;
+        Ok(confirmed.unwrap_or(true))
+    }
+
+    /// Returns the host patterns the script may access when running for a user.
+    ///
+    /// If the user is subscribed to the domain but has not confirmed its allowed hosts since they
+    /// were widened, this only includes the current hosts that the user last confirmed.
+    pub fn user_domain_allowed_hosts(
+        &self,
+        user_id: UserId,
+        domain: &DomainSnapshot,
+    ) -> Result<Option<Vec<String>>, DataError> {
+        use schema::user_source_domain_subscriptions::dsl;
+
+        let confirmed = dsl::user_source_domain_subscriptions
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain.id()))
+            .filter(dsl::allowed_hosts_confirmed.eq(false))
+            .select(dsl::confirmed_allowed_hosts)
+            .first::<Option<String>>(&self.conn)
+            .optional()?
+            .flatten();
+        let confirmed = match confirmed {
+            Some(confirmed) => confirmed,
+            None => return Ok(domain.allowed_hosts()),
+        };
+
+        // deny everything rather than allow everything if the list is broken
+        let confirmed: Vec<String> = serde_json::from_str(&confirmed).unwrap_or_else(|err| {
+            error!(
+                "Failed to decode confirmed allowed hosts of domain {}: {}",
+                domain.id(),
+                err
+            );
+            Vec::new()
+        });
+        Ok(Some(match domain.allowed_hosts() {
+            Some(hosts) => confirmed
+                .into_iter()
+                .filter(|host| hosts.contains(host))
+                .collect(),
+            None => confirmed,
+        }))
+    }
+
+    /// Marks the allowed hosts of a domain as confirmed by a subscribed user.
+    pub fn user_confirm_domain_allowed_hosts(
+        &self,
+        user_id: UserId,
+        domain: &DomainSnapshot,
+    ) -> Result<(), DataError> {
+        use schema::user_source_domain_subscriptions::dsl;
+
+        diesel::update(dsl::user_source_domain_subscriptions)
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain.id()))
+            .set((
+                dsl::allowed_hosts_confirmed.eq(true),
+                dsl::confirmed_allowed_hosts.eq(None::<String>),
+            ))
+            .execute(&self.conn)?;
+        Ok(())
+    }
+
     pub fn user_unsubscribe_domain(
         &self,
         user_id: UserId,
@@ -241,7 +385,197 @@ impl DomainSnapshot {
     pub fn script(&self) -> &str {
         &self.inner.script
     }
+    pub fn is_library(&self) -> bool {
+        self.inner.is_library
+    }
+    pub fn script_limits(&self) -> DomainScriptLimits {
+        let value = |v: Option<i32>| v.map(|v| v.max(0) as u64);
+        DomainScriptLimits {
+            exec_time: value(self.inner.limit_exec_time),
+            request_timeout: value(self.inner.limit_request_timeout),
+            response_size: value(self.inner.limit_response_size),
+            heap_size: value(self.inner.limit_heap_size),
+        }
+    }
+
+    /// Sets the script limits. These should be validated beforehand.
+    pub fn set_script_limits(
+        &mut self,
+        data: &Data,
+        limits: DomainScriptLimits,
+    ) -> Result<(), DataError> {
+        use schema::source_domains::dsl;
+
+        let value = |v: Option<u64>| v.map(|v| i32::try_from(v).unwrap_or(i32::MAX));
+        let exec_time = value(limits.exec_time);
+        let request_timeout = value(limits.request_timeout);
+        let response_size = value(limits.response_size);
+        let heap_size = value(limits.heap_size);
+
+        diesel::update(schema::source_domains::table)
+            .filter(dsl::id.eq(self.inner.id))
+            .set((
+                dsl::limit_exec_time.eq(exec_time),
+                dsl::limit_request_timeout.eq(request_timeout),
+                dsl::limit_response_size.eq(response_size),
+                dsl::limit_heap_size.eq(heap_size),
+            ))
+            .execute(&data.conn)?;
+
+        self.inner.limit_exec_time = exec_time;
+        self.inner.limit_request_timeout = request_timeout;
+        self.inner.limit_response_size = response_size;
+        self.inner.limit_heap_size = heap_size;
+        Ok(())
+    }
+
+    /// Returns how fetch requests from the script are handled.
+    pub fn fetch_mode(&self) -> FetchMode {
+        match &*self.inner.fetch_mode {
+            "record" => FetchMode::Record,
+            "replay" => FetchMode::Replay,
+            _ => FetchMode::Live,
+        }
+    }
+
+    pub fn set_fetch_mode(&mut self, data: &Data, mode: FetchMode) -> Result<(), DataError> {
+        use schema::source_domains::dsl;
+
+        let mode = match mode {
+            FetchMode::Live => "live",
+            FetchMode::Record => "record",
+            FetchMode::Replay => "replay",
+        };
+
+        diesel::update(schema::source_domains::table)
+            .filter(dsl::id.eq(self.inner.id))
+            .set(dsl::fetch_mode.eq(mode))
+            .execute(&data.conn)?;
+
+        self.inner.fetch_mode = mode.into();
+        Ok(())
+    }
+
+    /// Returns the cumulative settings if sources of this domain are cumulative.
+    pub fn cumulative(&self) -> Option<CumulativeSettings> {
+        if !self.inner.cumulative {
+            return None;
+        }
+        let value = |v: Option<i32>| v.map(|v| v.max(0) as u64);
+        Some(CumulativeSettings {
+            max_items: value(self.inner.cumulative_max_items),
+            max_age: value(self.inner.cumulative_max_age),
+        })
+    }
+
+    /// Makes sources of this domain cumulative, or not if None.
+    pub fn set_cumulative(
+        &mut self,
+        data: &Data,
+        settings: Option<CumulativeSettings>,
+    ) -> Result<(), DataError> {
+        use schema::source_domains::dsl;
+
+        let value = |v: Option<u64>| v.map(|v| i32::try_from(v).unwrap_or(i32::MAX));
+        let cumulative = settings.is_some();
+        let settings = settings.unwrap_or_default();
+        let max_items = value(settings.max_items);
+        let max_age = value(settings.max_age);
+
+        diesel::update(schema::source_domains::table)
+            .filter(dsl::id.eq(self.inner.id))
+            .set((
+                dsl::cumulative.eq(cumulative),
+                dsl::cumulative_max_items.eq(max_items),
+                dsl::cumulative_max_age.eq(max_age),
+            ))
+            .execute(&data.conn)?;
+
+        self.inner.cumulative = cumulative;
+        self.inner.cumulative_max_items = max_items;
+        self.inner.cumulative_max_age = max_age;
+        Ok(())
+    }
+
+    /// Returns the host patterns the script may access, or None if it may access any host.
+    pub fn allowed_hosts(&self) -> Option<Vec<String>> {
+        self.inner.allowed_hosts.as_ref().map(|hosts| {
+            // deny everything rather than allow everything if the list is broken
+            serde_json::from_str(hosts).unwrap_or_else(|err| {
+                error!(
+                    "Failed to decode allowed hosts of domain {}: {}",
+                    self.id(),
+                    err
+                );
+                Vec::new()
+            })
+        })
+    }
 
+    /// Sets the host patterns the script may access, or allows any host if None.
+    ///
+    /// If this allows hosts that weren't allowed before, subscribed users will have to confirm the
+    /// allowed hosts again and are notified with a `subscribed_domain_did_update` event. Until
+    /// they do, scripts running for them may only access the hosts they confirmed before.
+    pub fn set_allowed_hosts(
+        &mut self,
+        data: &Data,
+        allowed_hosts: Option<Vec<String>>,
+    ) -> Result<(), DataError> {
+        use schema::source_domains::dsl;
+        use schema::user_source_domain_subscriptions::dsl as udsl;
+
+        let prev_hosts = self.allowed_hosts();
+        let is_widened = match (&prev_hosts, &allowed_hosts) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(prev), Some(hosts)) => hosts.iter().any(|host| !prev.contains(host)),
+        };
+        let prev_hosts_enc = prev_hosts
+            .map(|hosts| serde_json::to_string(&hosts).expect("failed to encode allowed hosts"));
+        let allowed_hosts_enc = allowed_hosts
+            .map(|hosts| serde_json::to_string(&hosts).expect("failed to encode allowed hosts"));
+
+        let subscribers = data.conn.transaction::<_, DataError, _>(|| {
+            diesel::update(schema::source_domains::table)
+                .filter(dsl::id.eq(self.inner.id))
+                .set(dsl::allowed_hosts.eq(&allowed_hosts_enc))
+                .execute(&data.conn)?;
+
+            if !is_widened {
+                return Ok(Vec::new());
+            }
+            let subscriptions =
+                udsl::user_source_domain_subscriptions.filter(udsl::domain.eq(self.id()));
+            // users who haven't confirmed the last change keep the hosts they confirmed before
+            diesel::update(subscriptions.filter(udsl::allowed_hosts_confirmed.eq(true)))
+                .set((
+                    udsl::allowed_hosts_confirmed.eq(false),
+                    udsl::confirmed_allowed_hosts.eq(&prev_hosts_enc),
+                ))
+                .execute(&data.conn)?;
+            Ok(subscriptions
+                .select(udsl::user_id)
+                .get_results::<UserId>(&data.conn)?)
+        })?;
+
+        self.inner.allowed_hosts = allowed_hosts_enc;
+
+        for user_id in subscribers {
+            data.users.do_send(UserMgrDispatchEvent(
+                user_id,
+                DispatchUserEvent::new(protocol::Event::SubscribedDomainDidUpdate {
+                    domain: self.id().to_string(),
+                }),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Updates the domain. If the script changed, it's recorded as a new script revision by the
+    /// given author with the given message.
+    ///
+    /// - `is_library`: if None, library status is left unchanged
     pub fn update(
         &mut self,
         data: &Data,
@@ -249,7 +583,10 @@ impl DomainSnapshot {
         name: String,
         description: String,
         is_public: bool,
+        is_library: Option<bool>,
         script: String,
+        author_id: UserId,
+        message: &str,
     ) -> Result<(), UpdateDomainError> {
         if abbrev.graphemes(true).count() < 1 {
             return Err(UpdateDomainError::AbbrevTooShort);
@@ -269,28 +606,74 @@ impl DomainSnapshot {
         if script.len() > SCRIPT_MAX_LEN {
             return Err(UpdateDomainError::ScriptTooLong);
         }
+        if message.graphemes(true).count() > REVISION_MESSAGE_MAX_LEN {
+            return Err(UpdateDomainError::MessageTooLong);
+        }
+
+        let is_library = is_library.unwrap_or(self.inner.is_library);
 
         use schema::source_domains::dsl;
 
-        diesel::update(schema::source_domains::table)
-            .filter(dsl::id.eq(self.inner.id))
-            .set((
-                dsl::abbrev.eq(&abbrev),
-                dsl::name.eq(&name),
-                dsl::description.eq(&description),
-                dsl::is_public.eq(&is_public),
-                dsl::script.eq(&script),
-            ))
-            .execute(&data.conn)
-            .map_err(DataError::from)?;
+        data.conn.transaction::<_, DataError, _>(|| {
+            diesel::update(schema::source_domains::table)
+                .filter(dsl::id.eq(self.inner.id))
+                .set((
+                    dsl::abbrev.eq(&abbrev),
+                    dsl::name.eq(&name),
+                    dsl::description.eq(&description),
+                    dsl::is_public.eq(&is_public),
+                    dsl::is_library.eq(&is_library),
+                    dsl::script.eq(&script),
+                ))
+                .execute(&data.conn)?;
+
+            if script != self.inner.script {
+                data.domain_url_patterns_clear(self.id())?;
+                data.add_script_revision(self.id(), author_id, message, &script)?;
+            }
+            Ok(())
+        })?;
 
         self.inner.abbrev = abbrev;
         self.inner.name = name;
         self.inner.description = description;
         self.inner.is_public = is_public;
+        self.inner.is_library = is_library;
         self.inner.script = script;
         Ok(())
     }
+
+    /// Restores the script of an earlier revision, which is recorded as a new revision.
+    /// Returns the id of the new revision, or None if the revision does not exist.
+    pub fn rollback_script(
+        &mut self,
+        data: &Data,
+        author_id: UserId,
+        revision: i32,
+    ) -> Result<Option<i32>, DataError> {
+        let revision = match data.script_revision(self.id(), revision)? {
+            Some(revision) => revision,
+            None => return Ok(None),
+        };
+
+        use schema::source_domains::dsl;
+
+        let message = format!("Roll back to revision {}", revision.id());
+        let id = data.conn.transaction::<_, DataError, _>(|| {
+            diesel::update(schema::source_domains::table)
+                .filter(dsl::id.eq(self.inner.id))
+                .set(dsl::script.eq(revision.script()))
+                .execute(&data.conn)?;
+
+            if revision.script() != self.inner.script {
+                data.domain_url_patterns_clear(self.id())?;
+            }
+            data.add_script_revision(self.id(), author_id, &message, revision.script())
+        })?;
+
+        self.inner.script = revision.script().to_string();
+        Ok(Some(id))
+    }
 }
 
 impl From<models::SourceDomain> for DomainSnapshot {
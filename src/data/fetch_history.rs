@@ -0,0 +1,171 @@
+use super::{models, schema, Data, DataError};
+use crate::data::users::UserId;
+use crate::fetcher::FetchMsg;
+use chrono::{DateTime, SecondsFormat, Utc};
+use diesel::prelude::*;
+use libflate::gzip;
+use std::io;
+use std::time::Duration;
+
+/// A fetch attempt to be recorded in the fetch history of a source.
+pub struct NewFetchHistoryEntry<'a> {
+    pub source_uri: &'a str,
+    pub date: DateTime<Utc>,
+    /// The user that requested the fetch, or None if it was the auto fetcher.
+    pub initiator: Option<UserId>,
+    pub success: bool,
+    /// Real time the script ran.
+    pub duration: Duration,
+    /// Hash of the resulting source version, if successful.
+    pub version_hash: Option<&'a str>,
+    pub log: &'a [FetchMsg],
+    /// Whether the script read secrets of the initiator.
+    pub read_secrets: bool,
+}
+
+impl Data {
+    /// Adds an entry to the fetch history of a source.
+    pub fn add_fetch_history_entry(&self, entry: NewFetchHistoryEntry) -> Result<(), DataError> {
+        use schema::source_fetch_history::dsl;
+
+        let mut log_enc = gzip::Encoder::new(Vec::new())?;
+        rmp_serde::encode::write(&mut log_enc, entry.log)?;
+        let log_enc = log_enc.finish().into_result()?;
+
+        let duration = entry.duration.as_millis().min(i32::MAX as u128) as i32;
+
+        diesel::insert_into(dsl::source_fetch_history)
+            .values((
+                dsl::source_uri.eq(entry.source_uri),
+                dsl::date.eq(entry.date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                dsl::initiator_id.eq(entry.initiator),
+                dsl::success.eq(entry.success),
+                dsl::duration.eq(duration),
+                dsl::version_hash.eq(entry.version_hash),
+                dsl::log.eq(log_enc),
+                dsl::read_secrets.eq(entry.read_secrets),
+            ))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Returns fetch history entries of a source, newest first.
+    ///
+    /// - `visible_to`: if set, only returns entries initiated by this user or the auto fetcher
+    /// - `before`: if set, only returns entries older than the entry with this id (for paging)
+    pub fn fetch_history(
+        &self,
+        source_uri: &str,
+        visible_to: Option<UserId>,
+        before: Option<i32>,
+        limit: u32,
+    ) -> Result<Vec<FetchHistoryEntrySnapshot>, DataError> {
+        use schema::source_fetch_history::dsl;
+
+        let mut query = dsl::source_fetch_history
+            .filter(dsl::source_uri.eq(source_uri))
+            .into_boxed();
+        if let Some(user_id) = visible_to {
+            query = query.filter(
+                dsl::initiator_id
+                    .is_null()
+                    .or(dsl::initiator_id.eq(user_id)),
+            );
+        }
+        if let Some(before) = before {
+            query = query.filter(dsl::id.lt(before));
+        }
+
+        let entries = query
+            .order(dsl::id.desc())
+            .limit(limit as i64)
+            .get_results::<models::SourceFetchHistoryEntry>(&self.conn)?;
+
+        Ok(entries
+            .into_iter()
+            .map(FetchHistoryEntrySnapshot::from)
+            .collect())
+    }
+
+    /// Deletes all but the newest `max_entries` fetch history entries of a source.
+    pub fn prune_fetch_history(&self, source_uri: &str, max_entries: u64) -> Result<(), DataError> {
+        use schema::source_fetch_history::dsl;
+
+        let oldest_kept = dsl::source_fetch_history
+            .filter(dsl::source_uri.eq(source_uri))
+            .order(dsl::id.desc())
+            .select(dsl::id)
+            .offset(max_entries.saturating_sub(1) as i64)
+            .first::<Option<i32>>(&self.conn)
+            .optional()?
+            .flatten();
+
+        if let Some(oldest_kept) = oldest_kept {
+            diesel::delete(
+                dsl::source_fetch_history
+                    .filter(dsl::source_uri.eq(source_uri))
+                    .filter(dsl::id.lt(oldest_kept)),
+            )
+            .execute(&self.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes all fetch history entries older than the given date.
+    pub fn delete_fetch_history_before(&self, date: DateTime<Utc>) -> Result<(), DataError> {
+        use schema::source_fetch_history::dsl;
+
+        let date = date.to_rfc3339_opts(SecondsFormat::Secs, true);
+        diesel::delete(dsl::source_fetch_history.filter(dsl::date.lt(date))).execute(&self.conn)?;
+
+        Ok(())
+    }
+}
+
+pub struct FetchHistoryEntrySnapshot {
+    inner: models::SourceFetchHistoryEntry,
+}
+
+impl FetchHistoryEntrySnapshot {
+    pub fn id(&self) -> i32 {
+        self.inner.id.expect("fetch history entry has no id")
+    }
+
+    pub fn date(&self) -> &str {
+        &self.inner.date
+    }
+
+    pub fn initiator(&self) -> Option<UserId> {
+        self.inner.initiator_id
+    }
+
+    pub fn success(&self) -> bool {
+        self.inner.success
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.inner.duration.max(0) as u64)
+    }
+
+    pub fn version_hash(&self) -> Option<&str> {
+        self.inner.version_hash.as_ref().map(|s| &**s)
+    }
+
+    pub fn log(&self) -> Result<Vec<FetchMsg>, DataError> {
+        let log_dec = gzip::Decoder::new(io::Cursor::new(&self.inner.log))?;
+        Ok(rmp_serde::decode::from_read(log_dec)?)
+    }
+
+    /// Returns whether the script read secrets of the initiator.
+    pub fn read_secrets(&self) -> bool {
+        self.inner.read_secrets
+    }
+}
+
+impl From<models::SourceFetchHistoryEntry> for FetchHistoryEntrySnapshot {
+    fn from(this: models::SourceFetchHistoryEntry) -> Self {
+        FetchHistoryEntrySnapshot { inner: this }
+    }
+}
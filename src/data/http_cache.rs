@@ -0,0 +1,98 @@
+use super::{schema, Data, DataError};
+use chrono::{Duration, SecondsFormat, Utc};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+/// Cached responses that have not been stored for this long will be evicted.
+const HTTP_CACHE_MAX_AGE_DAYS: i64 = 30;
+
+/// Max total size of all cached responses in bytes. Once exceeded, the oldest responses will be
+/// evicted.
+const HTTP_CACHE_MAX_SIZE: i64 = 256 * 1024 * 1024;
+
+/// Cache keys contain request headers, so they're stored hashed.
+fn hash_cache_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+impl Data {
+    /// Returns an encoded response from the HTTP cache of a domain.
+    pub fn http_cache_get(&self, domain: &str, key: &str) -> Result<Option<Vec<u8>>, DataError> {
+        use schema::http_cache::dsl;
+
+        let res = dsl::http_cache
+            .filter(dsl::domain.eq(domain))
+            .filter(dsl::cache_key.eq(hash_cache_key(key)))
+            .select(dsl::response)
+            .first::<Vec<u8>>(&self.conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Stores an encoded response in the HTTP cache of a domain, replacing any previous response
+    /// with the same key.
+    ///
+    /// Also evicts old responses, and the oldest responses if the cache has become too large.
+    pub fn http_cache_set(
+        &self,
+        domain: &str,
+        key: &str,
+        response: &[u8],
+    ) -> Result<(), DataError> {
+        use schema::http_cache::dsl;
+
+        let now = Utc::now();
+        let expired = now - Duration::days(HTTP_CACHE_MAX_AGE_DAYS);
+
+        self.conn.transaction::<_, DataError, _>(|| {
+            diesel::delete(
+                dsl::http_cache.filter(
+                    dsl::date_stored.lt(expired.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                ),
+            )
+            .execute(&self.conn)?;
+
+            diesel::replace_into(dsl::http_cache)
+                .values((
+                    dsl::domain.eq(domain),
+                    dsl::cache_key.eq(hash_cache_key(key)),
+                    dsl::response.eq(response),
+                    dsl::size.eq(response.len() as i32),
+                    dsl::date_stored.eq(now.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                ))
+                .execute(&self.conn)?;
+
+            let total_size = dsl::http_cache
+                .select(diesel::dsl::sum(dsl::size))
+                .first::<Option<i64>>(&self.conn)?
+                .unwrap_or(0);
+            if total_size > HTTP_CACHE_MAX_SIZE {
+                let mut excess = total_size - HTTP_CACHE_MAX_SIZE;
+                let responses = dsl::http_cache
+                    .select((dsl::id, dsl::size))
+                    .order(dsl::date_stored.asc())
+                    .get_results::<(Option<i32>, i32)>(&self.conn)?;
+
+                for (id, size) in responses {
+                    if excess <= 0 {
+                        break;
+                    }
+                    diesel::delete(dsl::http_cache.filter(dsl::id.eq(id))).execute(&self.conn)?;
+                    excess -= size as i64;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Deletes all cached responses of a domain.
+    pub fn http_cache_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::http_cache::dsl;
+
+        diesel::delete(dsl::http_cache.filter(dsl::domain.eq(domain))).execute(&self.conn)?;
+
+        Ok(())
+    }
+}
@@ -5,12 +5,22 @@ use diesel::sqlite::SqliteConnection;
 use std::io;
 use thiserror::Error;
 
+mod cumulative_sources;
+pub mod domain_cassettes;
+pub mod domain_cookies;
+pub mod domain_options;
+pub mod domain_storage;
+mod domain_url_patterns;
 pub mod domains;
+pub mod fetch_history;
+mod http_cache;
 mod models;
 mod registration;
 mod rss_auth_keys;
 mod schema;
+pub mod script_revisions;
 pub mod sources;
+pub mod user_secrets;
 pub mod users;
 
 /// Data interface.
@@ -35,6 +45,8 @@ pub enum DataError {
     Database(#[from] diesel::result::Error),
     #[error("data decode error: {0}")]
     Decode(#[from] rmp_serde::decode::Error),
+    #[error("data encode error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 }
@@ -50,6 +50,33 @@ pub struct SourceVersion {
     pub metadata: Vec<u8>,
     pub date_updated: Option<String>,
     pub items: Vec<u8>,
+    pub schedule: Option<Vec<u8>>,
+    pub options_hash: Option<String>,
+    pub script_revision: Option<i32>,
+    pub secrets_user_id: Option<i32>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct SourceFetchHistoryEntry {
+    pub id: Option<i32>,
+    pub source_uri: String,
+    pub date: String,
+    pub initiator_id: Option<i32>,
+    pub success: bool,
+    pub duration: i32,
+    pub version_hash: Option<String>,
+    pub log: Vec<u8>,
+    pub read_secrets: bool,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DomainScriptRevision {
+    pub id: Option<i32>,
+    pub domain: String,
+    pub date: String,
+    pub author_id: i32,
+    pub message: String,
+    pub script: String,
 }
 
 #[derive(Debug, Queryable)]
@@ -59,6 +86,7 @@ pub struct SourceItemVersion {
     pub hash: String,
     pub date_updated: Option<String>,
     pub data: Vec<u8>,
+    pub secrets_user_id: Option<i32>,
 }
 
 #[derive(Queryable)]
@@ -80,6 +108,16 @@ pub struct SourceDomain {
     pub owner_id: i32,
     pub is_public: bool,
     pub script: String,
+    pub is_library: bool,
+    pub limit_exec_time: Option<i32>,
+    pub limit_request_timeout: Option<i32>,
+    pub limit_response_size: Option<i32>,
+    pub limit_heap_size: Option<i32>,
+    pub fetch_mode: String,
+    pub cumulative: bool,
+    pub cumulative_max_items: Option<i32>,
+    pub cumulative_max_age: Option<i32>,
+    pub allowed_hosts: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -92,6 +130,7 @@ pub struct NewSourceDomain<'a> {
     pub owner_id: &'a i32,
     pub is_public: &'a bool,
     pub script: &'a str,
+    pub is_library: &'a bool,
 }
 
 #[derive(Debug, Clone, Queryable)]
@@ -1,3 +1,77 @@
+table! {
+    cumulative_source_items (id) {
+        id -> Nullable<Integer>,
+        source_uri -> Text,
+        path -> Text,
+        date_added -> Text,
+        last_seen -> Text,
+        generation -> Integer,
+    }
+}
+
+table! {
+    domain_cassettes (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        cassette -> Text,
+    }
+}
+
+table! {
+    domain_cookie_jars (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        cookies -> Text,
+    }
+}
+
+table! {
+    domain_options (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        options -> Text,
+    }
+}
+
+table! {
+    domain_script_revisions (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        date -> Text,
+        author_id -> Integer,
+        message -> Text,
+        script -> Text,
+    }
+}
+
+table! {
+    domain_storage (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        storage_key -> Text,
+        storage_value -> Text,
+    }
+}
+
+table! {
+    domain_url_patterns (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        patterns -> Text,
+    }
+}
+
+table! {
+    http_cache (id) {
+        id -> Nullable<Integer>,
+        domain -> Text,
+        cache_key -> Text,
+        response -> Binary,
+        size -> Integer,
+        date_stored -> Text,
+    }
+}
+
 table! {
     registration_tokens (id) {
         id -> Nullable<Integer>,
@@ -16,6 +90,30 @@ table! {
         owner_id -> Integer,
         is_public -> Bool,
         script -> Text,
+        is_library -> Bool,
+        limit_exec_time -> Nullable<Integer>,
+        limit_request_timeout -> Nullable<Integer>,
+        limit_response_size -> Nullable<Integer>,
+        limit_heap_size -> Nullable<Integer>,
+        fetch_mode -> Text,
+        cumulative -> Bool,
+        cumulative_max_items -> Nullable<Integer>,
+        cumulative_max_age -> Nullable<Integer>,
+        allowed_hosts -> Nullable<Text>,
+    }
+}
+
+table! {
+    source_fetch_history (id) {
+        id -> Nullable<Integer>,
+        source_uri -> Text,
+        date -> Text,
+        initiator_id -> Nullable<Integer>,
+        success -> Bool,
+        duration -> Integer,
+        version_hash -> Nullable<Text>,
+        log -> Binary,
+        read_secrets -> Bool,
     }
 }
 
@@ -34,6 +132,7 @@ table! {
         hash -> Text,
         date_updated -> Nullable<Text>,
         data -> Binary,
+        secrets_user_id -> Nullable<Integer>,
     }
 }
 
@@ -63,6 +162,29 @@ table! {
         metadata -> Binary,
         date_updated -> Nullable<Text>,
         items -> Binary,
+        schedule -> Nullable<Binary>,
+        options_hash -> Nullable<Text>,
+        script_revision -> Nullable<Integer>,
+        secrets_user_id -> Nullable<Integer>,
+    }
+}
+
+table! {
+    user_domain_options (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        domain -> Text,
+        options -> Text,
+    }
+}
+
+table! {
+    user_domain_secrets (id) {
+        id -> Nullable<Integer>,
+        user_id -> Integer,
+        domain -> Text,
+        name -> Text,
+        value -> Binary,
     }
 }
 
@@ -81,6 +203,8 @@ table! {
         id -> Nullable<Integer>,
         user_id -> Integer,
         domain -> Text,
+        allowed_hosts_confirmed -> Bool,
+        confirmed_allowed_hosts -> Nullable<Text>,
     }
 }
 
@@ -126,13 +250,24 @@ table! {
 }
 
 allow_tables_to_appear_in_same_query!(
+    cumulative_source_items,
+    domain_cassettes,
+    domain_cookie_jars,
+    domain_options,
+    domain_script_revisions,
+    domain_storage,
+    domain_url_patterns,
+    http_cache,
     registration_tokens,
     source_domains,
+    source_fetch_history,
     source_item_resource_dependencies,
     source_item_versions,
     source_resources,
     source_version_associated_items,
     source_versions,
+    user_domain_options,
+    user_domain_secrets,
     user_rss_auth_keys,
     user_source_domain_subscriptions,
     user_source_items,
@@ -0,0 +1,355 @@
+//! Revision history of domain scripts.
+
+use super::{models, schema, Data, DataError};
+use crate::data::users::UserId;
+use chrono::{SecondsFormat, Utc};
+use diesel::prelude::*;
+use std::fmt::Write;
+
+/// Max len of a revision message in graphemes.
+pub const REVISION_MESSAGE_MAX_LEN: usize = 1024;
+
+/// Max number of revisions kept per domain. Older revisions are deleted when a new one is added.
+pub const MAX_SCRIPT_REVISIONS: i64 = 500;
+
+/// Number of unchanged lines shown around changes in a diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Max number of changed lines for which a minimal diff will be computed. Beyond this, the changed
+/// part is shown as replaced entirely, which is still correct but less readable.
+const DIFF_MAX_EDITS: usize = 2000;
+
+/// A script revision without the script itself.
+pub struct ScriptRevisionInfo {
+    pub id: i32,
+    pub date: String,
+    pub author_id: UserId,
+    pub message: String,
+}
+
+impl Data {
+    /// Adds a revision to the script history of a domain and returns its id.
+    ///
+    /// Only the newest `MAX_SCRIPT_REVISIONS` revisions are kept.
+    pub fn add_script_revision(
+        &self,
+        domain: &str,
+        author_id: UserId,
+        message: &str,
+        script: &str,
+    ) -> Result<i32, DataError> {
+        use schema::domain_script_revisions::dsl;
+
+        diesel::insert_into(dsl::domain_script_revisions)
+            .values((
+                dsl::domain.eq(domain),
+                dsl::date.eq(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+                dsl::author_id.eq(author_id),
+                dsl::message.eq(message),
+                dsl::script.eq(script),
+            ))
+            .execute(&self.conn)?;
+
+        let id = dsl::domain_script_revisions
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::id)
+            .order(dsl::id.desc())
+            .first::<Option<i32>>(&self.conn)?
+            .expect("script revision has no id");
+
+        let oldest_kept = dsl::domain_script_revisions
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::id)
+            .order(dsl::id.desc())
+            .offset(MAX_SCRIPT_REVISIONS - 1)
+            .first::<Option<i32>>(&self.conn)
+            .optional()?
+            .flatten();
+        if let Some(oldest_kept) = oldest_kept {
+            diesel::delete(
+                dsl::domain_script_revisions
+                    .filter(dsl::domain.eq(domain))
+                    .filter(dsl::id.lt(oldest_kept)),
+            )
+            .execute(&self.conn)?;
+        }
+
+        Ok(id)
+    }
+
+    /// Returns script revisions of a domain, newest first.
+    ///
+    /// - `before`: if set, only returns revisions older than the revision with this id (for paging)
+    pub fn script_revisions(
+        &self,
+        domain: &str,
+        before: Option<i32>,
+        limit: u32,
+    ) -> Result<Vec<ScriptRevisionInfo>, DataError> {
+        use schema::domain_script_revisions::dsl;
+
+        let mut query = dsl::domain_script_revisions
+            .filter(dsl::domain.eq(domain))
+            .into_boxed();
+        if let Some(before) = before {
+            query = query.filter(dsl::id.lt(before));
+        }
+
+        let revisions = query
+            .order(dsl::id.desc())
+            .limit(limit as i64)
+            .select((dsl::id, dsl::date, dsl::author_id, dsl::message))
+            .get_results::<(Option<i32>, String, i32, String)>(&self.conn)?;
+
+        Ok(revisions
+            .into_iter()
+            .map(|(id, date, author_id, message)| ScriptRevisionInfo {
+                id: id.expect("script revision has no id"),
+                date,
+                author_id,
+                message,
+            })
+            .collect())
+    }
+
+    /// Returns a script revision of a domain.
+    pub fn script_revision(
+        &self,
+        domain: &str,
+        id: i32,
+    ) -> Result<Option<ScriptRevisionSnapshot>, DataError> {
+        use schema::domain_script_revisions::dsl;
+
+        let res = dsl::domain_script_revisions
+            .filter(dsl::domain.eq(domain))
+            .filter(dsl::id.eq(id))
+            .first::<models::DomainScriptRevision>(&self.conn)
+            .optional()?;
+        Ok(res.map(ScriptRevisionSnapshot::from))
+    }
+
+    /// Returns the id of the newest script revision of a domain, which is the current script.
+    pub fn current_script_revision(&self, domain: &str) -> Result<Option<i32>, DataError> {
+        use schema::domain_script_revisions::dsl;
+
+        Ok(dsl::domain_script_revisions
+            .filter(dsl::domain.eq(domain))
+            .select(dsl::id)
+            .order(dsl::id.desc())
+            .first::<Option<i32>>(&self.conn)
+            .optional()?
+            .flatten())
+    }
+
+    /// Deletes the script history of a domain.
+    pub fn domain_script_revisions_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::domain_script_revisions::dsl;
+
+        diesel::delete(dsl::domain_script_revisions.filter(dsl::domain.eq(domain)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+}
+
+pub struct ScriptRevisionSnapshot {
+    inner: models::DomainScriptRevision,
+}
+
+impl ScriptRevisionSnapshot {
+    pub fn id(&self) -> i32 {
+        self.inner.id.expect("script revision has no id")
+    }
+
+    pub fn date(&self) -> &str {
+        &self.inner.date
+    }
+
+    pub fn author_id(&self) -> UserId {
+        self.inner.author_id
+    }
+
+    pub fn message(&self) -> &str {
+        &self.inner.message
+    }
+
+    pub fn script(&self) -> &str {
+        &self.inner.script
+    }
+}
+
+impl From<models::DomainScriptRevision> for ScriptRevisionSnapshot {
+    fn from(this: models::DomainScriptRevision) -> Self {
+        ScriptRevisionSnapshot { inner: this }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Computes a line diff using Myers' algorithm. Returns None if more than `max_edits` lines
+/// would have to be deleted or inserted.
+fn diff_lines(a: &[&str], b: &[&str], max_edits: usize) -> Option<Vec<DiffOp>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = max_edits.min(a.len() + b.len()) as isize;
+    let offset = max_d + 1;
+    let mut v = vec![0isize; 2 * max_d as usize + 3];
+    // v[k - d - 1..=k + d + 1] before each step d, for backtracking
+    let mut trace = Vec::new();
+
+    for d in 0..=max_d {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: Vec<Vec<isize>>, n: isize, m: isize) -> Vec<DiffOp> {
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x {
+                DiffOp::Insert
+            } else {
+                DiffOp::Delete
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+/// Returns a unified diff between two scripts, which is empty if they are the same.
+pub fn diff_scripts(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let a: Vec<_> = old.lines().collect();
+    let b: Vec<_> = new.lines().collect();
+
+    let prefix = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops = vec![DiffOp::Equal; prefix];
+    match diff_lines(a_mid, b_mid, DIFF_MAX_EDITS) {
+        Some(mid) => ops.extend(mid),
+        None => {
+            ops.extend(a_mid.iter().map(|_| DiffOp::Delete));
+            ops.extend(b_mid.iter().map(|_| DiffOp::Insert));
+        }
+    }
+    ops.extend(vec![DiffOp::Equal; suffix]);
+
+    // line positions in a and b before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            DiffOp::Equal => {
+                i += 1;
+                j += 1;
+            }
+            DiffOp::Delete => i += 1,
+            DiffOp::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let changes: Vec<_> = (0..ops.len())
+        .filter(|&i| ops[i] != DiffOp::Equal)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    writeln!(out, "--- {}", old_label).unwrap();
+    writeln!(out, "+++ {}", new_label).unwrap();
+
+    let mut c = 0;
+    while c < changes.len() {
+        // extend the hunk while the next change is close enough to share context
+        let mut last = c;
+        while last + 1 < changes.len()
+            && changes[last + 1] - changes[last] <= 2 * DIFF_CONTEXT_LINES + 1
+        {
+            last += 1;
+        }
+        let start = changes[c].saturating_sub(DIFF_CONTEXT_LINES);
+        let end = (changes[last] + 1 + DIFF_CONTEXT_LINES).min(ops.len());
+
+        let (a_start, b_start) = positions[start];
+        let (a_end, b_end) = positions[end];
+        let range = |start: usize, len: usize| {
+            if len == 0 {
+                format!("{},0", start)
+            } else {
+                format!("{},{}", start + 1, len)
+            }
+        };
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(a_start, a_end - a_start),
+            range(b_start, b_end - b_start)
+        )
+        .unwrap();
+
+        for k in start..end {
+            let (i, j) = positions[k];
+            match ops[k] {
+                DiffOp::Equal => writeln!(out, " {}", a[i]),
+                DiffOp::Delete => writeln!(out, "-{}", a[i]),
+                DiffOp::Insert => writeln!(out, "+{}", b[j]),
+            }
+            .unwrap();
+        }
+
+        c = last + 1;
+    }
+
+    out
+}
@@ -90,13 +90,24 @@ impl Data {
 
     /// Creates a new source version and returns the hash.
     ///
-    /// Will do nothing if the hash already exists.
+    /// `options_hash` is the hash of the domain options the source was fetched with, if the
+    /// domain has options. `script_revision` is the id of the script revision that produced it.
+    /// `secrets_user_id` is the user whose secrets the script read while fetching, if any.
+    ///
+    /// Will do nothing if the hash already exists. Since the script revision is not part of the
+    /// hash, a version that is produced again by a later revision keeps the revision that first
+    /// produced it.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_source_version(
         &self,
         uri: &str,
         metadata: &SourceMetadata,
         items: &SourceItems,
         date_updated: Option<&str>,
+        schedule: &SourceSchedule,
+        options_hash: Option<&str>,
+        script_revision: Option<i32>,
+        secrets_user_id: Option<UserId>,
     ) -> Result<String, CreateVersionError> {
         use schema::source_versions::dsl;
 
@@ -108,10 +119,23 @@ impl Data {
         };
         let domain = parsed_url.scheme();
 
-        let hash = get_source_hash(metadata, items, date_updated)?;
+        let schedule = if schedule.is_empty() {
+            None
+        } else {
+            Some(schedule)
+        };
+        let hash = get_source_hash(
+            metadata,
+            items,
+            date_updated,
+            schedule,
+            options_hash,
+            secrets_user_id,
+        )?;
 
         let metadata_enc = rmp_serde::encode::to_vec(metadata)?;
         let items_enc = rmp_serde::encode::to_vec(items)?;
+        let schedule_enc = schedule.map(rmp_serde::encode::to_vec).transpose()?;
 
         let mut item_uris = Vec::new();
         for item in items {
@@ -130,6 +154,10 @@ impl Data {
                 dsl::metadata.eq(metadata_enc),
                 dsl::date_updated.eq(date_updated),
                 dsl::items.eq(items_enc),
+                dsl::schedule.eq(schedule_enc),
+                dsl::options_hash.eq(options_hash),
+                dsl::script_revision.eq(script_revision),
+                dsl::secrets_user_id.eq(secrets_user_id),
             ))
             .execute(&self.conn)
             .map_err(DataError::from)?;
@@ -155,7 +183,9 @@ impl Data {
         Ok(hash)
     }
 
-    /// Creates a new source version and returns the hash.
+    /// Creates a new source item version and returns the hash.
+    ///
+    /// `secrets_user_id` is the user whose secrets the script read while fetching, if any.
     ///
     /// Will do nothing if the hash already exists.
     pub fn create_source_item_version(
@@ -163,6 +193,7 @@ impl Data {
         uri: &str,
         contents: SourceItemData,
         date_updated: Option<&str>,
+        secrets_user_id: Option<UserId>,
     ) -> Result<String, CreateVersionError> {
         use schema::source_item_versions::dsl;
 
@@ -170,7 +201,7 @@ impl Data {
             return Err(CreateVersionError::InvalidUri);
         }
 
-        let hash = get_source_item_hash(&contents, date_updated)?;
+        let hash = get_source_item_hash(&contents, date_updated, secrets_user_id)?;
 
         let mut contents_enc = gzip::Encoder::new(Vec::new())?;
         rmp_serde::encode::write(&mut contents_enc, &contents)?;
@@ -182,6 +213,7 @@ impl Data {
                 dsl::hash.eq(&hash),
                 dsl::date_updated.eq(date_updated),
                 dsl::data.eq(contents_enc),
+                dsl::secrets_user_id.eq(secrets_user_id),
             ))
             .execute(&self.conn)
             .map_err(DataError::from)?;
@@ -189,6 +221,55 @@ impl Data {
         Ok(hash)
     }
 
+    pub fn source_resource_by_hash(
+        &self,
+        hash: &str,
+    ) -> Result<Option<SourceResourceSnapshot>, DataError> {
+        use schema::source_resources::dsl;
+        let res = dsl::source_resources
+            .filter(dsl::hash.eq(hash))
+            .first::<models::SourceResource>(&self.conn)
+            .optional()?;
+        Ok(res.map(SourceResourceSnapshot::from))
+    }
+
+    /// Stores a resource and adds it as a dependency of a source item version.
+    /// Returns the resource hash.
+    ///
+    /// Will not store the resource again if the hash already exists.
+    pub fn create_source_item_resource(
+        &self,
+        item_hash: &str,
+        resource: &SourceResourceData,
+    ) -> Result<String, CreateVersionError> {
+        use schema::source_item_resource_dependencies::dsl as sird;
+        use schema::source_resources::dsl as sr;
+
+        let hash = get_source_resource_hash(&resource.data);
+        let metadata_enc = rmp_serde::encode::to_vec(&resource.metadata)?;
+
+        self.conn.transaction::<_, DataError, _>(|| {
+            diesel::insert_or_ignore_into(sr::source_resources)
+                .values((
+                    sr::hash.eq(&hash),
+                    sr::metadata.eq(&metadata_enc),
+                    sr::data.eq(&resource.data),
+                ))
+                .execute(&self.conn)?;
+
+            diesel::insert_or_ignore_into(sird::source_item_resource_dependencies)
+                .values((
+                    sird::source_item_hash.eq(item_hash),
+                    sird::resource_hash.eq(&hash),
+                ))
+                .execute(&self.conn)?;
+
+            Ok(())
+        })?;
+
+        Ok(hash)
+    }
+
     pub fn user_source(
         &self,
         user_id: UserId,
@@ -513,10 +594,12 @@ impl Data {
     /// - Deletes source versions that are not referenced in any user sources
     /// - Deletes source item versions that are not referenced in any user source items
     /// - Deletes any user sources and user source items with no data
+    /// - Deletes item dates of cumulative sources that no longer exist
     ///
     /// This garbage collection does not need to be stop-the-world, since it is extremely unlikely
     /// that a source version would be recycled.
     pub fn garbage_collect_sources(&self) -> Result<(), DataError> {
+        use schema::cumulative_source_items::dsl as csi;
         use schema::source_item_resource_dependencies::dsl as sird;
         use schema::source_item_versions::dsl as siv;
         use schema::source_resources::dsl as sr;
@@ -573,6 +656,13 @@ impl Data {
         ))
         .execute(&self.conn)?;
 
+        // delete all cumulative item dates with no source
+        diesel::delete(
+            csi::cumulative_source_items
+                .filter(csi::source_uri.ne_all(sv::source_versions.select(sv::uri))),
+        )
+        .execute(&self.conn)?;
+
         Ok(())
     }
 
@@ -600,6 +690,46 @@ impl Data {
         Ok(hash.flatten())
     }
 
+    /// Like [Data::latest_user_source_version], but only considers versions that were fetched
+    /// with the given domain options, and that were either fetched without reading secrets or
+    /// using the secrets of the given user.
+    pub fn latest_user_source_version_with_options(
+        &self,
+        uri: &str,
+        options_hash: Option<&str>,
+        user_id: Option<UserId>,
+    ) -> Result<Option<String>, DataError> {
+        use schema::source_versions::dsl as sv;
+        use schema::user_sources::dsl;
+
+        let mut versions = sv::source_versions
+            .filter(sv::uri.eq(uri))
+            .select(sv::hash.nullable())
+            .into_boxed();
+        versions = match options_hash {
+            Some(options_hash) => versions.filter(sv::options_hash.eq(options_hash)),
+            None => versions.filter(sv::options_hash.is_null()),
+        };
+        versions = match user_id {
+            Some(user_id) => versions.filter(
+                sv::secrets_user_id
+                    .is_null()
+                    .or(sv::secrets_user_id.eq(user_id)),
+            ),
+            None => versions.filter(sv::secrets_user_id.is_null()),
+        };
+
+        let hash = dsl::user_sources
+            .filter(dsl::uri.eq(uri))
+            .filter(dsl::version_hash.eq_any(versions))
+            .order(dsl::version_date.desc())
+            .select(dsl::version_hash)
+            .first::<Option<String>>(&self.conn)
+            .optional()?;
+
+        Ok(hash.flatten())
+    }
+
     pub fn source_item_has_versionless_user(
         &self,
         source_uri: &str,
@@ -629,15 +759,27 @@ pub struct SourceMetadata {
     pub tags: BTreeMap<String, serde_json::Value>,
 }
 
+pub use aof_script::SourceMetaItem;
+
 pub type SourceItems = Vec<SourceMetaItem>;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct SourceMetaItem {
-    pub path: String,
-    #[serde(rename = "virtual", default)]
-    pub is_virtual: bool,
-    #[serde(default)]
-    pub tags: BTreeMap<String, serde_json::Value>,
+pub use aof_script::ScheduledUpdate;
+
+/// Hints from the domain script about when a source will update.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SourceSchedule {
+    /// When the source is expected to update next (RFC 3339).
+    pub next_update: Option<String>,
+    /// Times of the week at which the source usually updates.
+    pub update_schedule: Vec<ScheduledUpdate>,
+    /// If true, the source will not update anymore.
+    pub completed: bool,
+}
+
+impl SourceSchedule {
+    pub fn is_empty(&self) -> bool {
+        self.next_update.is_none() && self.update_schedule.is_empty() && !self.completed
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -645,15 +787,43 @@ pub struct SourceItemData {
     pub tags: BTreeMap<String, serde_json::Value>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceResourceMetadata {
+    /// The URL this resource was downloaded from.
+    pub url: String,
+    pub content_type: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SourceResourceData {
+    pub metadata: SourceResourceMetadata,
+    pub data: Vec<u8>,
+}
+
 fn get_source_hash(
     meta: &SourceMetadata,
     items: &SourceItems,
     date_updated: Option<&str>,
+    schedule: Option<&SourceSchedule>,
+    options_hash: Option<&str>,
+    secrets_user_id: Option<UserId>,
 ) -> Result<String, rmp_serde::encode::Error> {
     let mut hash = sha2::Sha512::default();
     rmp_serde::encode::write(&mut hash, meta)?;
     rmp_serde::encode::write(&mut hash, items)?;
     rmp_serde::encode::write(&mut hash, &date_updated)?;
+    // only hashed if present so that hashes of versions without a schedule stay the same
+    if let Some(schedule) = schedule {
+        rmp_serde::encode::write(&mut hash, schedule)?;
+    }
+    // versions fetched with different options must not be shared
+    if let Some(options_hash) = options_hash {
+        rmp_serde::encode::write(&mut hash, options_hash)?;
+    }
+    // versions fetched using a user's secrets must not be shared either
+    if let Some(secrets_user_id) = secrets_user_id {
+        rmp_serde::encode::write(&mut hash, &secrets_user_id)?;
+    }
 
     let res = hash.finalize();
     Ok(hex::encode(res.as_slice()))
@@ -662,15 +832,29 @@ fn get_source_hash(
 fn get_source_item_hash(
     data: &SourceItemData,
     date_updated: Option<&str>,
+    secrets_user_id: Option<UserId>,
 ) -> Result<String, rmp_serde::encode::Error> {
     let mut hash = sha2::Sha512::default();
     rmp_serde::encode::write(&mut hash, data)?;
     rmp_serde::encode::write(&mut hash, &date_updated)?;
+    // versions fetched using a user's secrets must not be shared
+    if let Some(secrets_user_id) = secrets_user_id {
+        rmp_serde::encode::write(&mut hash, &secrets_user_id)?;
+    }
 
     let res = hash.finalize();
     Ok(hex::encode(res.as_slice()))
 }
 
+/// Returns the content-addressed hash of a resource.
+pub fn get_source_resource_hash(data: &[u8]) -> String {
+    let mut hash = sha2::Sha512::default();
+    hash.update(data);
+
+    let res = hash.finalize();
+    hex::encode(res.as_slice())
+}
+
 pub struct SourceVersionSnapshot {
     inner: models::SourceVersion,
 }
@@ -689,6 +873,19 @@ impl SourceVersionSnapshot {
     pub fn items(&self) -> Result<Vec<SourceMetaItem>, rmp_serde::decode::Error> {
         rmp_serde::decode::from_read(io::Cursor::new(&self.inner.items))
     }
+
+    /// Returns the id of the domain script revision that produced this version, if known.
+    pub fn script_revision(&self) -> Option<i32> {
+        self.inner.script_revision
+    }
+
+    /// Returns the update schedule hints of this version, if the script provided any.
+    pub fn schedule(&self) -> Result<Option<SourceSchedule>, rmp_serde::decode::Error> {
+        match &self.inner.schedule {
+            Some(schedule) => rmp_serde::decode::from_read(io::Cursor::new(schedule)).map(Some),
+            None => Ok(None),
+        }
+    }
 }
 
 impl From<models::SourceVersion> for SourceVersionSnapshot {
@@ -720,6 +917,26 @@ impl From<models::SourceItemVersion> for SourceItemVersionSnapshot {
     }
 }
 
+pub struct SourceResourceSnapshot {
+    inner: models::SourceResource,
+}
+
+impl SourceResourceSnapshot {
+    pub fn metadata(&self) -> Result<SourceResourceMetadata, rmp_serde::decode::Error> {
+        rmp_serde::decode::from_read(io::Cursor::new(&self.inner.metadata))
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.inner.data
+    }
+}
+
+impl From<models::SourceResource> for SourceResourceSnapshot {
+    fn from(this: models::SourceResource) -> Self {
+        SourceResourceSnapshot { inner: this }
+    }
+}
+
 pub struct UserSourceSnapshot {
     inner: models::UserSource,
 }
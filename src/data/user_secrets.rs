@@ -0,0 +1,214 @@
+use super::{schema, Data, DataError};
+use crate::config::Config;
+use crate::data::users::UserId;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use diesel::prelude::*;
+use hmac::{Hmac, Mac, NewMac};
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::Sha256;
+use thiserror::Error;
+
+/// Max len of a secret name in bytes.
+pub const SECRET_NAME_MAX_LEN: usize = 256;
+
+/// Max len of a secret value in bytes.
+pub const SECRET_VALUE_MAX_LEN: usize = 4096;
+
+/// Max number of secrets a user may store for a single domain.
+pub const SECRETS_PER_DOMAIN_MAX: usize = 32;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum UserSecretError {
+    #[error("name is empty")]
+    NameEmpty,
+    #[error("name is too long")]
+    NameTooLong,
+    #[error("value is too large")]
+    ValueTooLarge,
+    #[error("too many secrets")]
+    TooMany,
+    #[error("failed to encrypt secret")]
+    Encrypt,
+    #[error(transparent)]
+    Data(#[from] DataError),
+}
+
+/// Returns the cipher used to encrypt secrets at rest.
+///
+/// The key is derived from the server's private key, so that it is not the same key as the one
+/// used for session cookies.
+fn secrets_cipher() -> Aes256Gcm {
+    let mut mac = Hmac::<Sha256>::new_varkey(Config::shared().private_key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"aof user secrets");
+    let key = mac.finalize().into_bytes();
+    Aes256Gcm::new(&key)
+}
+
+/// Associated data for a secret, so that encrypted values can't be moved to a different user,
+/// domain, or name.
+fn secret_aad(user_id: UserId, domain: &str, name: &str) -> Vec<u8> {
+    format!("{}\0{}\0{}", user_id, domain, name).into_bytes()
+}
+
+impl Data {
+    /// Returns the names of all secrets a user has stored for a domain.
+    pub fn user_domain_secret_names(
+        &self,
+        user_id: UserId,
+        domain: &str,
+    ) -> Result<Vec<String>, DataError> {
+        use schema::user_domain_secrets::dsl;
+
+        Ok(dsl::user_domain_secrets
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain))
+            .order(dsl::name.asc())
+            .select(dsl::name)
+            .get_results(&self.conn)?)
+    }
+
+    /// Returns the decrypted value of a user's secret for a domain.
+    ///
+    /// Secrets that can't be decrypted (e.g. because the server's private key changed) are treated
+    /// as missing.
+    pub fn user_domain_secret(
+        &self,
+        user_id: UserId,
+        domain: &str,
+        name: &str,
+    ) -> Result<Option<String>, DataError> {
+        use schema::user_domain_secrets::dsl;
+
+        let value = dsl::user_domain_secrets
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::domain.eq(domain))
+            .filter(dsl::name.eq(name))
+            .select(dsl::value)
+            .first::<Vec<u8>>(&self.conn)
+            .optional()?;
+        let value = match value {
+            Some(value) if value.len() > NONCE_LEN => value,
+            Some(_) | None => return Ok(None),
+        };
+
+        let (nonce, ciphertext) = value.split_at(NONCE_LEN);
+        let aad = secret_aad(user_id, domain, name);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        match secrets_cipher().decrypt(GenericArray::from_slice(nonce), payload) {
+            Ok(value) => Ok(String::from_utf8(value).ok()),
+            Err(_) => {
+                warn!(
+                    "Failed to decrypt secret of user {} for domain {}",
+                    user_id, domain
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Encrypts and stores a user's secret for a domain, replacing any previous value.
+    pub fn user_domain_secret_set(
+        &self,
+        user_id: UserId,
+        domain: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<(), UserSecretError> {
+        use schema::user_domain_secrets::dsl;
+
+        if name.is_empty() {
+            return Err(UserSecretError::NameEmpty);
+        }
+        if name.len() > SECRET_NAME_MAX_LEN {
+            return Err(UserSecretError::NameTooLong);
+        }
+        if value.len() > SECRET_VALUE_MAX_LEN {
+            return Err(UserSecretError::ValueTooLarge);
+        }
+
+        let mut nonce = [0; NONCE_LEN];
+        OsRng::default().fill(&mut nonce);
+        let aad = secret_aad(user_id, domain, name);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: &aad,
+        };
+        let ciphertext = secrets_cipher()
+            .encrypt(GenericArray::from_slice(&nonce), payload)
+            .map_err(|_| UserSecretError::Encrypt)?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend_from_slice(&ciphertext);
+
+        let stored = self.conn.transaction::<_, DataError, _>(|| {
+            let names = self.user_domain_secret_names(user_id, domain)?;
+            if !names.iter().any(|n| n == name) && names.len() >= SECRETS_PER_DOMAIN_MAX {
+                return Ok(false);
+            }
+
+            diesel::replace_into(dsl::user_domain_secrets)
+                .values((
+                    dsl::user_id.eq(user_id),
+                    dsl::domain.eq(domain),
+                    dsl::name.eq(name),
+                    dsl::value.eq(&encrypted),
+                ))
+                .execute(&self.conn)?;
+
+            Ok(true)
+        })?;
+
+        if !stored {
+            return Err(UserSecretError::TooMany);
+        }
+        Ok(())
+    }
+
+    /// Deletes a user's secret for a domain.
+    pub fn user_domain_secret_delete(
+        &self,
+        user_id: UserId,
+        domain: &str,
+        name: &str,
+    ) -> Result<(), DataError> {
+        use schema::user_domain_secrets::dsl;
+
+        diesel::delete(
+            dsl::user_domain_secrets
+                .filter(dsl::user_id.eq(user_id))
+                .filter(dsl::domain.eq(domain))
+                .filter(dsl::name.eq(name)),
+        )
+        .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes all users' secrets for a domain.
+    pub fn domain_secrets_clear(&self, domain: &str) -> Result<(), DataError> {
+        use schema::user_domain_secrets::dsl;
+
+        diesel::delete(dsl::user_domain_secrets.filter(dsl::domain.eq(domain)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes all secrets of a user.
+    pub fn user_secrets_clear(&self, user_id: UserId) -> Result<(), DataError> {
+        use schema::user_domain_secrets::dsl;
+
+        diesel::delete(dsl::user_domain_secrets.filter(dsl::user_id.eq(user_id)))
+            .execute(&self.conn)?;
+
+        Ok(())
+    }
+}
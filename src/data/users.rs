@@ -172,6 +172,13 @@ impl Data {
         }
         {
             use schema::source_domains::dsl;
+            let domains = dsl::source_domains
+                .filter(dsl::owner_id.eq(user))
+                .select(dsl::domain)
+                .get_results::<String>(&self.conn)?;
+            for domain in domains {
+                self.domain_script_revisions_clear(&domain)?;
+            }
             diesel::delete(dsl::source_domains.filter(dsl::owner_id.eq(user)))
                 .execute(&self.conn)?;
         }
@@ -180,6 +187,8 @@ impl Data {
             diesel::delete(dsl::user_rss_auth_keys.filter(dsl::user_id.eq(user)))
                 .execute(&self.conn)?;
         }
+        self.user_secrets_clear(user)?;
+        self.user_option_values_clear(user)?;
         {
             use schema::users::dsl;
             diesel::delete(dsl::users.filter(dsl::id.eq(user))).execute(&self.conn)?;
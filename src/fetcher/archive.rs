@@ -0,0 +1,269 @@
+//! Archiving of resources referenced in source item HTML.
+//!
+//! Images and stylesheets are downloaded at fetch time so that source items stay readable even if
+//! the original host deletes them.
+
+use super::rate_limit::reserve_request_within;
+use super::request_fetch_permission;
+use crate::data::sources::{
+    get_source_resource_hash, SourceItemData, SourceResourceData, SourceResourceMetadata,
+};
+use aof_script::url::Url;
+use aof_script::{check_allowed_hosts, USER_AGENT};
+use futures::stream::{self, StreamExt};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Max size of a single archived resource in bytes.
+const RESOURCE_MAX_SIZE: usize = 16_777_216;
+
+/// Max number of resources that will be archived for a single source item.
+const RESOURCE_MAX_COUNT: usize = 128;
+
+/// Timeout for downloading a single resource.
+const RESOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Max total time spent archiving the resources of all source items in a fetch.
+/// Resources that have not been downloaded by then are left as-is.
+pub const ARCHIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Max number of resources that will be downloaded at the same time.
+const MAX_CONCURRENT_DOWNLOADS: usize = 8;
+
+/// Elements whose resources are archived, and the attribute containing the resource URL.
+const RESOURCE_SELECTORS: &[(&str, &str)] = &[("img", "src"), ("link[rel~=stylesheet]", "href")];
+
+/// Attribute added to elements whose resource was archived. Contains the resource hash.
+pub const ARCHIVED_ATTR: &str = "data-aof-archived";
+
+thread_local! {
+    /// Fetches run on blocking threads, so each thread needs its own system for the HTTP client.
+    static RUNNER: RefCell<Option<actix_rt::SystemRunner>> = RefCell::new(None);
+}
+
+/// Downloads all images and stylesheets referenced in the HTML tags of a source item.
+///
+/// Relative URLs are resolved against the canonical URL of the item, if it has one.
+/// Archived elements will be marked with the resource hash (see [ARCHIVED_ATTR]), and the
+/// downloaded resources are returned so that they can be stored along with the item.
+/// Resources that fail to download or can't be downloaded before the deadline are left as-is.
+///
+/// `allowed_hosts` are the host patterns the domain script may access. Resources on other hosts
+/// are not downloaded, since the script could otherwise use resource URLs to send data anywhere.
+pub fn archive_item_resources(
+    item: &mut SourceItemData,
+    allowed_hosts: Option<&[String]>,
+    deadline: Instant,
+) -> Vec<SourceResourceData> {
+    let base = match item.tags.get("canonical_url") {
+        Some(serde_json::Value::String(url)) => Url::parse(url).ok(),
+        _ => None,
+    };
+
+    let mut urls = Vec::new();
+    for html in item_html_mut(item) {
+        for (_, url) in resource_urls(&parse_html(html), base.as_ref()) {
+            if let Some(Err(err)) = allowed_hosts.map(|hosts| check_allowed_hosts(&url, hosts)) {
+                debug!("Not archiving resource {}: {}", url, err);
+                continue;
+            }
+            if urls.len() < RESOURCE_MAX_COUNT && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    if urls.is_empty() {
+        return Vec::new();
+    }
+
+    let referrer = base.as_ref().map(|url| url.to_string());
+    let resources = download_all(urls, referrer, deadline);
+    let hashes: HashMap<_, _> = resources
+        .iter()
+        .map(|res| {
+            (
+                res.metadata.url.clone(),
+                get_source_resource_hash(&res.data),
+            )
+        })
+        .collect();
+
+    for html in item_html_mut(item) {
+        let doc = parse_html(html);
+        let mut did_change = false;
+        for ((mut node, attr), url) in resource_urls(&doc, base.as_ref()) {
+            if let Some(hash) = hashes.get(url.as_str()) {
+                // also make relative URLs absolute, since they can't be resolved by the client
+                node.set_attr(attr, url.as_str());
+                node.set_attr(ARCHIVED_ATTR, hash);
+                did_change = true;
+            }
+        }
+        if did_change {
+            *html = doc.select("body").html().to_string();
+        }
+    }
+
+    resources
+}
+
+/// Returns all HTML tags of a source item.
+fn item_html_mut(item: &mut SourceItemData) -> Vec<&mut String> {
+    let mut html = Vec::new();
+    for (key, value) in item.tags.iter_mut() {
+        match (key.as_str(), value) {
+            ("contents", serde_json::Value::String(contents)) => html.push(contents),
+            ("preface", serde_json::Value::Object(obj))
+            | ("appendix", serde_json::Value::Object(obj)) => {
+                for (_, value) in obj.iter_mut() {
+                    if let serde_json::Value::String(value) = value {
+                        html.push(value);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    html
+}
+
+fn parse_html(html: &str) -> nipper::Document {
+    let mut html_wrapped = String::new();
+    html_wrapped.push_str("<!doctype html><html><head></head><body>");
+    html_wrapped.push_str(html);
+    html_wrapped.push_str("</body></html>");
+    nipper::Document::from(&html_wrapped)
+}
+
+/// Returns all elements with a resource that can be archived, along with the URL attribute name
+/// and the resolved resource URL.
+fn resource_urls<'a>(
+    doc: &'a nipper::Document,
+    base: Option<&Url>,
+) -> Vec<((nipper::Selection<'a>, &'static str), Url)> {
+    let mut nodes = Vec::new();
+    for (selector, attr) in RESOURCE_SELECTORS {
+        for node in doc.select(selector).iter() {
+            let src = match node.attr(attr) {
+                Some(src) => src,
+                None => continue,
+            };
+            let url = match base {
+                Some(base) => base.join(src.trim()),
+                None => Url::parse(src.trim()),
+            };
+            match url {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                    nodes.push(((node, *attr), url));
+                }
+                _ => (),
+            }
+        }
+    }
+    nodes
+}
+
+/// Downloads resources concurrently until the deadline.
+/// Returns the resources that were downloaded successfully.
+fn download_all(
+    urls: Vec<Url>,
+    referrer: Option<String>,
+    deadline: Instant,
+) -> Vec<SourceResourceData> {
+    let mut requests = Vec::new();
+    for url in urls {
+        match request_fetch_permission(&url) {
+            Ok(addr) => requests.push((url, addr)),
+            Err(err) => debug!("Failed to archive resource {}: {}", url, err),
+        }
+    }
+    if requests.is_empty() {
+        return Vec::new();
+    }
+
+    RUNNER.with(|runner| {
+        let mut runner = runner.borrow_mut();
+        let runner = runner.get_or_insert_with(|| actix_rt::System::new("aof-resource-archive"));
+
+        runner.block_on(async move {
+            let client = awc::Client::builder().timeout(RESOURCE_TIMEOUT).finish();
+            let client = &client;
+            let referrer = referrer.as_deref();
+
+            stream::iter(requests)
+                .map(|(url, addr)| async move {
+                    // the rate limit slot is only reserved once the download is about to start,
+                    // so that slots aren't taken up by downloads that may never happen
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let wait = match url.host_str() {
+                        _ if remaining == Duration::from_secs(0) => None,
+                        Some(host) => reserve_request_within(host, remaining),
+                        None => Some(Duration::from_secs(0)),
+                    };
+                    let res = match wait {
+                        Some(wait) => actix_rt::time::timeout(remaining, async {
+                            actix_rt::time::delay_for(wait).await;
+                            download(client, &url, addr, referrer).await
+                        })
+                        .await
+                        .unwrap_or_else(|_| Err("timed out".into())),
+                        None => Err("rate limited until after the archive deadline".into()),
+                    };
+
+                    match res {
+                        Ok(resource) => Some(resource),
+                        Err(err) => {
+                            debug!("Failed to archive resource {}: {}", url, err);
+                            None
+                        }
+                    }
+                })
+                .buffer_unordered(MAX_CONCURRENT_DOWNLOADS)
+                .filter_map(|res| async move { res })
+                .collect()
+                .await
+        })
+    })
+}
+
+async fn download(
+    client: &awc::Client,
+    url: &Url,
+    addr: SocketAddr,
+    referrer: Option<&str>,
+) -> Result<SourceResourceData, String> {
+    // connect to the checked address instead of resolving the host again
+    let mut req = client
+        .get(url.as_str())
+        .address(addr)
+        .header("User-Agent", USER_AGENT);
+    if let Some(referrer) = referrer {
+        req = req.header("Referer", referrer);
+    }
+
+    let mut res = req.send().await.map_err(|e| format!("{}", e))?;
+    if !res.status().is_success() {
+        return Err(format!("status {}", res.status()));
+    }
+
+    let content_type = res
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let data = res
+        .body()
+        .limit(RESOURCE_MAX_SIZE)
+        .await
+        .map_err(|e| format!("{}", e))?;
+
+    Ok(SourceResourceData {
+        metadata: SourceResourceMetadata {
+            url: url.to_string(),
+            content_type,
+        },
+        data: data.to_vec(),
+    })
+}
@@ -0,0 +1,72 @@
+//! Merging of fetch results into cumulative sources.
+
+use crate::data::domains::CumulativeSettings;
+use crate::data::sources::SourceMetaItem;
+use crate::data::{Data, DataError};
+use chrono::{Duration, Utc};
+use std::collections::{HashMap, HashSet};
+
+/// Merges newly fetched items into the items of the previous version of a cumulative source.
+///
+/// Previous items keep their position and are replaced if they were fetched again. New items are
+/// appended in the order in which the script returned them. Previous items that were not fetched
+/// again are removed once they exceed the limits in the settings, oldest first. The max age is
+/// counted from the last fetch that returned an item.
+pub fn merge_cumulative_items(
+    data: &Data,
+    source_uri: &str,
+    settings: CumulativeSettings,
+    previous: Vec<SourceMetaItem>,
+    items: Vec<SourceMetaItem>,
+) -> Result<Vec<SourceMetaItem>, DataError> {
+    let now = Utc::now();
+    let dates = data.cumulative_item_dates(source_uri)?;
+
+    let previous_paths: HashSet<_> = previous.iter().map(|item| item.path.clone()).collect();
+    let mut fetched = HashMap::new();
+    let mut new_items = Vec::new();
+    for item in items {
+        if previous_paths.contains(&item.path) {
+            fetched.insert(item.path.clone(), item);
+        } else {
+            new_items.push(item);
+        }
+    }
+
+    // (item, was fetched just now)
+    let mut merged: Vec<_> = previous
+        .into_iter()
+        .map(|item| match fetched.remove(&item.path) {
+            Some(item) => (item, true),
+            None => (item, false),
+        })
+        .collect();
+    merged.extend(new_items.into_iter().map(|item| (item, true)));
+
+    if let Some(max_age) = settings.max_age {
+        let cutoff = now - Duration::seconds(max_age as i64);
+        merged.retain(|(item, is_fetched)| {
+            // items without a date were added before the source became cumulative
+            *is_fetched || dates.get(&item.path).map_or(true, |date| *date >= cutoff)
+        });
+    }
+    if let Some(max_items) = settings.max_items {
+        let mut excess = merged.len().saturating_sub(max_items as usize);
+        merged.retain(|(_, is_fetched)| {
+            if excess > 0 && !*is_fetched {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    let paths: Vec<_> = merged
+        .iter()
+        .map(|(item, is_fetched)| (&*item.path, *is_fetched))
+        .collect();
+    data.set_cumulative_items(source_uri, &paths, now)?;
+
+    Ok(merged.into_iter().map(|(item, _)| item).collect())
+}
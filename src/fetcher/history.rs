@@ -0,0 +1,45 @@
+//! Recording source fetch attempts in the fetch history.
+
+use crate::config::Config;
+use crate::data::fetch_history::NewFetchHistoryEntry;
+use crate::data::{Data, DataError};
+use chrono::{Duration, Utc};
+
+const DEFAULT_MAX_ENTRIES: u64 = 50;
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+fn max_entries() -> u64 {
+    match &Config::shared().fetch_history {
+        Some(config) => config.max_entries.max(1),
+        None => DEFAULT_MAX_ENTRIES,
+    }
+}
+
+fn max_age_days() -> u64 {
+    match &Config::shared().fetch_history {
+        Some(config) => config.max_age,
+        None => DEFAULT_MAX_AGE_DAYS,
+    }
+}
+
+/// Records a fetch attempt and drops the oldest entries of the source if there are too many.
+///
+/// Errors are only logged, since they shouldn't cause the fetch itself to fail.
+pub fn record_fetch(data: &Data, entry: NewFetchHistoryEntry) {
+    let source_uri = entry.source_uri;
+    let res = data
+        .add_fetch_history_entry(entry)
+        .and_then(|_| data.prune_fetch_history(source_uri, max_entries()));
+    if let Err(err) = res {
+        error!(
+            "Failed to record fetch history entry for {}: {}",
+            source_uri, err
+        );
+    }
+}
+
+/// Deletes fetch history entries that are older than the configured max age.
+pub fn garbage_collect_fetch_history(data: &Data) -> Result<(), DataError> {
+    let max_age = Duration::days(max_age_days().min(i32::MAX as u64) as i64);
+    data.delete_fetch_history_before(Utc::now() - max_age)
+}
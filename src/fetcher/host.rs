@@ -0,0 +1,594 @@
+use crate::config::ScriptLimitValues;
+use crate::data::domain_cassettes::CASSETTE_MAX_LEN;
+use crate::data::domain_cookies::COOKIE_JAR_MAX_LEN;
+use crate::data::domain_storage::DomainStorageError;
+use crate::data::domains::DomainSnapshot;
+use crate::data::users::UserId;
+use crate::fetcher::limits::domain_script_limits;
+use crate::fetcher::ScriptHost;
+use crate::state::SharedData;
+use aof_script::url::Url;
+use aof_script::{CachedResponse, Cassette, CassetteRequest, CookieJar, FetchMode};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+
+/// Handles requests from a script running for a specific domain.
+///
+/// Responses recorded to the domain's cassette are stored when the host is dropped.
+///
+/// Once the script has read a secret, responses may contain data private to the user, so they are
+/// no longer stored in the shared HTTP cache or the cassette, and changes to cookies and domain
+/// storage are only kept in memory.
+pub struct FetchHost<'a> {
+    data: &'a SharedData,
+    domain: &'a DomainSnapshot,
+    /// The user the script is running for. Secrets are only available if this is set.
+    user_id: Option<UserId>,
+    /// Host patterns the script may access, or None if it may access any host.
+    allowed_hosts: Option<Vec<String>>,
+    /// The domain's cassette, once loaded.
+    cassette: RefCell<Option<Cassette>>,
+    /// Set if a response was recorded to the cassette.
+    cassette_changed: Cell<bool>,
+    /// Set once the script has read a secret.
+    did_read_secrets: Cell<bool>,
+    /// The cookie jar, once it's no longer persisted because the script has read a secret.
+    private_cookies: RefCell<Option<String>>,
+    /// Storage values changed after the script has read a secret. None if the value was deleted.
+    private_storage: RefCell<HashMap<String, Option<String>>>,
+}
+
+impl<'a> FetchHost<'a> {
+    pub fn new(data: &'a SharedData, domain: &'a DomainSnapshot) -> Self {
+        FetchHost {
+            data,
+            domain,
+            user_id: None,
+            allowed_hosts: domain.allowed_hosts(),
+            cassette: RefCell::new(None),
+            cassette_changed: Cell::new(false),
+            did_read_secrets: Cell::new(false),
+            private_cookies: RefCell::new(None),
+            private_storage: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a host for a script running on behalf of a user, which may access their secrets.
+    pub fn for_user(data: &'a SharedData, domain: &'a DomainSnapshot, user_id: UserId) -> Self {
+        let mut host = Self::new(data, domain);
+        host.user_id = Some(user_id);
+        host
+    }
+
+    /// Restricts the script to the given host patterns instead of the domain's allowed hosts.
+    pub fn with_allowed_hosts(mut self, allowed_hosts: Option<Vec<String>>) -> Self {
+        self.allowed_hosts = allowed_hosts;
+        self
+    }
+
+    /// Returns the user whose secrets the script has read, if any.
+    pub fn secrets_user(&self) -> Option<UserId> {
+        if self.did_read_secrets.get() {
+            self.user_id
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> FetchHost<'a> {
+    /// Calls the closure with the domain's cassette, loading it if necessary.
+    fn with_cassette<T>(&self, f: impl FnOnce(&mut Cassette) -> T) -> Result<T, String> {
+        let mut cassette = self.cassette.borrow_mut();
+        if cassette.is_none() {
+            let stored = match self.data.lock().domain_cassette(self.domain.id()) {
+                Ok(stored) => stored,
+                Err(err) => {
+                    error!(
+                        "Failed to read cassette of domain {}: {}",
+                        self.domain.id(),
+                        err
+                    );
+                    return Err(String::from("internal error"));
+                }
+            };
+            *cassette = Some(match stored {
+                // cassettes are validated when uploaded, so this shouldn't fail
+                Some(stored) => serde_json::from_str(&stored)
+                    .map_err(|err| format!("invalid cassette: {}", err))?,
+                None => Cassette::default(),
+            });
+        }
+        Ok(f(cassette.as_mut().unwrap()))
+    }
+}
+
+impl<'a> Drop for FetchHost<'a> {
+    fn drop(&mut self) {
+        if !self.cassette_changed.get() {
+            return;
+        }
+        let cassette = match &*self.cassette.borrow() {
+            Some(cassette) => serde_json::to_string(cassette),
+            None => return,
+        };
+
+        // the cassette never exceeds the max size when recording, so it can always be stored
+        let res = cassette
+            .map_err(|err| format!("{}", err))
+            .and_then(|cassette| {
+                self.data
+                    .lock()
+                    .domain_cassette_set(self.domain.id(), &cassette)
+                    .map_err(|err| format!("{}", err))
+            });
+        if let Err(err) = res {
+            error!(
+                "Failed to write cassette of domain {}: {}",
+                self.domain.id(),
+                err
+            );
+        }
+    }
+}
+
+/// Stores cookies from Set-Cookie headers in a JSON-encoded cookie jar, and returns the new jar.
+/// If the jar gets too large, the oldest cookies are evicted.
+fn store_cookies(jar: Option<&str>, url: &str, set_cookies: &[String]) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|err| format!("invalid URL: {}", err))?;
+    // if the stored jar is broken for some reason, just start over
+    let mut jar: CookieJar = jar
+        .and_then(|jar| serde_json::from_str(jar).ok())
+        .unwrap_or_default();
+    jar.store_cookies(&url, set_cookies.iter().map(|s| &**s));
+    Ok(jar.to_json(COOKIE_JAR_MAX_LEN))
+}
+
+impl<'a> ScriptHost for FetchHost<'a> {
+    fn script_limits(&self) -> ScriptLimitValues {
+        domain_script_limits(&self.domain.script_limits())
+    }
+
+    fn storage_get(&self, key: &str) -> Result<Option<String>, String> {
+        if let Some(value) = self.private_storage.borrow().get(key) {
+            return Ok(value.clone());
+        }
+        self.data
+            .lock()
+            .domain_storage_get(self.domain.id(), key)
+            .map_err(|err| {
+                error!(
+                    "Failed to read storage of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })
+    }
+
+    fn storage_set(&self, key: &str, value: &str) -> Result<(), String> {
+        if self.did_read_secrets.get() {
+            self.private_storage
+                .borrow_mut()
+                .insert(key.to_string(), Some(value.to_string()));
+            return Ok(());
+        }
+        match self
+            .data
+            .lock()
+            .domain_storage_set(self.domain.id(), key, value)
+        {
+            Ok(()) => Ok(()),
+            Err(DomainStorageError::Data(err)) => {
+                error!(
+                    "Failed to write storage of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                Err(String::from("internal error"))
+            }
+            Err(err) => Err(format!("{}", err)),
+        }
+    }
+
+    fn storage_delete(&self, key: &str) -> Result<(), String> {
+        if self.did_read_secrets.get() {
+            self.private_storage
+                .borrow_mut()
+                .insert(key.to_string(), None);
+            return Ok(());
+        }
+        self.data
+            .lock()
+            .domain_storage_delete(self.domain.id(), key)
+            .map_err(|err| {
+                error!(
+                    "Failed to write storage of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })
+    }
+
+    fn secret_get(&self, name: &str) -> Result<Option<String>, String> {
+        let user_id = match self.user_id {
+            Some(user_id) => user_id,
+            None => {
+                return Err(String::from(
+                    "secrets are only available in fetches requested by a user",
+                ))
+            }
+        };
+        let secret = self
+            .data
+            .lock()
+            .user_domain_secret(user_id, self.domain.id(), name)
+            .map_err(|err| {
+                error!(
+                    "Failed to read secret of user {} for domain {}: {}",
+                    user_id,
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })?;
+        if secret.is_some() && !self.did_read_secrets.get() {
+            // keep the current cookies, but don't persist any new ones (e.g. from logging in)
+            *self.private_cookies.borrow_mut() = self.cookies_get()?;
+            self.did_read_secrets.set(true);
+        }
+        Ok(secret)
+    }
+
+    fn cookies_get(&self) -> Result<Option<String>, String> {
+        if self.did_read_secrets.get() {
+            return Ok(self.private_cookies.borrow().clone());
+        }
+        self.data
+            .lock()
+            .domain_cookie_jar(self.domain.id())
+            .map_err(|err| {
+                error!(
+                    "Failed to read cookie jar of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })
+    }
+
+    fn cookies_store(&self, url: &str, set_cookies: &[String]) -> Result<(), String> {
+        if self.did_read_secrets.get() {
+            let jar = store_cookies(self.private_cookies.borrow().as_deref(), url, set_cookies)?;
+            *self.private_cookies.borrow_mut() = Some(jar);
+            return Ok(());
+        }
+
+        // the jar is read and written while holding the lock, so that concurrent fetches of the
+        // domain don't lose each other's cookies
+        let data = self.data.lock();
+        let jar = data.domain_cookie_jar(self.domain.id()).map_err(|err| {
+            error!(
+                "Failed to read cookie jar of domain {}: {}",
+                self.domain.id(),
+                err
+            );
+            String::from("internal error")
+        })?;
+        let jar = store_cookies(jar.as_deref(), url, set_cookies)?;
+        data.domain_cookie_jar_set(self.domain.id(), &jar)
+            .map_err(|err| {
+                error!(
+                    "Failed to write cookie jar of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })
+    }
+
+    fn http_cache_get(&self, key: &str) -> Result<Option<CachedResponse>, String> {
+        let response = match self.data.lock().http_cache_get(self.domain.id(), key) {
+            Ok(Some(response)) => response,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                error!(
+                    "Failed to read cached response of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                return Err(String::from("internal error"));
+            }
+        };
+
+        match rmp_serde::decode::from_read(io::Cursor::new(&response)) {
+            Ok(response) => Ok(Some(response)),
+            Err(err) => {
+                // probably from an older version; will be replaced
+                warn!(
+                    "Failed to decode cached response of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    fn http_cache_set(&self, key: &str, response: &CachedResponse) -> Result<(), String> {
+        if self.did_read_secrets.get() {
+            return Ok(());
+        }
+        let response = rmp_serde::encode::to_vec(response).map_err(|err| {
+            error!(
+                "Failed to encode cached response for {}: {}",
+                response.url, err
+            );
+            String::from("internal error")
+        })?;
+
+        self.data
+            .lock()
+            .http_cache_set(self.domain.id(), key, &response)
+            .map_err(|err| {
+                error!(
+                    "Failed to store cached response of domain {}: {}",
+                    self.domain.id(),
+                    err
+                );
+                String::from("internal error")
+            })
+    }
+
+    fn fetch_mode(&self) -> FetchMode {
+        self.domain.fetch_mode()
+    }
+
+    fn allowed_hosts(&self) -> Option<Vec<String>> {
+        self.allowed_hosts.clone()
+    }
+
+    fn cassette_get(&self, request: &CassetteRequest) -> Result<Option<CachedResponse>, String> {
+        self.with_cassette(|cassette| cassette.response(request))
+    }
+
+    fn cassette_record(
+        &self,
+        request: CassetteRequest,
+        response: CachedResponse,
+    ) -> Result<(), String> {
+        if self.did_read_secrets.get() {
+            return Ok(());
+        }
+        if self.with_cassette(|cassette| cassette.record(request, response, CASSETTE_MAX_LEN))? {
+            self.cassette_changed.set(true);
+            Ok(())
+        } else {
+            Err(String::from("the cassette is full"))
+        }
+    }
+
+    fn load_library(&self, id: &str) -> Result<String, String> {
+        if id == self.domain.id() {
+            return Err(String::from("a domain cannot import itself"));
+        }
+
+        let library = match self.data.lock().domain_by_domain_id(id) {
+            Ok(Some(library)) => library,
+            Ok(None) => return Err(format!("no such domain {:?}", id)),
+            Err(err) => {
+                error!("Failed to load library domain {}: {}", id, err);
+                return Err(String::from("internal error"));
+            }
+        };
+
+        // only allow importing own or public domains, so that private scripts can't be read
+        if library.owner_id() != self.domain.owner_id() && !library.is_public() {
+            return Err(format!("no such domain {:?}", id));
+        }
+        if !library.is_library() {
+            return Err(format!("domain {:?} is not a library", id));
+        }
+
+        Ok(library.script().to_string())
+    }
+}
+
+/// Handles requests from a script during a test run.
+///
+/// Reads go to the domain's data as usual, but changes to storage, cookies and the cassette are
+/// only kept in memory for the duration of the test run.
+pub struct TestRunHost<'a> {
+    host: FetchHost<'a>,
+    /// Storage values changed during the test run. None if the value was deleted.
+    storage: RefCell<HashMap<String, Option<String>>>,
+    cookies: RefCell<Option<String>>,
+    /// Responses recorded during the test run.
+    cassette: RefCell<Cassette>,
+}
+
+impl<'a> TestRunHost<'a> {
+    pub fn new(data: &'a SharedData, domain: &'a DomainSnapshot) -> Self {
+        Self::with_host(FetchHost::new(data, domain))
+    }
+
+    /// Creates a host for a test run requested by a user, which may access their secrets.
+    pub fn for_user(data: &'a SharedData, domain: &'a DomainSnapshot, user_id: UserId) -> Self {
+        Self::with_host(FetchHost::for_user(data, domain, user_id))
+    }
+
+    fn with_host(host: FetchHost<'a>) -> Self {
+        TestRunHost {
+            host,
+            storage: RefCell::new(HashMap::new()),
+            cookies: RefCell::new(None),
+            cassette: RefCell::new(Cassette::default()),
+        }
+    }
+}
+
+impl<'a> ScriptHost for TestRunHost<'a> {
+    fn script_limits(&self) -> ScriptLimitValues {
+        self.host.script_limits()
+    }
+
+    fn storage_get(&self, key: &str) -> Result<Option<String>, String> {
+        match self.storage.borrow().get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.host.storage_get(key),
+        }
+    }
+
+    fn storage_set(&self, key: &str, value: &str) -> Result<(), String> {
+        self.storage
+            .borrow_mut()
+            .insert(key.to_string(), Some(value.to_string()));
+        Ok(())
+    }
+
+    fn storage_delete(&self, key: &str) -> Result<(), String> {
+        self.storage.borrow_mut().insert(key.to_string(), None);
+        Ok(())
+    }
+
+    fn secret_get(&self, name: &str) -> Result<Option<String>, String> {
+        self.host.secret_get(name)
+    }
+
+    fn cookies_get(&self) -> Result<Option<String>, String> {
+        match &*self.cookies.borrow() {
+            Some(cookies) => Ok(Some(cookies.clone())),
+            None => self.host.cookies_get(),
+        }
+    }
+
+    fn cookies_store(&self, url: &str, set_cookies: &[String]) -> Result<(), String> {
+        let jar = self.cookies_get()?;
+        *self.cookies.borrow_mut() = Some(store_cookies(jar.as_deref(), url, set_cookies)?);
+        Ok(())
+    }
+
+    fn http_cache_get(&self, key: &str) -> Result<Option<CachedResponse>, String> {
+        self.host.http_cache_get(key)
+    }
+
+    fn http_cache_set(&self, _key: &str, _response: &CachedResponse) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn fetch_mode(&self) -> FetchMode {
+        self.host.fetch_mode()
+    }
+
+    fn allowed_hosts(&self) -> Option<Vec<String>> {
+        self.host.allowed_hosts()
+    }
+
+    fn cassette_get(&self, request: &CassetteRequest) -> Result<Option<CachedResponse>, String> {
+        match self.cassette.borrow().response(request) {
+            Some(response) => Ok(Some(response)),
+            None => self.host.cassette_get(request),
+        }
+    }
+
+    fn cassette_record(
+        &self,
+        request: CassetteRequest,
+        response: CachedResponse,
+    ) -> Result<(), String> {
+        if self.host.did_read_secrets.get() {
+            return Ok(());
+        }
+        if self
+            .cassette
+            .borrow_mut()
+            .record(request, response, CASSETTE_MAX_LEN)
+        {
+            Ok(())
+        } else {
+            Err(String::from("the cassette is full"))
+        }
+    }
+
+    fn load_library(&self, id: &str) -> Result<String, String> {
+        self.host.load_library(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the Cookie header that a JSON-encoded jar sends to a URL.
+    fn cookie_header(jar: &str, url: &str) -> Option<String> {
+        let jar: CookieJar = serde_json::from_str(jar).unwrap();
+        jar.cookie_header(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn store_cookies_merges_into_jar() {
+        let url = "https://www.example.com/a/b";
+        let jar = store_cookies(None, url, &[String::from("a=1; Path=/")]).unwrap();
+
+        // (stored jar, Set-Cookie headers, expected Cookie header)
+        let cases = [
+            (Some(&*jar), vec!["b=2; Path=/"], Some("a=1; b=2")),
+            (Some(&*jar), vec!["a=2; Path=/"], Some("a=2")),
+            // different paths are different cookies
+            (Some(&*jar), vec!["a=2"], Some("a=2; a=1")),
+            (Some(&*jar), vec!["a=; Path=/; Max-Age=0"], None),
+            (Some(&*jar), vec!["b=2; Path=/; Max-Age=0"], Some("a=1")),
+            (
+                Some(&*jar),
+                vec!["b=2; Path=/; Domain=evil.com"],
+                Some("a=1"),
+            ),
+            (Some(&*jar), vec!["invalid"], Some("a=1")),
+            (Some(&*jar), vec![], Some("a=1")),
+            // a broken jar is replaced
+            (Some("{"), vec!["b=2; Path=/"], Some("b=2")),
+            (None, vec!["b=2; Path=/"], Some("b=2")),
+        ];
+        for (jar, set_cookies, expected) in &cases {
+            let set_cookies: Vec<_> = set_cookies.iter().map(|s| s.to_string()).collect();
+            let new_jar = store_cookies(*jar, url, &set_cookies).unwrap();
+            assert_eq!(
+                cookie_header(&new_jar, url).as_deref(),
+                *expected,
+                "{:?} + {:?}",
+                jar,
+                set_cookies
+            );
+        }
+    }
+
+    #[test]
+    fn store_cookies_keeps_other_hosts() {
+        let jar = store_cookies(None, "https://a.example.com/", &[String::from("a=1")]).unwrap();
+        let jar =
+            store_cookies(Some(&jar), "https://b.example.com/", &[String::from("b=2")]).unwrap();
+        let jar = store_cookies(
+            Some(&jar),
+            "https://c.example.com/",
+            &[String::from("c=3; Domain=example.com")],
+        )
+        .unwrap();
+
+        let cases = [
+            ("https://a.example.com/", Some("a=1; c=3")),
+            ("https://b.example.com/", Some("b=2; c=3")),
+            ("https://example.com/", Some("c=3")),
+            ("https://evil-example.com/", None),
+        ];
+        for (url, expected) in &cases {
+            assert_eq!(cookie_header(&jar, url).as_deref(), *expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn store_cookies_rejects_invalid_urls() {
+        assert!(store_cookies(None, "not a url", &[String::from("a=1")]).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Resource limits of domain scripts.
+
+use crate::config::{Config, ScriptLimitValues};
+use crate::data::domains::DomainScriptLimits;
+use aof_script::ScriptLimits;
+use std::time::Duration;
+
+/// Limits used if none are configured.
+const DEFAULT_SCRIPT_LIMITS: ScriptLimitValues = ScriptLimitValues {
+    exec_time: 6,
+    request_timeout: 20,
+    response_size: 256,
+    heap_size: 256,
+};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Returns the default script limits.
+pub fn default_script_limits() -> ScriptLimitValues {
+    match &Config::shared().script_limits {
+        Some(limits) => limits.default,
+        None => DEFAULT_SCRIPT_LIMITS,
+    }
+}
+
+/// Returns the max script limits that a domain may set.
+pub fn max_script_limits() -> ScriptLimitValues {
+    match &Config::shared().script_limits {
+        Some(limits) => limits.max.unwrap_or(limits.default),
+        None => DEFAULT_SCRIPT_LIMITS,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidScriptLimits {
+    /// A limit is zero.
+    Zero,
+    /// A limit is larger than the max value.
+    TooHigh,
+}
+
+/// Checks if the domain script limits may be set.
+pub fn validate_domain_script_limits(
+    limits: &DomainScriptLimits,
+) -> Result<(), InvalidScriptLimits> {
+    let max = max_script_limits();
+    let pairs = [
+        (limits.exec_time, max.exec_time),
+        (limits.request_timeout, max.request_timeout),
+        (limits.response_size, max.response_size),
+        (limits.heap_size, max.heap_size),
+    ];
+    for (value, max) in &pairs {
+        match value {
+            Some(0) => return Err(InvalidScriptLimits::Zero),
+            Some(value) if value > max => return Err(InvalidScriptLimits::TooHigh),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the effective script limits for a domain.
+///
+/// Domain limits are clamped to the max limits, since these may have changed since.
+pub fn domain_script_limits(limits: &DomainScriptLimits) -> ScriptLimitValues {
+    let default = default_script_limits();
+    let max = max_script_limits();
+    let resolve = |value: Option<u64>, default: u64, max: u64| match value {
+        Some(value) => value.min(max).max(1),
+        None => default,
+    };
+
+    ScriptLimitValues {
+        exec_time: resolve(limits.exec_time, default.exec_time, max.exec_time),
+        request_timeout: resolve(
+            limits.request_timeout,
+            default.request_timeout,
+            max.request_timeout,
+        ),
+        response_size: resolve(
+            limits.response_size,
+            default.response_size,
+            max.response_size,
+        ),
+        heap_size: resolve(limits.heap_size, default.heap_size, max.heap_size),
+    }
+}
+
+/// Converts script limits to the limits passed to the script runtime.
+pub fn runtime_script_limits(limits: &ScriptLimitValues) -> ScriptLimits {
+    ScriptLimits {
+        max_heap_size: (limits.heap_size * MIB) as usize,
+        request_timeout: Duration::from_secs(limits.request_timeout),
+        max_response_size: (limits.response_size * MIB) as usize,
+    }
+}
@@ -1,6 +1,12 @@
-use crate::data::sources::{canonicalize_uri, CreateVersionError, SourceItemData, SourceMetadata};
+use crate::data::domain_options::{options_hash, DomainOptionValues};
+use crate::data::domains::DomainSnapshot;
+use crate::data::fetch_history::NewFetchHistoryEntry;
+use crate::data::sources::{
+    canonicalize_uri, CreateVersionError, SourceItemData, SourceMetadata, SourceResourceData,
+    SourceSchedule,
+};
 use crate::data::users::UserId;
-use crate::data::DataError;
+use crate::data::{Data, DataError};
 use crate::session::protocol;
 use crate::session::users::{DispatchUserEvent, UserMgrDispatchEvent};
 use crate::state::SharedData;
@@ -8,18 +14,56 @@ use actix::prelude::*;
 use actix_rt::blocking::BlockingError;
 use actix_web::web;
 use aof_script::console::{ConsoleMessage, MessageType, MsgFrag};
+use aof_script::url::Url;
+use aof_script::{PreviousSource, SearchResult};
 use chrono::Utc;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+mod archive;
+mod cumulative;
+mod history;
+mod host;
+pub mod limits;
+pub mod network;
+mod rate_limit;
+mod resolve;
 mod script;
 
 use crate::session::protocol::UpdateType;
-pub use script::{request_fetch_permission, run_ipc_fork, FetchMsg, FetchTime};
+use archive::{archive_item_resources, ARCHIVE_TIMEOUT};
+use cumulative::merge_cumulative_items;
+pub use history::garbage_collect_fetch_history;
+use history::record_fetch;
+use host::{FetchHost, TestRunHost};
+pub use network::request_fetch_permission;
+use rate_limit::{reserve_request, reserve_request_now};
+pub use resolve::{resolve_url, ResolvedUrl};
+pub use script::{init_worker_pool, run_ipc_fork, FetchMsg, FetchTime, ScriptError, ScriptHost};
 
 pub struct Fetcher {
     data: SharedData,
 }
 
+/// Type of script request in a test run.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum TestRunKind {
+    #[serde(rename = "source")]
+    Source,
+    #[serde(rename = "source_item")]
+    SourceItem,
+    /// The path is used as the search query.
+    #[serde(rename = "search")]
+    Search,
+}
+
+/// Users grouped by their domain options and the host patterns the script may access for them.
+type FetchGroups = Vec<(DomainOptionValues, Option<Vec<String>>, Vec<UserId>)>;
+
+/// Max number of search results returned by [Fetcher::search].
+const MAX_SEARCH_RESULTS: usize = 100;
+
 #[derive(Debug, Error)]
 pub enum FetchError {
     #[error("invalid uri")]
@@ -43,6 +87,14 @@ impl Fetcher {
     /// If this is `Some`, then *only* that user will know about the fetch.
     /// If this is `None`, the request is assumed to have been initiated by the global fetcher,
     /// so all subscribed users will be notified.
+    ///
+    /// The script is given the domain options of the user. Since users with different options
+    /// must not share source versions, the global fetcher fetches the source once for every set
+    /// of options among the subscribed users, and returns the first successful result.
+    /// Users who haven't confirmed the domain's allowed hosts since they were widened are also
+    /// fetched for separately, with only the hosts they confirmed.
+    ///
+    /// The attempt is recorded in the fetch history of the source.
     pub fn fetch_source(
         shared_data: &SharedData,
         user_id: Option<UserId>,
@@ -50,20 +102,175 @@ impl Fetcher {
     ) -> Result<(Vec<FetchMsg>, Option<String>), FetchError> {
         let uri = canonicalize_uri(uri).map_err(|_| FetchError::InvalidUri)?;
 
+        let (domain, script_revision, groups) =
+            match Self::source_fetch_groups(shared_data, user_id, &uri) {
+                Ok(res) => res,
+                Err(err) => {
+                    return Self::record_source_fetch(
+                        shared_data,
+                        user_id,
+                        &uri,
+                        Vec::new(),
+                        None,
+                        false,
+                        Err(err),
+                    )
+                }
+            };
+
+        let mut result: Option<(Vec<FetchMsg>, Option<String>)> = None;
+        for (options, allowed_hosts, evt_users) in groups {
+            let res = Self::fetch_source_with_options(
+                shared_data,
+                user_id,
+                &uri,
+                &domain,
+                script_revision,
+                &options,
+                allowed_hosts,
+                evt_users,
+            )?;
+            if result.as_ref().map_or(true, |(_, hash)| hash.is_none()) {
+                result = Some(res);
+            }
+        }
+        Ok(result.expect("no options to fetch source with"))
+    }
+
+    /// Returns the domain of a source, the current revision of its script, and the users to fetch
+    /// the source for, grouped by their domain options and allowed hosts.
+    fn source_fetch_groups(
+        shared_data: &SharedData,
+        user_id: Option<UserId>,
+        uri: &Url,
+    ) -> Result<(DomainSnapshot, Option<i32>, FetchGroups), FetchError> {
         let data = shared_data.lock();
         let domain_name = uri.scheme().to_string();
-        let domain = match data.domain_by_domain_id(&domain_name)? {
-            Some(domain) => domain,
-            None => return Err(FetchError::DomainNotFound(domain_name)),
-        };
-
-        let evt_users = if let Some(user) = user_id {
-            vec![user]
+        let (domain, script_revision) =
+            match data.domain_by_domain_id_with_script_revision(&domain_name)? {
+                Some(res) => res,
+                None => return Err(FetchError::DomainNotFound(domain_name)),
+            };
+
+        let groups = if let Some(user) = user_id {
+            let options = data.effective_domain_options(Some(user), &domain_name)?;
+            let allowed_hosts = data.user_domain_allowed_hosts(user, &domain)?;
+            vec![(options, allowed_hosts, vec![user])]
         } else {
-            data.source_get_subscribed_users(&uri.to_string())?
+            let users = data.source_get_subscribed_users(&uri.to_string())?;
+            Self::group_users(&data, &domain, users)?
         };
 
-        drop(data);
+        Ok((domain, script_revision, groups))
+    }
+
+    /// Records a source fetch attempt in the fetch history and returns its result.
+    ///
+    /// `res` is the resulting source version hash, or None if the script failed. Errors are added
+    /// to the log. `read_secrets` is whether the script read secrets of the user.
+    fn record_source_fetch(
+        shared_data: &SharedData,
+        user_id: Option<UserId>,
+        uri: &Url,
+        mut msg: Vec<FetchMsg>,
+        time: Option<FetchTime>,
+        read_secrets: bool,
+        res: Result<Option<String>, FetchError>,
+    ) -> Result<(Vec<FetchMsg>, Option<String>), FetchError> {
+        if let Err(err) = &res {
+            msg.push(FetchMsg {
+                time: None,
+                msg: ConsoleMessage {
+                    msg_type: MessageType::Error,
+                    message: vec![MsgFrag::Log(format!("{}", err))],
+                },
+            });
+        }
+        let hash = res.as_ref().ok().and_then(|hash| hash.as_deref());
+
+        record_fetch(
+            &shared_data.lock(),
+            NewFetchHistoryEntry {
+                source_uri: &uri.to_string(),
+                date: Utc::now(),
+                initiator: user_id,
+                success: hash.is_some(),
+                duration: time.map_or(Duration::from_secs(0), |time| time.real),
+                version_hash: hash,
+                log: &msg,
+                read_secrets,
+            },
+        );
+
+        let hash = res?;
+        Ok((msg, hash))
+    }
+
+    /// Fetches a source with the given domain options and allowed hosts and updates the given
+    /// users.
+    ///
+    /// `script_revision` is the id of the current revision of the domain script.
+    /// The attempt is recorded in the fetch history regardless of how it ends.
+    #[allow(clippy::too_many_arguments)]
+    fn fetch_source_with_options(
+        shared_data: &SharedData,
+        user_id: Option<UserId>,
+        uri: &Url,
+        domain: &DomainSnapshot,
+        script_revision: Option<i32>,
+        options: &DomainOptionValues,
+        allowed_hosts: Option<Vec<String>>,
+        evt_users: Vec<UserId>,
+    ) -> Result<(Vec<FetchMsg>, Option<String>), FetchError> {
+        let host = match user_id {
+            Some(user_id) => FetchHost::for_user(shared_data, domain, user_id),
+            None => FetchHost::new(shared_data, domain),
+        }
+        .with_allowed_hosts(allowed_hosts);
+
+        let mut msg = Vec::new();
+        let mut time = None;
+        let res = Self::run_source_fetch(
+            shared_data,
+            user_id,
+            uri,
+            domain,
+            &host,
+            script_revision,
+            options,
+            evt_users,
+            &mut msg,
+            &mut time,
+        );
+        let read_secrets = host.secrets_user().is_some();
+        Self::record_source_fetch(shared_data, user_id, uri, msg, time, read_secrets, res)
+    }
+
+    /// Runs the script to fetch a source and stores the result.
+    ///
+    /// Returns the resulting source version hash, or None if the script failed. The log and
+    /// script run time are written to `msg` and `time`.
+    #[allow(clippy::too_many_arguments)]
+    fn run_source_fetch(
+        shared_data: &SharedData,
+        user_id: Option<UserId>,
+        uri: &Url,
+        domain: &DomainSnapshot,
+        host: &FetchHost,
+        script_revision: Option<i32>,
+        options: &DomainOptionValues,
+        evt_users: Vec<UserId>,
+        msg: &mut Vec<FetchMsg>,
+        time: &mut Option<FetchTime>,
+    ) -> Result<Option<String>, FetchError> {
+        let domain_name = domain.id().to_string();
+        let options_hash = options_hash(options);
+        let previous = Self::previous_source(
+            &shared_data.lock(),
+            &uri.to_string(),
+            options_hash.as_deref(),
+            user_id,
+        )?;
 
         let evt = DispatchUserEvent::new(protocol::Event::SourceFetchDidBegin {
             source: uri.to_string(),
@@ -74,18 +281,67 @@ impl Fetcher {
                 .do_send(UserMgrDispatchEvent(*user, evt.clone()));
         }
 
-        let (msg, res) = script::fetch_source(&domain_name, domain.script(), uri.path());
+        let (script_msg, script_time, res) = script::fetch_source(
+            host,
+            &domain_name,
+            domain.script(),
+            uri.path(),
+            previous.as_ref(),
+            options,
+        );
+        *msg = script_msg;
+        *time = script_time;
 
         match res {
             Ok(mut source) => {
+                // archive item resources before acquiring a connection, since this may take a while
+                let archive_deadline = Instant::now() + ARCHIVE_TIMEOUT;
+                let mut item_data = Vec::new();
+                for meta_item in &source.items {
+                    if let Some(source_item) = source.item_data.remove(&meta_item.path) {
+                        let mut item = SourceItemData {
+                            tags: source_item.tags,
+                        };
+                        let resources = archive_item_resources(
+                            &mut item,
+                            host.allowed_hosts().as_deref(),
+                            archive_deadline,
+                        );
+                        item_data.push((
+                            meta_item.path.clone(),
+                            item,
+                            source_item.last_updated,
+                            resources,
+                        ));
+                    }
+                }
+
                 let data = shared_data.lock();
                 let uri = uri.to_string();
                 let date = Utc::now();
+                let items = match domain.cumulative() {
+                    Some(settings) => merge_cumulative_items(
+                        &data,
+                        &uri,
+                        settings,
+                        previous.map_or(Vec::new(), |previous| previous.items),
+                        source.items,
+                    )?,
+                    None => source.items,
+                };
                 let hash = data.create_source_version(
                     &uri,
                     &SourceMetadata { tags: source.tags },
-                    &source.items,
+                    &items,
                     source.last_updated.as_ref().map(|s| &**s),
+                    &SourceSchedule {
+                        next_update: source.next_update,
+                        update_schedule: source.update_schedule,
+                        completed: source.completed,
+                    },
+                    options_hash.as_deref(),
+                    script_revision,
+                    host.secrets_user(),
                 )?;
 
                 let evt = DispatchUserEvent::new(protocol::Event::SourceFetchDidEnd {
@@ -100,45 +356,40 @@ impl Fetcher {
                         .do_send(UserMgrDispatchEvent(*user, evt.clone()));
                 }
 
-                if !source.item_data.is_empty() {
-                    for meta_item in &source.items {
-                        if let Some(source_item) = source.item_data.remove(&meta_item.path) {
-                            let mut item_uri = String::from(&domain_name);
-                            item_uri.push_str("://");
-                            item_uri.push_str(&meta_item.path);
-                            let item_uri = match canonicalize_uri(&item_uri) {
-                                Ok(uri) => uri.to_string(),
-                                Err(_) => continue,
-                            };
-
-                            let hash = data.create_source_item_version(
-                                &item_uri,
-                                SourceItemData {
-                                    tags: source_item.tags,
-                                },
-                                source_item.last_updated.as_ref().map(|s| &**s),
-                            )?;
-
-                            let evt = DispatchUserEvent::new(
-                                protocol::Event::SubscribedSourceItemDidUpdate {
-                                    source_item: uri.clone(),
-                                    update_type: UpdateType::Update,
-                                },
-                            );
-                            for user in &evt_users {
-                                data.user_update_source_item(*user, &item_uri, date, &hash)?;
-                                shared_data
-                                    .users()
-                                    .do_send(UserMgrDispatchEvent(*user, evt.clone()));
-                            }
-                        }
+                for (path, item, last_updated, resources) in item_data {
+                    let mut item_uri = String::from(&domain_name);
+                    item_uri.push_str("://");
+                    item_uri.push_str(&path);
+                    let item_uri = match canonicalize_uri(&item_uri) {
+                        Ok(uri) => uri.to_string(),
+                        Err(_) => continue,
+                    };
+
+                    let hash = Self::create_source_item_version(
+                        &data,
+                        &item_uri,
+                        item,
+                        last_updated.as_ref().map(|s| &**s),
+                        &resources,
+                        host.secrets_user(),
+                    )?;
+
+                    let evt =
+                        DispatchUserEvent::new(protocol::Event::SubscribedSourceItemDidUpdate {
+                            source_item: uri.clone(),
+                            update_type: UpdateType::Update,
+                        });
+                    for user in &evt_users {
+                        data.user_update_source_item(*user, &item_uri, date, &hash)?;
+                        shared_data
+                            .users()
+                            .do_send(UserMgrDispatchEvent(*user, evt.clone()));
                     }
                 }
 
-                Ok((msg, Some(hash)))
+                Ok(Some(hash))
             }
             Err(err) => {
-                let mut msg = msg;
                 msg.push(FetchMsg {
                     time: None,
                     msg: ConsoleMessage {
@@ -158,10 +409,14 @@ impl Fetcher {
                         .do_send(UserMgrDispatchEvent(user, evt.clone()));
                 }
 
-                Ok((msg, None))
+                Ok(None)
             }
         }
     }
+    /// Fetches a source item.
+    ///
+    /// Like [Fetcher::fetch_source], the global fetcher fetches the item once for every set of
+    /// domain options among the subscribed users.
     pub fn fetch_source_item(
         shared_data: &SharedData,
         user_id: Option<UserId>,
@@ -176,14 +431,44 @@ impl Fetcher {
             None => return Err(FetchError::DomainNotFound(domain_name)),
         };
 
-        let evt_users = if let Some(user) = user_id {
-            vec![user]
+        let groups = if let Some(user) = user_id {
+            let options = data.effective_domain_options(Some(user), &domain_name)?;
+            let allowed_hosts = data.user_domain_allowed_hosts(user, &domain)?;
+            vec![(options, allowed_hosts, vec![user])]
         } else {
-            data.source_item_get_subscribed_users(&uri.to_string())?
+            let users = data.source_item_get_subscribed_users(&uri.to_string())?;
+            Self::group_users(&data, &domain, users)?
         };
 
         drop(data);
 
+        for (options, allowed_hosts, evt_users) in groups {
+            Self::fetch_source_item_with_options(
+                shared_data,
+                user_id,
+                &uri,
+                &domain,
+                &options,
+                allowed_hosts,
+                evt_users,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Fetches a source item with the given domain options and allowed hosts and updates the
+    /// given users.
+    fn fetch_source_item_with_options(
+        shared_data: &SharedData,
+        user_id: Option<UserId>,
+        uri: &Url,
+        domain: &DomainSnapshot,
+        options: &DomainOptionValues,
+        allowed_hosts: Option<Vec<String>>,
+        evt_users: Vec<UserId>,
+    ) -> Result<(), FetchError> {
+        let domain_name = domain.id();
+
         let evt = DispatchUserEvent::new(protocol::Event::SourceItemFetchDidBegin {
             source_item: uri.to_string(),
         });
@@ -193,33 +478,43 @@ impl Fetcher {
                 .do_send(UserMgrDispatchEvent(*user, evt.clone()));
         }
 
-        let (msg, res) = script::fetch_source_item(&domain_name, domain.script(), uri.path());
+        let host = match user_id {
+            Some(user_id) => FetchHost::for_user(shared_data, domain, user_id),
+            None => FetchHost::new(shared_data, domain),
+        }
+        .with_allowed_hosts(allowed_hosts);
+        let (msg, res) =
+            script::fetch_source_item(&host, domain_name, domain.script(), uri.path(), options);
 
         match res {
             Ok(source_item) => {
+                let mut item = SourceItemData {
+                    tags: source_item.tags,
+                };
+                let resources = archive_item_resources(
+                    &mut item,
+                    host.allowed_hosts().as_deref(),
+                    Instant::now() + ARCHIVE_TIMEOUT,
+                );
+
                 let data = shared_data.lock();
                 let uri = uri.to_string();
                 let date = Utc::now();
-                let hash = data.create_source_item_version(
+                let hash = Self::create_source_item_version(
+                    &data,
                     &uri,
-                    SourceItemData {
-                        tags: source_item.tags,
-                    },
+                    item,
                     source_item.last_updated.as_ref().map(|s| &**s),
+                    &resources,
+                    host.secrets_user(),
                 )?;
 
-                let users = if let Some(user) = user_id {
-                    vec![user]
-                } else {
-                    data.source_item_get_subscribed_users(&uri)?
-                };
-
                 let evt = DispatchUserEvent::new(protocol::Event::SourceItemFetchDidEnd {
                     source_item: uri.clone(),
                     success: true,
                     log: msg.into_iter().map(|x| x.into()).collect(),
                 });
-                for user in users {
+                for user in evt_users {
                     data.user_update_source_item(user, &uri, date, &hash)?;
                     shared_data
                         .users()
@@ -251,6 +546,197 @@ impl Fetcher {
 
         Ok(())
     }
+
+    /// Runs a script for a domain without storing any results.
+    ///
+    /// Changes the script makes to domain storage and cookies are discarded, and no users will be
+    /// notified. Returns the fetched data as it would have been stored.
+    /// Like in a regular fetch, sources are given their latest stored version, and the script is
+    /// given the domain options and may access the secrets of the user that requested the test run.
+    pub fn test_run(
+        shared_data: &SharedData,
+        user_id: UserId,
+        domain: &DomainSnapshot,
+        script: &str,
+        kind: TestRunKind,
+        path: &str,
+    ) -> (Vec<FetchMsg>, Result<serde_json::Value, ScriptError>) {
+        let host = TestRunHost::for_user(shared_data, domain, user_id);
+        let options = shared_data
+            .lock()
+            .effective_domain_options(Some(user_id), domain.id())
+            .unwrap_or_else(|err| {
+                error!("Failed to load domain options: {}", err);
+                DomainOptionValues::new()
+            });
+        let (mut msg, res) = match kind {
+            TestRunKind::Source => {
+                let options_hash = options_hash(&options);
+                let previous = match canonicalize_uri(&format!("{}://{}", domain.id(), path)) {
+                    Ok(uri) => Self::previous_source(
+                        &shared_data.lock(),
+                        &uri.to_string(),
+                        options_hash.as_deref(),
+                        Some(user_id),
+                    )
+                    .unwrap_or_else(|err| {
+                        error!("Failed to load previous source version: {}", err);
+                        None
+                    }),
+                    Err(()) => None,
+                };
+                let (msg, _, res) = script::fetch_source(
+                    &host,
+                    domain.id(),
+                    script,
+                    path,
+                    previous.as_ref(),
+                    &options,
+                );
+                (msg, res.and_then(|data| Ok(serde_json::to_value(data)?)))
+            }
+            TestRunKind::SourceItem => {
+                let (msg, res) =
+                    script::fetch_source_item(&host, domain.id(), script, path, &options);
+                (msg, res.and_then(|data| Ok(serde_json::to_value(data)?)))
+            }
+            TestRunKind::Search => {
+                let (msg, res) = script::search_sources(&host, domain.id(), script, path);
+                (msg, res.and_then(|data| Ok(serde_json::to_value(data)?)))
+            }
+        };
+
+        if let Err(err) = &res {
+            msg.push(FetchMsg {
+                time: None,
+                msg: ConsoleMessage {
+                    msg_type: MessageType::Error,
+                    message: vec![MsgFrag::Log(format!("{}", err))],
+                },
+            });
+        }
+
+        (msg, res)
+    }
+
+    /// Searches a domain for sources using its script.
+    pub fn search(
+        shared_data: &SharedData,
+        domain: &DomainSnapshot,
+        query: &str,
+    ) -> (Vec<FetchMsg>, Result<Vec<SearchResult>, ScriptError>) {
+        let host = FetchHost::new(shared_data, domain);
+        let (mut msg, mut res) = script::search_sources(&host, domain.id(), domain.script(), query);
+
+        match &mut res {
+            Ok(results) if results.len() > MAX_SEARCH_RESULTS => {
+                msg.push(FetchMsg {
+                    time: None,
+                    msg: ConsoleMessage {
+                        msg_type: MessageType::Warn,
+                        message: vec![MsgFrag::Log(format!(
+                            "script returned {} results; only the first {} are used",
+                            results.len(),
+                            MAX_SEARCH_RESULTS
+                        ))],
+                    },
+                });
+                results.truncate(MAX_SEARCH_RESULTS);
+            }
+            Ok(_) => (),
+            Err(err) => msg.push(FetchMsg {
+                time: None,
+                msg: ConsoleMessage {
+                    msg_type: MessageType::Error,
+                    message: vec![MsgFrag::Log(format!("{}", err))],
+                },
+            }),
+        }
+
+        (msg, res)
+    }
+
+    /// Returns the latest stored version of a source, which is passed to the script so that it may
+    /// fetch the source incrementally.
+    ///
+    /// Versions fetched using the secrets of a user other than `user_id` are never returned.
+    fn previous_source(
+        data: &Data,
+        uri: &str,
+        options_hash: Option<&str>,
+        user_id: Option<UserId>,
+    ) -> Result<Option<PreviousSource>, DataError> {
+        let version =
+            match data.latest_user_source_version_with_options(uri, options_hash, user_id)? {
+                Some(hash) => data.source_by_hash(&hash)?,
+                None => None,
+            };
+        let version = match version {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        match (version.tags(), version.items()) {
+            (Ok(tags), Ok(items)) => Ok(Some(PreviousSource {
+                last_updated: version.date_updated().map(String::from),
+                tags,
+                items,
+            })),
+            (Err(err), _) | (_, Err(err)) => {
+                // fall back to a full fetch
+                error!("Failed to decode source version of {}: {}", uri, err);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Groups users by their domain options and allowed hosts, so that a source can be fetched once
+    /// for each group. If there are no users, there is one group with the default options and the
+    /// domain's allowed hosts.
+    fn group_users(
+        data: &Data,
+        domain: &DomainSnapshot,
+        users: Vec<UserId>,
+    ) -> Result<FetchGroups, DataError> {
+        let mut groups: FetchGroups = Vec::new();
+        for user in users {
+            let options = data.effective_domain_options(Some(user), domain.id())?;
+            let allowed_hosts = data.user_domain_allowed_hosts(user, domain)?;
+            match groups
+                .iter_mut()
+                .find(|(o, h, _)| *o == options && *h == allowed_hosts)
+            {
+                Some((_, _, users)) => users.push(user),
+                None => groups.push((options, allowed_hosts, vec![user])),
+            }
+        }
+        if groups.is_empty() {
+            groups.push((
+                data.effective_domain_options(None, domain.id())?,
+                domain.allowed_hosts(),
+                Vec::new(),
+            ));
+        }
+        Ok(groups)
+    }
+
+    /// Creates a source item version along with its archived resources.
+    ///
+    /// `secrets_user_id` is the user whose secrets the script read while fetching, if any.
+    fn create_source_item_version(
+        data: &Data,
+        uri: &str,
+        item: SourceItemData,
+        date_updated: Option<&str>,
+        resources: &[SourceResourceData],
+        secrets_user_id: Option<UserId>,
+    ) -> Result<String, FetchError> {
+        let hash = data.create_source_item_version(uri, item, date_updated, secrets_user_id)?;
+        for resource in resources {
+            data.create_source_item_resource(&hash, resource)?;
+        }
+        Ok(hash)
+    }
 }
 
 impl Actor for Fetcher {
@@ -0,0 +1,18 @@
+//! Network policy for outgoing requests made by scripts and by the server.
+
+use crate::config::Config;
+use aof_script::url::Url;
+use aof_script::NetworkPolicy;
+use std::net::SocketAddr;
+
+/// Returns the network policy for outgoing requests, which is the default policy if none is
+/// configured.
+pub fn network_policy() -> NetworkPolicy {
+    Config::shared().network.clone().unwrap_or_default()
+}
+
+/// Checks whether the server may access a URL on behalf of a user (e.g. to proxy or archive a
+/// resource), and returns the address the request must be sent to.
+pub fn request_fetch_permission(url: &Url) -> Result<SocketAddr, String> {
+    aof_script::request_fetch_permission(url, &network_policy(), |_| {})
+}
@@ -0,0 +1,125 @@
+//! Server-wide rate limiting of outgoing requests.
+//!
+//! Scripts run in separate processes, so they have to ask the server for a request slot before
+//! each request (see [reserve_request]). Requests are limited per host name.
+
+use crate::config::{Config, RateLimit};
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Rate limit used if none is configured, which does not limit requests.
+const DEFAULT_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 0,
+    interval: 0,
+};
+
+/// Max time a request may be delayed. If exceeded, the request will fail.
+pub const MAX_WAIT_TIME: Duration = Duration::from_secs(120);
+
+/// Number of hosts after which stale hosts will be removed.
+const HOST_CLEANUP_THRESHOLD: usize = 256;
+
+lazy_static! {
+    static ref HOSTS: Mutex<HashMap<String, HostSlots>> = Mutex::new(HashMap::new());
+}
+
+/// Reserved request slots for a host.
+struct HostSlots {
+    interval: Duration,
+    /// Start times of the most recent requests, in ascending order.
+    slots: VecDeque<Instant>,
+}
+
+impl HostSlots {
+    fn is_stale(&self, now: Instant) -> bool {
+        self.slots
+            .back()
+            .map_or(true, |t| *t + self.interval <= now)
+    }
+}
+
+fn rate_limit_for_host(host: &str) -> RateLimit {
+    let config = Config::shared();
+    match &config.rate_limit {
+        Some(rate_limit) => rate_limit
+            .hosts
+            .get(host)
+            .copied()
+            .unwrap_or(rate_limit.default),
+        None => DEFAULT_RATE_LIMIT,
+    }
+}
+
+/// Reserves a slot for a request to the given host.
+///
+/// Returns the time the caller must wait before making the request.
+pub fn reserve_request(host: &str) -> Result<Duration, String> {
+    match reserve_request_within(host, MAX_WAIT_TIME) {
+        Some(wait) => Ok(wait),
+        None => Err(format!(
+            "too many requests to host {:?}, try again later",
+            host.to_ascii_lowercase()
+        )),
+    }
+}
+
+/// Reserves a slot for a request to the given host, but fails if the caller would have to wait.
+pub fn reserve_request_now(host: &str) -> Result<(), String> {
+    match reserve_request_within(host, Duration::from_secs(0)) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "too many requests to host {:?}, try again later",
+            host.to_ascii_lowercase()
+        )),
+    }
+}
+
+/// Reserves a slot for a request to the given host, unless the caller would have to wait longer
+/// than `max_wait`.
+///
+/// Returns the time the caller must wait before making the request, or None if no slot was
+/// reserved.
+pub fn reserve_request_within(host: &str, max_wait: Duration) -> Option<Duration> {
+    let host = host.to_ascii_lowercase();
+    let limit = rate_limit_for_host(&host);
+    if limit.requests == 0 {
+        return Some(Duration::from_secs(0));
+    }
+    let interval = Duration::from_secs(limit.interval);
+    let now = Instant::now();
+
+    let mut hosts = HOSTS.lock().unwrap();
+    if hosts.len() > HOST_CLEANUP_THRESHOLD {
+        hosts.retain(|_, host| !host.is_stale(now));
+    }
+
+    let host_slots = hosts.entry(host.clone()).or_insert_with(|| HostSlots {
+        interval,
+        slots: VecDeque::new(),
+    });
+    host_slots.interval = interval;
+    let slots = &mut host_slots.slots;
+    while slots.len() as u64 > limit.requests {
+        // the limit was lowered
+        slots.pop_front();
+    }
+
+    let slot = if slots.len() as u64 == limit.requests {
+        now.max(slots[0] + interval)
+    } else {
+        now
+    };
+    let wait = slot - now;
+    if wait > max_wait {
+        return None;
+    }
+
+    if slots.len() as u64 == limit.requests {
+        slots.pop_front();
+    }
+    slots.push_back(slot);
+
+    Some(wait)
+}
@@ -0,0 +1,212 @@
+//! Resolving web URLs to sources, using the URL patterns exported by domain scripts.
+
+use super::host::TestRunHost;
+use super::script;
+use crate::data::domains::DomainSnapshot;
+use crate::data::sources::canonicalize_uri;
+use crate::data::users::UserId;
+use crate::data::DataError;
+use crate::state::SharedData;
+use aof_script::UrlPatterns;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Max size of a compiled URL pattern in bytes.
+const URL_PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// Max number of compiled URL patterns kept in memory. The cache is cleared once exceeded.
+const URL_PATTERN_CACHE_MAX_LEN: usize = 1024;
+
+/// Max number of scripts of public domains that will be run in a single request to get their URL
+/// patterns. Other public domains with uncached patterns are skipped until a later request.
+const MAX_UNCACHED_PUBLIC_DOMAINS: usize = 4;
+
+lazy_static! {
+    /// Compiled URL patterns by source. Invalid patterns are stored as None.
+    static ref URL_PATTERN_CACHE: Mutex<HashMap<String, Option<Regex>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// How a domain is related to the user resolving a URL. Results are ranked in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum DomainRelation {
+    #[serde(rename = "owned")]
+    Owned,
+    #[serde(rename = "subscribed")]
+    Subscribed,
+    #[serde(rename = "public")]
+    Public,
+}
+
+/// A source that corresponds to a web URL.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedUrl {
+    pub uri: String,
+    pub domain: String,
+    pub relation: DomainRelation,
+}
+
+/// Finds sources corresponding to a web URL in all domains the user can access.
+///
+/// Domains whose URL patterns are not cached yet will have their script run to get them, so this
+/// may take a while. To bound this, only a few public domains with uncached patterns are
+/// considered per call (see [MAX_UNCACHED_PUBLIC_DOMAINS]).
+pub fn resolve_url(
+    shared_data: &SharedData,
+    user_id: UserId,
+    url: &str,
+) -> Result<Vec<ResolvedUrl>, DataError> {
+    let mut domains = Vec::new();
+    {
+        let data = shared_data.lock();
+        let mut ids = data.user_full_domain_ids(user_id)?;
+        for id in data.public_domain_ids()? {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        for id in ids {
+            if let Some(domain) = data.domain_by_domain_id(&id)? {
+                let relation = if domain.owner_id() == user_id {
+                    DomainRelation::Owned
+                } else if data.is_user_subscribed(user_id, &domain)? {
+                    DomainRelation::Subscribed
+                } else {
+                    DomainRelation::Public
+                };
+                domains.push((relation, domain));
+            }
+        }
+    }
+    domains.sort_by_key(|(relation, _)| *relation);
+
+    let mut results = Vec::new();
+    let mut uncached_public_domains = 0;
+    for (relation, domain) in domains {
+        let patterns = match cached_url_patterns(shared_data, &domain)? {
+            Some(patterns) => patterns,
+            None if relation == DomainRelation::Public
+                && uncached_public_domains >= MAX_UNCACHED_PUBLIC_DOMAINS =>
+            {
+                continue
+            }
+            None => {
+                if relation == DomainRelation::Public {
+                    uncached_public_domains += 1;
+                }
+                load_url_patterns(shared_data, &domain)?
+            }
+        };
+        let path = match match_url_path(shared_data, &domain, &patterns, url) {
+            Some(path) => path,
+            None => continue,
+        };
+        if let Ok(uri) = canonicalize_uri(&format!("{}://{}", domain.id(), path)) {
+            results.push(ResolvedUrl {
+                uri: uri.to_string(),
+                domain: domain.id().into(),
+                relation,
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+/// Returns the URL patterns of a domain from the cache.
+fn cached_url_patterns(
+    shared_data: &SharedData,
+    domain: &DomainSnapshot,
+) -> Result<Option<UrlPatterns>, DataError> {
+    if let Some(patterns) = shared_data.lock().domain_url_patterns(domain.id())? {
+        match serde_json::from_str(&patterns) {
+            Ok(patterns) => return Ok(Some(patterns)),
+            Err(err) => warn!(
+                "Failed to decode URL patterns of domain {}: {}",
+                domain.id(),
+                err
+            ),
+        }
+    }
+    Ok(None)
+}
+
+/// Runs the script of a domain to get its URL patterns, and caches them.
+fn load_url_patterns(
+    shared_data: &SharedData,
+    domain: &DomainSnapshot,
+) -> Result<UrlPatterns, DataError> {
+    let host = TestRunHost::new(shared_data, domain);
+    let (_, res) = script::url_patterns(&host, domain.id(), domain.script());
+    // scripts that fail are treated as having no patterns until they are updated
+    let patterns = res.unwrap_or_else(|err| {
+        debug!(
+            "Failed to get URL patterns of domain {}: {}",
+            domain.id(),
+            err
+        );
+        UrlPatterns::default()
+    });
+
+    if let Ok(encoded) = serde_json::to_string(&patterns) {
+        shared_data
+            .lock()
+            .domain_url_patterns_set(domain.id(), &encoded)?;
+    }
+    Ok(patterns)
+}
+
+/// Compiles a URL pattern such that it must match the whole URL, or returns it from the cache.
+/// Returns None if the pattern is invalid.
+fn compile_url_pattern(pattern: &str) -> Option<Regex> {
+    let mut cache = URL_PATTERN_CACHE.lock().unwrap();
+    if let Some(regex) = cache.get(pattern) {
+        return regex.clone();
+    }
+    if cache.len() >= URL_PATTERN_CACHE_MAX_LEN {
+        cache.clear();
+    }
+
+    let regex = RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .size_limit(URL_PATTERN_SIZE_LIMIT)
+        .build()
+        .ok();
+    cache.insert(pattern.to_string(), regex.clone());
+    regex
+}
+
+/// Returns the source path for a URL if it matches any of the patterns.
+///
+/// The path is either the `path` capture group of the first matching pattern, or determined by
+/// the script's `matchUrl` function if it has one.
+fn match_url_path(
+    shared_data: &SharedData,
+    domain: &DomainSnapshot,
+    patterns: &UrlPatterns,
+    url: &str,
+) -> Option<String> {
+    let captured_path = patterns.patterns.iter().find_map(|pattern| {
+        // invalid patterns are ignored
+        let regex = compile_url_pattern(pattern)?;
+        regex
+            .captures(url)
+            .map(|captures| captures.name("path").map(|path| path.as_str().to_string()))
+    })?;
+
+    let path = if patterns.match_url {
+        let host = TestRunHost::new(shared_data, domain);
+        let (_, res) = script::match_url(&host, domain.id(), domain.script(), url);
+        res.unwrap_or_else(|err| {
+            debug!("Failed to match URL in domain {}: {}", domain.id(), err);
+            None
+        })
+    } else {
+        captured_path
+    };
+
+    path.filter(|path| !path.is_empty())
+}
@@ -1,63 +1,74 @@
-use crate::data::sources::SourceMetaItem;
-use serde::Deserialize;
-use serde_json::Value;
-use std::collections::BTreeMap;
 use thiserror::Error;
 
+mod pool;
 mod script;
 
-pub use script::{request_fetch_permission, run_ipc_fork, FetchMsg, FetchTime};
-
-/// Source data output from a script.
-#[derive(Deserialize, Debug, Clone)]
-pub struct SourceFetchData {
-    #[serde(default)]
-    pub last_updated: Option<String>,
-    pub tags: BTreeMap<String, Value>,
-    #[serde(default)]
-    pub items: Vec<SourceMetaItem>,
-    #[serde(default)]
-    pub item_data: BTreeMap<String, SourceItemFetchData>,
-}
-
-/// Source item data output from a script.
-#[derive(Deserialize, Debug, Clone)]
-pub struct SourceItemFetchData {
-    #[serde(default)]
-    pub last_updated: Option<String>,
-    pub tags: BTreeMap<String, Value>,
-}
+use crate::data::domain_options::DomainOptionValues;
+use aof_script::console::{ConsoleMessage, MessageType, MsgFrag};
+use aof_script::{
+    IssueLevel, PreviousSource, ResultIssue, SearchResult, SourceFetchData, SourceItemFetchData,
+    UrlPatterns,
+};
+pub use pool::init_worker_pool;
+pub use script::{run_ipc_fork, FetchMsg, FetchTime, ScriptHost};
 
 /// Errors that may occur when running a script.
 #[derive(Debug, Error)]
 pub enum ScriptError {
     #[error("failed to parse script response: {0}")]
     Parse(#[from] serde_json::Error),
+    #[error("script returned an invalid result (see log for details)")]
+    Invalid,
     #[error("script timed out (infinite loop?)")]
     Timeout,
+    #[error("script exceeded its memory limit")]
+    OutOfMemory,
     #[error("failed to run script: {0}")]
     Script(String),
 }
 
 /// Fetches a source.
+///
+/// If the previous version of the source is given, the script may fetch it incrementally.
+/// The domain options are passed to the script as they are.
+/// Also returns how long the script ran, if it was run.
 pub fn fetch_source(
+    host: &dyn ScriptHost,
     domain: &str,
     script: &str,
     path: &str,
-) -> (Vec<FetchMsg>, Result<SourceFetchData, ScriptError>) {
+    previous: Option<&PreviousSource>,
+    options: &DomainOptionValues,
+) -> (
+    Vec<FetchMsg>,
+    Option<FetchTime>,
+    Result<SourceFetchData, ScriptError>,
+) {
     let mut messages = Vec::new();
-    let result = script::run_request(
+    let previous = match previous.map(serde_json::to_string).transpose() {
+        Ok(previous) => previous,
+        Err(err) => return (messages, None, Err(err.into())),
+    };
+    let options = match serde_json::to_string(options) {
+        Ok(options) => options,
+        Err(err) => return (messages, None, Err(err.into())),
+    };
+    let (time, result) = script::run_request(
         script::Fetch::Source {
             domain: domain.into(),
             script: script.into(),
             path: path.into(),
+            previous,
+            options,
         },
+        host,
         &mut messages,
     );
 
     let result = match result {
-        Ok(data) => serde_json::from_value(data).map_err(ScriptError::from),
+        Ok(data) => validated(aof_script::validate_source(data), &mut messages),
         Err(script::ScriptError::Timeout) => Err(ScriptError::Timeout),
+        Err(script::ScriptError::OutOfMemory) => Err(ScriptError::OutOfMemory),
         Err(script::ScriptError::NoResult) => Err(ScriptError::Script(String::from(
             "script execution ended with no result",
         ))),
@@ -65,28 +76,37 @@ pub fn fetch_source(
         Err(script::ScriptError::Fatal(err)) => Err(ScriptError::Script(err)),
     };
 
-    (messages, result)
+    (messages, Some(time), result)
 }
 
 /// Fetches a source item.
 pub fn fetch_source_item(
+    host: &dyn ScriptHost,
     domain: &str,
     script: &str,
     path: &str,
+    options: &DomainOptionValues,
 ) -> (Vec<FetchMsg>, Result<SourceItemFetchData, ScriptError>) {
     let mut messages = Vec::new();
-    let result = script::run_request(
+    let options = match serde_json::to_string(options) {
+        Ok(options) => options,
+        Err(err) => return (messages, Err(err.into())),
+    };
+    let (_, result) = script::run_request(
         script::Fetch::SourceItem {
             domain: domain.into(),
             script: script.into(),
             path: path.into(),
+            options,
         },
+        host,
         &mut messages,
     );
 
     let result = match result {
-        Ok(data) => serde_json::from_value(data).map_err(ScriptError::from),
+        Ok(data) => validated(aof_script::validate_source_item(data), &mut messages),
         Err(script::ScriptError::Timeout) => Err(ScriptError::Timeout),
+        Err(script::ScriptError::OutOfMemory) => Err(ScriptError::OutOfMemory),
         Err(script::ScriptError::NoResult) => Err(ScriptError::Script(String::from(
             "script completed with no result",
         ))),
@@ -96,3 +116,99 @@ pub fn fetch_source_item(
 
     (messages, result)
 }
+
+/// Searches for sources.
+pub fn search_sources(
+    host: &dyn ScriptHost,
+    domain: &str,
+    script: &str,
+    query: &str,
+) -> (Vec<FetchMsg>, Result<Vec<SearchResult>, ScriptError>) {
+    run_validated(
+        script::Fetch::Search {
+            domain: domain.into(),
+            script: script.into(),
+            query: query.into(),
+        },
+        host,
+        aof_script::validate_search_results,
+    )
+}
+
+/// Returns the URL patterns of a domain.
+pub fn url_patterns(
+    host: &dyn ScriptHost,
+    domain: &str,
+    script: &str,
+) -> (Vec<FetchMsg>, Result<UrlPatterns, ScriptError>) {
+    run_validated(
+        script::Fetch::UrlPatterns {
+            domain: domain.into(),
+            script: script.into(),
+        },
+        host,
+        aof_script::validate_url_patterns,
+    )
+}
+
+/// Returns the source path for a web URL, using the domain's `matchUrl` function.
+pub fn match_url(
+    host: &dyn ScriptHost,
+    domain: &str,
+    script: &str,
+    url: &str,
+) -> (Vec<FetchMsg>, Result<Option<String>, ScriptError>) {
+    let (messages, result) = run_validated(
+        script::Fetch::MatchUrl {
+            domain: domain.into(),
+            script: script.into(),
+            url: url.into(),
+        },
+        host,
+        aof_script::validate_url_match,
+    );
+    (messages, result.map(|m| m.path))
+}
+
+/// Runs a request and validates the result.
+fn run_validated<T>(
+    request: script::Fetch,
+    host: &dyn ScriptHost,
+    validate: fn(serde_json::Value) -> (Option<T>, Vec<ResultIssue>),
+) -> (Vec<FetchMsg>, Result<T, ScriptError>) {
+    let mut messages = Vec::new();
+    let (_, result) = script::run_request(request, host, &mut messages);
+
+    let result = match result {
+        Ok(data) => validated(validate(data), &mut messages),
+        Err(script::ScriptError::Timeout) => Err(ScriptError::Timeout),
+        Err(script::ScriptError::OutOfMemory) => Err(ScriptError::OutOfMemory),
+        Err(script::ScriptError::NoResult) => Err(ScriptError::Script(String::from(
+            "script completed with no result",
+        ))),
+        Err(script::ScriptError::Exec(err)) => Err(ScriptError::Script(err)),
+        Err(script::ScriptError::Fatal(err)) => Err(ScriptError::Script(err)),
+    };
+
+    (messages, result)
+}
+
+/// Adds validation issues to the log and returns the result if it was valid.
+fn validated<T>(
+    (data, issues): (Option<T>, Vec<ResultIssue>),
+    messages: &mut Vec<FetchMsg>,
+) -> Result<T, ScriptError> {
+    for issue in issues {
+        messages.push(FetchMsg {
+            time: None,
+            msg: ConsoleMessage {
+                msg_type: match issue.level {
+                    IssueLevel::Error => MessageType::Error,
+                    IssueLevel::Warning => MessageType::Warn,
+                },
+                message: vec![MsgFrag::Log(issue.to_string())],
+            },
+        });
+    }
+    data.ok_or(ScriptError::Invalid)
+}
@@ -0,0 +1,285 @@
+//! Pool of script worker processes.
+//!
+//! Scripts are run in separate processes (see [super::script::run_ipc_fork]). Starting a process
+//! takes a while, so a number of idle workers is kept around and each worker is reused for several
+//! fetches before it is replaced.
+
+use super::script::{Fetch, FetchMsg, ScriptMsg, WorkerRequest};
+use crate::config::Config;
+use aof_script::console::{ConsoleMessage, MessageType, MsgFrag};
+use aof_script::{FetchMode, NetworkPolicy, ScriptLimits};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use ipc_channel::ipc::{IpcOneShotServer, IpcReceiver, IpcSender};
+use ipc_channel::router::ROUTER;
+use lazy_static::lazy_static;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Number of idle workers if not configured.
+const DEFAULT_WORKER_COUNT: usize = 2;
+
+/// Number of jobs after which a worker will be replaced, if not configured.
+const DEFAULT_WORKER_MAX_JOBS: usize = 32;
+
+/// Max number of bytes of stdout/stderr output that will be kept per job.
+const MAX_OUTPUT_LEN: usize = 65_536;
+
+lazy_static! {
+    static ref IDLE_WORKERS: Mutex<Vec<Worker>> = Mutex::new(Vec::new());
+}
+
+/// Number of workers currently being spawned in the background.
+static SPAWNING_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+fn worker_count() -> usize {
+    match &Config::shared().script_workers {
+        Some(config) => config.count,
+        None => DEFAULT_WORKER_COUNT,
+    }
+}
+
+fn worker_max_jobs() -> usize {
+    match &Config::shared().script_workers {
+        Some(config) => config.max_jobs.max(1),
+        None => DEFAULT_WORKER_MAX_JOBS,
+    }
+}
+
+#[derive(Default)]
+struct WorkerOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// A script worker process.
+pub(super) struct Worker {
+    process: Child,
+    req_send: IpcSender<WorkerRequest>,
+    recv: Receiver<ScriptMsg>,
+    output: Arc<Mutex<WorkerOutput>>,
+    output_readers: Vec<JoinHandle<()>>,
+    jobs: usize,
+}
+
+impl Worker {
+    fn spawn() -> Result<Worker, String> {
+        let (ipc_server, ipc_server_name) =
+            IpcOneShotServer::<(IpcSender<WorkerRequest>, IpcReceiver<ScriptMsg>)>::new()
+                .map_err(|e| format!("failed to open IPC server: {}", e))?;
+
+        let bin_path = std::env::current_exe()
+            .map_err(|_| String::from("failed to fork: could not find current executable"))?;
+        let mut process = Command::new(bin_path)
+            .args(&["--fetcher-ipc-fork", &ipc_server_name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to spawn child: {}", e))?;
+
+        // output needs to be read continuously, or the worker will block once the pipe is full
+        let output = Arc::new(Mutex::new(WorkerOutput::default()));
+        let output_readers = vec![
+            read_output(process.stdout.take().unwrap(), Arc::clone(&output), false),
+            read_output(process.stderr.take().unwrap(), Arc::clone(&output), true),
+        ];
+
+        let (_, (req_send, recv)) = ipc_server
+            .accept()
+            .map_err(|e| format!("Could not accept IPC connection: {}", e))?;
+
+        Ok(Worker {
+            process,
+            req_send,
+            recv: ROUTER.route_ipc_receiver_to_new_crossbeam_receiver(recv),
+            output,
+            output_readers,
+            jobs: 0,
+        })
+    }
+
+    /// Sends a fetch request to the worker.
+    pub(super) fn send_request(
+        &mut self,
+        request: Fetch,
+        limits: ScriptLimits,
+        fetch_mode: FetchMode,
+        allowed_hosts: Option<Vec<String>>,
+        network_policy: NetworkPolicy,
+    ) -> Result<(), String> {
+        self.jobs += 1;
+        self.req_send
+            .send((request, limits, fetch_mode, allowed_hosts, network_policy))
+            .map_err(|e| format!("failed to send request: {}", e))
+    }
+
+    /// Waits for the next message from the worker.
+    /// Waits for the next message from the worker, up to the given timeout.
+    pub(super) fn recv_timeout(&self, timeout: Duration) -> Result<ScriptMsg, RecvTimeoutError> {
+        self.recv.recv_timeout(timeout)
+    }
+
+    /// Moves the worker's stdout and stderr output into the messages.
+    pub(super) fn take_output(&self, messages: &mut Vec<FetchMsg>) {
+        let mut output = self.output.lock().unwrap();
+        let WorkerOutput { stdout, stderr } = &mut *output;
+        for (msg_type, buf) in
+            [(MessageType::Stdout, stdout), (MessageType::Stderr, stderr)].iter_mut()
+        {
+            if !buf.is_empty() {
+                messages.push(FetchMsg {
+                    time: None,
+                    msg: ConsoleMessage {
+                        msg_type: *msg_type,
+                        message: vec![MsgFrag::Log(String::from_utf8_lossy(buf).to_string())],
+                    },
+                });
+                buf.clear();
+            }
+        }
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.process.try_wait(), Ok(None))
+    }
+
+    /// Kills the worker and collects any remaining output.
+    fn kill(mut self, messages: &mut Vec<FetchMsg>) {
+        if let Err(e) = self.process.kill() {
+            match e.kind() {
+                std::io::ErrorKind::InvalidInput => (),
+                _ => {
+                    error!("Failed to kill fetcher: {}", e);
+                }
+            }
+        }
+        if let Err(e) = self.process.wait() {
+            error!("Failed to wait for fetcher: {}", e);
+        }
+        for reader in self.output_readers.drain(..) {
+            let _ = reader.join();
+        }
+        self.take_output(messages);
+    }
+
+    /// Lets the worker exit on its own.
+    fn shut_down(self) {
+        let Worker {
+            mut process,
+            req_send,
+            ..
+        } = self;
+        // the worker will exit once the request channel is closed
+        drop(req_send);
+        thread::spawn(move || {
+            if let Err(e) = process.wait() {
+                error!("Failed to wait for fetcher: {}", e);
+            }
+        });
+    }
+}
+
+fn read_output<R: Read + Send + 'static>(
+    mut pipe: R,
+    output: Arc<Mutex<WorkerOutput>>,
+    is_stderr: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => {
+                    let mut output = output.lock().unwrap();
+                    let output = if is_stderr {
+                        &mut output.stderr
+                    } else {
+                        &mut output.stdout
+                    };
+                    if output.len() < MAX_OUTPUT_LEN {
+                        output.extend_from_slice(&buf[..len]);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Spawns workers in the background until there are enough idle workers.
+fn replenish_workers() {
+    let count = worker_count();
+    loop {
+        let idle = IDLE_WORKERS.lock().unwrap().len();
+        let spawning = SPAWNING_WORKERS.load(Ordering::SeqCst);
+        if idle + spawning >= count {
+            break;
+        }
+        SPAWNING_WORKERS.fetch_add(1, Ordering::SeqCst);
+
+        thread::spawn(|| {
+            match Worker::spawn() {
+                Ok(worker) => {
+                    let mut idle = IDLE_WORKERS.lock().unwrap();
+                    if idle.len() < worker_count() {
+                        idle.push(worker);
+                    } else {
+                        drop(idle);
+                        worker.shut_down();
+                    }
+                }
+                Err(err) => error!("Failed to spawn script worker: {}", err),
+            }
+            SPAWNING_WORKERS.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Starts the initial workers in the background.
+pub fn init_worker_pool() {
+    replenish_workers();
+}
+
+/// Returns an idle worker, or spawns a new one if there are none.
+pub(super) fn take_worker() -> Result<Worker, String> {
+    loop {
+        let worker = IDLE_WORKERS.lock().unwrap().pop();
+        replenish_workers();
+        match worker {
+            Some(mut worker) => {
+                if worker.is_alive() {
+                    break Ok(worker);
+                }
+                // the worker crashed while idle; it shouldn't have any output worth keeping
+                worker.kill(&mut Vec::new());
+            }
+            None => break Worker::spawn(),
+        }
+    }
+}
+
+/// Returns a worker to the pool after it has finished a job.
+pub(super) fn return_worker(worker: Worker) {
+    if worker.jobs >= worker_max_jobs() {
+        worker.shut_down();
+        replenish_workers();
+        return;
+    }
+
+    let mut idle = IDLE_WORKERS.lock().unwrap();
+    if idle.len() < worker_count() {
+        idle.push(worker);
+    } else {
+        drop(idle);
+        worker.shut_down();
+    }
+}
+
+/// Kills a worker that is in an unknown state (e.g. after a timeout) and collects its output.
+pub(super) fn discard_worker(worker: Worker, messages: &mut Vec<FetchMsg>) {
+    worker.kill(messages);
+    replenish_workers();
+}
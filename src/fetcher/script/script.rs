@@ -1,21 +1,32 @@
-use aof_script::console::{ConsoleMessage, MessageType, MsgFrag};
+use super::pool::{discard_worker, return_worker, take_worker};
+use crate::config::ScriptLimitValues;
+use crate::fetcher::limits::runtime_script_limits;
+use crate::fetcher::network::network_policy;
+use crate::fetcher::rate_limit::MAX_WAIT_TIME;
+use crate::fetcher::{reserve_request, reserve_request_now};
+use aof_script::console::{redact_secrets, ConsoleMessage, MessageType, MsgFrag};
 use aof_script::reqwest;
-use aof_script::url::{self, Url};
-use aof_script::{AofRequest, InnerScript, ScriptContext};
-use ipc_channel::ipc::{IpcOneShotServer, IpcReceiver, IpcSender};
+use aof_script::url::Url;
+use aof_script::{
+    check_allowed_hosts, request_fetch_permission, AofRequest, CachedResponse, CassetteRequest,
+    FetchMode, HeapLimitExceeded, InnerScript, NetworkPolicy, ScriptContext, ScriptLimits,
+};
+use crossbeam_channel::RecvTimeoutError;
+use ipc_channel::ipc::IpcSender;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::io::Read;
-use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
-const SCRIPT_EXEC_TIME: Duration = Duration::from_secs(6);
-const MONITOR_SLEEP_TIME: Duration = Duration::from_millis(50);
-const MONITOR_EXIT_WAIT_TIME: Duration = Duration::from_millis(50);
+/// How long to wait for a worker to finish up after the script has returned a result.
+const WORKER_DONE_TIMEOUT: Duration = Duration::from_secs(1);
+const RATE_LIMIT_LOG_THRESHOLD: Duration = Duration::from_secs(1);
+/// Time the script timer may stay paused beyond the max rate limit wait and the request timeout.
+/// A worker that takes longer to continue the timer is assumed to be stuck and is killed.
+const TIMER_PAUSE_MARGIN: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Error, Deserialize, Serialize)]
 pub enum ScriptError {
@@ -23,12 +34,54 @@ pub enum ScriptError {
     Timeout,
     #[error("script ended without result")]
     NoResult,
+    #[error("script exceeded its memory limit")]
+    OutOfMemory,
     #[error("fatal: {0}")]
     Fatal(String),
     #[error("script execution error: {0}")]
     Exec(String),
 }
 
+/// Handles script requests that need access to server data.
+///
+/// This is implemented by the server process; calls are forwarded from the script process over
+/// the IPC channel.
+pub trait ScriptHost {
+    /// Returns the resource limits of the script.
+    fn script_limits(&self) -> ScriptLimitValues;
+    /// Returns a JSON-encoded value from the domain's storage.
+    fn storage_get(&self, key: &str) -> Result<Option<String>, String>;
+    /// Sets a JSON-encoded value in the domain's storage.
+    fn storage_set(&self, key: &str, value: &str) -> Result<(), String>;
+    /// Deletes a value from the domain's storage.
+    fn storage_delete(&self, key: &str) -> Result<(), String>;
+    /// Returns a secret of the user the fetch was requested by.
+    fn secret_get(&self, name: &str) -> Result<Option<String>, String>;
+    /// Returns the domain's JSON-encoded cookie jar.
+    fn cookies_get(&self) -> Result<Option<String>, String>;
+    /// Stores cookies from the Set-Cookie headers of a response from the given URL in the
+    /// domain's cookie jar.
+    fn cookies_store(&self, url: &str, set_cookies: &[String]) -> Result<(), String>;
+    /// Returns a response from the domain's HTTP cache.
+    fn http_cache_get(&self, key: &str) -> Result<Option<CachedResponse>, String>;
+    /// Stores a response in the domain's HTTP cache.
+    fn http_cache_set(&self, key: &str, response: &CachedResponse) -> Result<(), String>;
+    /// Returns how fetch requests from the script are handled.
+    fn fetch_mode(&self) -> FetchMode;
+    /// Returns the host patterns the script may access, or None if it may access any host.
+    fn allowed_hosts(&self) -> Option<Vec<String>>;
+    /// Returns a recorded response from the domain's cassette.
+    fn cassette_get(&self, request: &CassetteRequest) -> Result<Option<CachedResponse>, String>;
+    /// Records a response to the domain's cassette.
+    fn cassette_record(
+        &self,
+        request: CassetteRequest,
+        response: CachedResponse,
+    ) -> Result<(), String>;
+    /// Returns the script of a library domain, if the domain is allowed to import it.
+    fn load_library(&self, id: &str) -> Result<String, String>;
+}
+
 /// Inner struct for time metrics in a fetch context.
 struct FetchTimeMetrics {
     start_time: Instant,
@@ -76,121 +129,139 @@ impl FetchTimeMetrics {
 /// ScriptContext implementor.
 struct FetchContext {
     request: AofRequest,
+    limits: ScriptLimits,
+    fetch_mode: FetchMode,
+    allowed_hosts: Option<Vec<String>>,
+    network_policy: NetworkPolicy,
     sender: Mutex<IpcSender<ScriptMsg>>,
-    msg_sender: Mutex<IpcSender<FetchMsg>>,
     time: Mutex<FetchTimeMetrics>,
+    /// Secret values returned to the script, which are redacted from the log.
+    secrets: Mutex<Vec<String>>,
 }
 
 impl FetchContext {
-    fn new(request: AofRequest, send: IpcSender<ScriptMsg>, msg_send: IpcSender<FetchMsg>) -> Self {
+    /// Checks whether the script may access a URL, not counting the rate limit.
+    fn check_access(&self, url: &Url) -> Result<(), String> {
+        if let Some(allowed_hosts) = &self.allowed_hosts {
+            check_allowed_hosts(url, allowed_hosts)?;
+        }
+        request_fetch_permission(url, &self.network_policy, |addr| {
+            self.send_message(ConsoleMessage {
+                msg_type: MessageType::Warn,
+                message: vec![MsgFrag::Log(format!(
+                    "Direct access of ip address {:?}",
+                    addr
+                ))],
+            });
+        })?;
+        Ok(())
+    }
+
+    /// Sends a request to the server process and waits for the response.
+    fn host_request<T, F>(&self, create_msg: F) -> Result<T, String>
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+        F: FnOnce(IpcSender<Result<T, String>>) -> ScriptMsg,
+    {
+        let (send, recv) = ipc_channel::ipc::channel()
+            .map_err(|e| format!("failed to open IPC channel: {}", e))?;
+        self.sender
+            .lock()
+            .unwrap()
+            .send(create_msg(send))
+            .map_err(|e| format!("failed to send request: {}", e))?;
+        recv.recv()
+            .map_err(|e| format!("failed to receive response: {:?}", e))?
+    }
+
+    fn storage_request(&self, request: StorageRequest) -> Result<Option<String>, String> {
+        self.host_request(|reply| ScriptMsg::Storage(request, reply))
+    }
+
+    /// Sends a console message to the server process.
+    fn send_message(&self, mut msg: ConsoleMessage) {
+        msg.redact(&self.revealed_secrets());
+        let time = self.time.lock().unwrap().get_time();
+        self.sender
+            .lock()
+            .unwrap()
+            .send(ScriptMsg::Console(FetchMsg {
+                time: Some(time),
+                msg,
+            }))
+            .unwrap();
+    }
+
+    fn new(
+        request: AofRequest,
+        limits: ScriptLimits,
+        fetch_mode: FetchMode,
+        allowed_hosts: Option<Vec<String>>,
+        network_policy: NetworkPolicy,
+        send: IpcSender<ScriptMsg>,
+    ) -> Self {
         FetchContext {
             request,
+            limits,
+            fetch_mode,
+            allowed_hosts,
+            network_policy,
             sender: Mutex::new(send),
-            msg_sender: Mutex::new(msg_send),
             time: Mutex::new(FetchTimeMetrics {
                 start_time: Instant::now(),
                 fetch_time: Duration::from_secs(0),
                 current_fetch_start: None,
             }),
+            secrets: Mutex::new(Vec::new()),
         }
     }
 }
 
-// FIXME: delete when is_global is stable
-trait IpExt {
-    fn is_global(&self) -> bool;
-}
-impl IpExt for std::net::Ipv4Addr {
-    fn is_global(&self) -> bool {
-        // crude approximation
-        !self.is_loopback()
-            && !self.is_private()
-            && !self.is_link_local()
-            && !self.is_multicast()
-            && !self.is_broadcast()
-    }
-}
-impl IpExt for std::net::Ipv6Addr {
-    fn is_global(&self) -> bool {
-        // cruder approximation
-        !self.is_loopback() && !self.is_multicast()
+impl ScriptContext for FetchContext {
+    fn limits(&self) -> ScriptLimits {
+        self.limits
     }
-}
 
-fn add_fake_port(s: &str) -> String {
-    let mut s = String::from(s);
-    s += ":443";
-    s
-}
+    fn network_policy(&self) -> Option<NetworkPolicy> {
+        Some(self.network_policy.clone())
+    }
 
-pub fn request_fetch_permission<F>(url: &Url, on_direct_ip_access: F) -> Result<(), String>
-where
-    F: FnOnce(std::net::IpAddr),
-{
-    match url.scheme() {
-        "http" | "https" => match url.host() {
-            Some(url::Host::Domain(domain)) => match add_fake_port(domain).to_socket_addrs() {
-                Ok(addrs) => {
-                    for addr in addrs {
-                        let is_global = match addr {
-                            SocketAddr::V4(addr) => IpExt::is_global(addr.ip()),
-                            SocketAddr::V6(addr) => IpExt::is_global(addr.ip()),
-                        };
-                        if !is_global {
-                            return Err(format!(
-                                "error resolving host {:?}: accessing {:?} is not allowed",
-                                domain,
-                                match addr {
-                                    SocketAddr::V4(addr) => addr.ip().to_string(),
-                                    SocketAddr::V6(addr) => addr.ip().to_string(),
-                                }
-                            ));
-                        }
-                    }
-                    Ok(())
-                }
-                Err(err) => Err(format!("could not resolve host {:?}: {}", domain, err)),
-            },
-            Some(url::Host::Ipv4(addr)) => {
-                if !IpExt::is_global(&addr) {
-                    Err(format!("accessing {:?} is not allowed", addr))
-                } else {
-                    on_direct_ip_access(std::net::IpAddr::V4(addr));
-                    Ok(())
-                }
-            }
-            Some(url::Host::Ipv6(addr)) => {
-                if !IpExt::is_global(&addr) {
-                    Err(format!("accessing {:?} is not allowed", addr))
-                } else {
-                    on_direct_ip_access(std::net::IpAddr::V6(addr));
-                    Ok(())
-                }
+    fn request_permission(&self, _method: &reqwest::Method, url: &Url) -> Result<(), String> {
+        self.check_access(url)?;
+
+        // rate limits are shared between all fetches, so they're handled by the server
+        if let Some(host) = url.host_str() {
+            let wait: Duration =
+                self.host_request(|reply| ScriptMsg::ReserveRequest(host.into(), reply))?;
+            if wait >= RATE_LIMIT_LOG_THRESHOLD {
+                self.send_message(ConsoleMessage {
+                    msg_type: MessageType::Info,
+                    message: vec![MsgFrag::Log(format!(
+                        "Waiting {:.1}s for rate limit of {:?}",
+                        wait.as_secs_f64(),
+                        host
+                    ))],
+                });
             }
-            None => Err(format!("URL has no host")),
-        },
-        scheme => Err(format!("URL scheme {:?} not allowed", scheme)),
+            thread::sleep(wait);
+        }
+
+        Ok(())
     }
-}
 
-impl ScriptContext for FetchContext {
-    fn request_permission(&self, _method: &reqwest::Method, url: &Url) -> Result<(), String> {
-        request_fetch_permission(url, |addr| {
-            self.msg_sender
-                .lock()
-                .unwrap()
-                .send(FetchMsg {
-                    time: Some(self.time.lock().unwrap().get_time()),
-                    msg: ConsoleMessage {
-                        msg_type: MessageType::Warn,
-                        message: vec![MsgFrag::Log(format!(
-                            "Direct access of ip address {:?}",
-                            addr
-                        ))],
-                    },
-                })
-                .unwrap();
-        })
+    fn request_redirect_permission(
+        &self,
+        _method: &reqwest::Method,
+        url: &Url,
+    ) -> Result<(), String> {
+        self.check_access(url)?;
+
+        // waiting here would block the HTTP client past the request timeout
+        if let Some(host) = url.host_str() {
+            self.host_request(|reply| ScriptMsg::ReserveRequestNow(host.into(), reply))?;
+        }
+
+        Ok(())
     }
 
     fn fetch_did_start(&self) {
@@ -219,16 +290,7 @@ impl ScriptContext for FetchContext {
     }
 
     fn on_console_message(&self, msg: ConsoleMessage) {
-        let time = self.time.lock().unwrap().get_time();
-
-        self.msg_sender
-            .lock()
-            .unwrap()
-            .send(FetchMsg {
-                time: Some(time),
-                msg,
-            })
-            .unwrap();
+        self.send_message(msg);
     }
 
     fn get_aof_request(&self) -> AofRequest {
@@ -242,6 +304,75 @@ impl ScriptContext for FetchContext {
             .send(ScriptMsg::Result(serde_json::to_string(&data).unwrap()))
             .unwrap();
     }
+
+    fn storage_get(&self, key: &str) -> Result<Option<Value>, String> {
+        match self.storage_request(StorageRequest::Get(key.into()))? {
+            Some(value) => serde_json::from_str(&value)
+                .map(Some)
+                .map_err(|e| format!("failed to decode stored value: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    fn storage_set(&self, key: &str, value: Value) -> Result<(), String> {
+        let value =
+            serde_json::to_string(&value).map_err(|e| format!("failed to encode value: {}", e))?;
+        self.storage_request(StorageRequest::Set(key.into(), value))?;
+        Ok(())
+    }
+
+    fn storage_delete(&self, key: &str) -> Result<(), String> {
+        self.storage_request(StorageRequest::Delete(key.into()))?;
+        Ok(())
+    }
+
+    fn secret_get(&self, name: &str) -> Result<Option<String>, String> {
+        let value = self.host_request(|reply| ScriptMsg::GetSecret(name.into(), reply))?;
+        if let Some(value) = &value {
+            self.secrets.lock().unwrap().push(value.clone());
+        }
+        Ok(value)
+    }
+
+    fn revealed_secrets(&self) -> Vec<String> {
+        self.secrets.lock().unwrap().clone()
+    }
+
+    fn cookies_get(&self) -> Result<Option<String>, String> {
+        self.host_request(ScriptMsg::GetCookies)
+    }
+
+    fn cookies_store(&self, url: &Url, set_cookies: Vec<String>) -> Result<(), String> {
+        self.host_request(|reply| ScriptMsg::StoreCookies(url.to_string(), set_cookies, reply))
+    }
+
+    fn http_cache_get(&self, key: &str) -> Result<Option<CachedResponse>, String> {
+        self.host_request(|reply| ScriptMsg::GetCachedResponse(key.into(), reply))
+    }
+
+    fn http_cache_set(&self, key: &str, response: CachedResponse) -> Result<(), String> {
+        self.host_request(|reply| ScriptMsg::CacheResponse(key.into(), response, reply))
+    }
+
+    fn fetch_mode(&self) -> FetchMode {
+        self.fetch_mode
+    }
+
+    fn cassette_get(&self, request: &CassetteRequest) -> Result<Option<CachedResponse>, String> {
+        self.host_request(|reply| ScriptMsg::GetRecordedResponse(request.clone(), reply))
+    }
+
+    fn cassette_record(
+        &self,
+        request: CassetteRequest,
+        response: CachedResponse,
+    ) -> Result<(), String> {
+        self.host_request(|reply| ScriptMsg::RecordResponse(request, response, reply))
+    }
+
+    fn load_library(&self, id: &str) -> Result<String, String> {
+        self.host_request(|reply| ScriptMsg::LoadLibrary(id.into(), reply))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -250,225 +381,358 @@ pub enum Fetch {
         domain: String,
         script: String,
         path: String,
+        /// JSON-encoded [aof_script::PreviousSource], since IPC messages can't contain arbitrary values.
+        previous: Option<String>,
+        /// JSON-encoded domain options.
+        options: String,
     },
     SourceItem {
         domain: String,
         script: String,
         path: String,
+        /// JSON-encoded domain options.
+        options: String,
+    },
+    Search {
+        domain: String,
+        script: String,
+        query: String,
+    },
+    UrlPatterns {
+        domain: String,
+        script: String,
+    },
+    MatchUrl {
+        domain: String,
+        script: String,
+        url: String,
     },
 }
 
 #[derive(Serialize, Deserialize)]
-enum ScriptMsg {
+pub(super) enum StorageRequest {
+    Get(String),
+    Set(String, String),
+    Delete(String),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) enum ScriptMsg {
     PauseTimer,
     ContinueTimer,
+    Console(FetchMsg),
+    Storage(StorageRequest, IpcSender<Result<Option<String>, String>>),
+    GetSecret(String, IpcSender<Result<Option<String>, String>>),
+    GetCookies(IpcSender<Result<Option<String>, String>>),
+    StoreCookies(String, Vec<String>, IpcSender<Result<(), String>>),
+    GetCachedResponse(String, IpcSender<Result<Option<CachedResponse>, String>>),
+    CacheResponse(String, CachedResponse, IpcSender<Result<(), String>>),
+    GetRecordedResponse(
+        CassetteRequest,
+        IpcSender<Result<Option<CachedResponse>, String>>,
+    ),
+    RecordResponse(
+        CassetteRequest,
+        CachedResponse,
+        IpcSender<Result<(), String>>,
+    ),
+    LoadLibrary(String, IpcSender<Result<String, String>>),
+    ReserveRequest(String, IpcSender<Result<Duration, String>>),
+    ReserveRequestNow(String, IpcSender<Result<(), String>>),
     FatalError(String),
     ErrResult(ScriptError),
     Result(String),
+    /// Sent when the worker has finished a request and is ready for the next one.
     Done,
 }
 
+fn decode_options(options: &str) -> Result<serde_json::Map<String, Value>, ScriptError> {
+    serde_json::from_str(options)
+        .map_err(|e| ScriptError::Fatal(format!("failed to decode domain options: {}", e)))
+}
+
+/// A request sent to a worker: the script request, its limits, the fetch mode, the hosts the
+/// script may access, and the network policy.
+pub(super) type WorkerRequest = (
+    Fetch,
+    ScriptLimits,
+    FetchMode,
+    Option<Vec<String>>,
+    NetworkPolicy,
+);
+
 async fn run_inner_request(
     request: Fetch,
+    limits: ScriptLimits,
+    fetch_mode: FetchMode,
+    allowed_hosts: Option<Vec<String>>,
+    network_policy: NetworkPolicy,
     send: IpcSender<ScriptMsg>,
-    msg_send: IpcSender<FetchMsg>,
 ) -> Result<(), ScriptError> {
     let (request, domain, script) = match request {
         Fetch::Source {
             domain,
             script,
             path,
-        } => (AofRequest::Source { path }, domain, script),
+            previous,
+            options,
+        } => {
+            let previous = match previous {
+                Some(previous) => Some(serde_json::from_str(&previous).map_err(|e| {
+                    ScriptError::Fatal(format!("failed to decode previous source: {}", e))
+                })?),
+                None => None,
+            };
+            let options = decode_options(&options)?;
+            (
+                AofRequest::Source {
+                    path,
+                    previous,
+                    options,
+                },
+                domain,
+                script,
+            )
+        }
         Fetch::SourceItem {
             domain,
             script,
             path,
-        } => (AofRequest::SourceItem { path }, domain, script),
+            options,
+        } => {
+            let options = decode_options(&options)?;
+            (AofRequest::SourceItem { path, options }, domain, script)
+        }
+        Fetch::Search {
+            domain,
+            script,
+            query,
+        } => (AofRequest::Search { query }, domain, script),
+        Fetch::UrlPatterns { domain, script } => (AofRequest::UrlPatterns, domain, script),
+        Fetch::MatchUrl {
+            domain,
+            script,
+            url,
+        } => (AofRequest::MatchUrl { url }, domain, script),
     };
 
-    let ctx = Arc::new(FetchContext::new(request, send, msg_send));
-    let mut script = InnerScript::create(ctx, &domain, &script)
-        .map_err(|e| ScriptError::Exec(format!("{}", e)))?;
-    script
-        .run()
-        .await
-        .map_err(|e| ScriptError::Exec(format!("{}", e)))?;
+    let ctx = Arc::new(FetchContext::new(
+        request,
+        limits,
+        fetch_mode,
+        allowed_hosts,
+        network_policy,
+        send,
+    ));
+    let mut script =
+        InnerScript::create(Arc::clone(&ctx) as Arc<dyn ScriptContext>, &domain, &script)
+            .map_err(|e| ScriptError::Exec(format!("{}", e)))?;
+    script.run().await.map_err(|e| {
+        if e.is::<HeapLimitExceeded>() {
+            ScriptError::OutOfMemory
+        } else {
+            // errors end up in the log, so they must not contain secrets either
+            ScriptError::Exec(redact_secrets(&format!("{}", e), &ctx.revealed_secrets()))
+        }
+    })?;
     Ok(())
 }
 
+/// Runs a script worker process, which handles requests until the server closes the channel.
 pub fn run_ipc_fork(ipc_server_name: &str) {
     // HACK: do this in a new thread because the new tokio runtime conflicts with the existing one
     // FIXME: don't do this
     let ipc_server_name = ipc_server_name.to_string();
     thread::spawn(|| {
         let oneshot_send = IpcSender::connect(ipc_server_name).unwrap();
-        let (msg_send, msg_recv) = ipc_channel::ipc::channel().unwrap();
         let (send, recv) = ipc_channel::ipc::channel().unwrap();
-        let (req_send, req_recv) = ipc_channel::ipc::channel().unwrap();
-        oneshot_send.send((req_send, recv, msg_recv)).unwrap();
-
-        let request = req_recv.recv().unwrap();
+        let (req_send, req_recv) = ipc_channel::ipc::channel::<WorkerRequest>().unwrap();
+        oneshot_send.send((req_send, recv)).unwrap();
 
         let rt = tokio::runtime::Runtime::new().unwrap();
-        let res = rt.block_on(run_inner_request(request, send.clone(), msg_send));
-
-        if let Err(err) = res {
-            send.send(ScriptMsg::ErrResult(err)).unwrap();
+        while let Ok((request, limits, fetch_mode, allowed_hosts, network_policy)) = req_recv.recv()
+        {
+            let res = rt.block_on(run_inner_request(
+                request,
+                limits,
+                fetch_mode,
+                allowed_hosts,
+                network_policy,
+                send.clone(),
+            ));
+
+            if let Err(err) = res {
+                send.send(ScriptMsg::ErrResult(err)).unwrap();
+            }
+            send.send(ScriptMsg::Done).unwrap();
         }
-        send.send(ScriptMsg::Done).unwrap();
     })
     .join()
     .unwrap();
 }
 
-pub fn run_request(request: Fetch, messages: &mut Vec<FetchMsg>) -> Result<Value, ScriptError> {
-    let (ipc_server, ipc_server_name) = IpcOneShotServer::<(
-        IpcSender<Fetch>,
-        IpcReceiver<ScriptMsg>,
-        IpcReceiver<FetchMsg>,
-    )>::new()
-    .map_err(|e| ScriptError::Fatal(format!("failed to open IPC server: {}", e)))?;
-    let mut child_pid = {
-        use std::process::{Command, Stdio};
-
-        let bin_path = std::env::current_exe().map_err(|_| {
-            ScriptError::Fatal("failed to fork: could not find current executable".into())
-        })?;
-        Command::new(bin_path)
-            .args(&["--fetcher-ipc-fork", &ipc_server_name])
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| ScriptError::Fatal(format!("failed to spawn child: {}", e)))?
-    };
-
-    let (_, (req_send, recv, msg_recv)) = ipc_server
-        .accept()
-        .map_err(|e| ScriptError::Fatal(format!("Could not accept IPC connection: {}", e)))?;
+/// Handles a message from the script that requires a response from the server.
+fn handle_host_request(msg: ScriptMsg, host: &dyn ScriptHost) {
+    // if sending a reply fails, the script process is gone and we'll find out soon enough
+    match msg {
+        ScriptMsg::Storage(request, reply) => {
+            let res = match request {
+                StorageRequest::Get(key) => host.storage_get(&key),
+                StorageRequest::Set(key, value) => host.storage_set(&key, &value).map(|()| None),
+                StorageRequest::Delete(key) => host.storage_delete(&key).map(|()| None),
+            };
+            let _ = reply.send(res);
+        }
+        ScriptMsg::GetSecret(name, reply) => {
+            let _ = reply.send(host.secret_get(&name));
+        }
+        ScriptMsg::GetCookies(reply) => {
+            let _ = reply.send(host.cookies_get());
+        }
+        ScriptMsg::StoreCookies(url, set_cookies, reply) => {
+            let _ = reply.send(host.cookies_store(&url, &set_cookies));
+        }
+        ScriptMsg::GetCachedResponse(key, reply) => {
+            let _ = reply.send(host.http_cache_get(&key));
+        }
+        ScriptMsg::CacheResponse(key, response, reply) => {
+            let _ = reply.send(host.http_cache_set(&key, &response));
+        }
+        ScriptMsg::GetRecordedResponse(request, reply) => {
+            let _ = reply.send(host.cassette_get(&request));
+        }
+        ScriptMsg::RecordResponse(request, response, reply) => {
+            let _ = reply.send(host.cassette_record(request, response));
+        }
+        ScriptMsg::LoadLibrary(id, reply) => {
+            let _ = reply.send(host.load_library(&id));
+        }
+        ScriptMsg::ReserveRequest(host, reply) => {
+            let _ = reply.send(reserve_request(&host));
+        }
+        ScriptMsg::ReserveRequestNow(host, reply) => {
+            let _ = reply.send(reserve_request_now(&host));
+        }
+        _ => unreachable!("not a host request"),
+    }
+}
 
-    req_send
-        .send(request)
-        .map_err(|e| ScriptError::Fatal(format!("failed to send request: {}", e)))?;
+pub fn run_request(
+    request: Fetch,
+    host: &dyn ScriptHost,
+    messages: &mut Vec<FetchMsg>,
+) -> (FetchTime, Result<Value, ScriptError>) {
+    // the worker pauses the timer while fetching, which is tracked here too so that the total
+    // time is known even if the worker never finishes
+    let mut time = FetchTimeMetrics {
+        start_time: Instant::now(),
+        fetch_time: Duration::from_secs(0),
+        current_fetch_start: None,
+    };
+    let result = run_request_timed(request, host, messages, &mut time);
+    if let Some(start) = time.current_fetch_start.take() {
+        time.fetch_time += start.elapsed();
+    }
+    (time.get_time(), result)
+}
 
-    macro_rules! drain_messages {
-        () => {
-            loop {
-                match msg_recv.try_recv() {
-                    Ok(msg) => messages.push(msg),
-                    Err(_) => break,
-                }
-            }
-        };
+fn run_request_timed(
+    request: Fetch,
+    host: &dyn ScriptHost,
+    messages: &mut Vec<FetchMsg>,
+    time: &mut FetchTimeMetrics,
+) -> Result<Value, ScriptError> {
+    let limits = host.script_limits();
+
+    let mut worker = take_worker().map_err(ScriptError::Fatal)?;
+    if let Err(err) = worker.send_request(
+        request,
+        runtime_script_limits(&limits),
+        host.fetch_mode(),
+        host.allowed_hosts(),
+        network_policy(),
+    ) {
+        discard_worker(worker, messages);
+        return Err(ScriptError::Fatal(err));
     }
 
-    let (_recv, result) = {
-        let mut time_left = SCRIPT_EXEC_TIME;
-        let mut timer_running = true;
-        let mut cycle_start = Instant::now();
-        let result = loop {
-            thread::sleep(MONITOR_SLEEP_TIME);
+    let mut time_left = Duration::from_secs(limits.exec_time);
+    let max_pause_time =
+        MAX_WAIT_TIME + Duration::from_secs(limits.request_timeout) + TIMER_PAUSE_MARGIN;
+    let mut timer_running = true;
+    let mut pause_deadline = Instant::now();
+    let mut result = None;
+    let worker_done = loop {
+        let wait_start = Instant::now();
+        let msg = if result.is_some() {
+            // the script has already responded; it just needs to finish up
+            worker.recv_timeout(WORKER_DONE_TIMEOUT)
+        } else if timer_running {
+            worker.recv_timeout(time_left)
+        } else {
+            worker.recv_timeout(pause_deadline.saturating_duration_since(Instant::now()))
+        };
+        if timer_running && result.is_none() {
+            time_left = time_left
+                .checked_sub(wait_start.elapsed())
+                .unwrap_or(Duration::from_secs(0));
+        }
 
-            if timer_running {
-                let elapsed = cycle_start.elapsed();
-                if elapsed >= time_left {
-                    break Err(ScriptError::Timeout);
-                } else {
-                    time_left -= elapsed;
+        match msg {
+            Ok(ScriptMsg::PauseTimer) => {
+                if timer_running {
+                    pause_deadline = Instant::now() + max_pause_time;
                 }
+                timer_running = false;
+                time.current_fetch_start.get_or_insert_with(Instant::now);
             }
-            cycle_start = Instant::now();
-
-            drain_messages!();
-
-            match recv.try_recv() {
-                Ok(ScriptMsg::PauseTimer) => {
-                    timer_running = false;
-                }
-                Ok(ScriptMsg::ContinueTimer) => {
-                    timer_running = true;
-                }
-                Ok(ScriptMsg::Result(result)) => match serde_json::from_str(&result) {
-                    Ok(result) => break Ok(result),
-                    Err(err) => {
-                        break Err(ScriptError::Fatal(format!(
-                            "Failed to deserialize result: {}",
-                            err
-                        )))
-                    }
-                },
-                Ok(ScriptMsg::ErrResult(err)) => {
-                    break Err(err);
-                }
-                Ok(ScriptMsg::FatalError(error)) => {
-                    break Err(ScriptError::Fatal(error));
+            Ok(ScriptMsg::ContinueTimer) => {
+                timer_running = true;
+                if let Some(start) = time.current_fetch_start.take() {
+                    time.fetch_time += start.elapsed();
                 }
-                Ok(ScriptMsg::Done) => {
-                    break Err(ScriptError::NoResult);
-                }
-                Err(ipc_channel::ipc::TryRecvError::Empty) => (),
-                Err(e) => break Err(ScriptError::Fatal(format!("IPC channel error: {:?}", e))),
             }
-        };
-
-        thread::sleep(MONITOR_EXIT_WAIT_TIME);
-
-        (recv, result)
-    };
-
-    let script_status = child_pid
-        .try_wait()
-        .map_err(|e| ScriptError::Fatal(format!("failed to wait for child: {}", e)))?;
-    if script_status.is_none() {
-        debug!("Killing fetcher because it's out of time");
-        let result = child_pid.kill();
-        if let Err(e) = result {
-            match e.kind() {
-                std::io::ErrorKind::InvalidInput => (),
-                _ => {
-                    error!("Failed to kill fetcher: {}", e);
+            Ok(ScriptMsg::Console(msg)) => messages.push(msg),
+            Ok(ScriptMsg::Result(data)) => {
+                result.get_or_insert_with(|| {
+                    serde_json::from_str(&data).map_err(|err| {
+                        ScriptError::Fatal(format!("Failed to deserialize result: {}", err))
+                    })
+                });
+            }
+            Ok(ScriptMsg::ErrResult(err)) => {
+                result.get_or_insert(Err(err));
+            }
+            Ok(ScriptMsg::FatalError(error)) => {
+                result.get_or_insert(Err(ScriptError::Fatal(error)));
+            }
+            Ok(ScriptMsg::Done) => break true,
+            Ok(msg) => handle_host_request(msg, host),
+            Err(RecvTimeoutError::Timeout) => {
+                if result.is_none() {
+                    debug!("Killing fetcher because it's out of time");
+                    result = Some(Err(ScriptError::Timeout));
                 }
+                break false;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                result.get_or_insert(Err(ScriptError::Fatal(
+                    "script process exited unexpectedly".into(),
+                )));
+                break false;
             }
         }
-    }
+    };
+
+    let result = result.unwrap_or(Err(ScriptError::NoResult));
 
-    child_pid
-        .wait()
-        .map_err(|e| ScriptError::Fatal(format!("failed to wait for child: {}", e)))?;
-
-    drain_messages!();
-
-    let mut stdout = child_pid.stdout.take().unwrap();
-    let mut stderr = child_pid.stderr.take().unwrap();
-    let mut stdout_buf = Vec::new();
-    let mut stderr_buf = Vec::new();
-    stdout
-        .read_to_end(&mut stdout_buf)
-        .map_err(|e| ScriptError::Fatal(format!("failed to read child stdout: {}", e)))?;
-    stderr
-        .read_to_end(&mut stderr_buf)
-        .map_err(|e| ScriptError::Fatal(format!("failed to read child stderr: {}", e)))?;
-    let stdout = String::from_utf8_lossy(&stdout_buf).to_string();
-    let stderr = String::from_utf8_lossy(&stderr_buf).to_string();
-
-    if !stdout.is_empty() {
-        messages.push(FetchMsg {
-            time: None,
-            msg: ConsoleMessage {
-                msg_type: MessageType::Stdout,
-                message: vec![MsgFrag::Log(stdout)],
-            },
-        });
-    }
-
-    if !stderr.is_empty() {
-        messages.push(FetchMsg {
-            time: None,
-            msg: ConsoleMessage {
-                msg_type: MessageType::Stderr,
-                message: vec![MsgFrag::Log(stderr)],
-            },
-        });
+    // a worker that ran out of memory may be left in a bad state, so it's not reused
+    let is_reusable = worker_done && !matches!(result, Err(ScriptError::OutOfMemory));
+    if is_reusable {
+        worker.take_output(messages);
+        return_worker(worker);
+    } else {
+        discard_worker(worker, messages);
     }
 
     result
@@ -4,7 +4,7 @@ use crate::session::cookie::Session;
 use crate::state::State;
 use actix::prelude::Stream;
 use actix_web::body::{Body, BodySize, MessageBody};
-use actix_web::http::HeaderValue;
+use actix_web::http::{header, HeaderValue};
 use actix_web::web::Bytes;
 use actix_web::{get, web, Error, HttpResponse, Responder, Scope};
 use aof_script::url::Url;
@@ -15,7 +15,7 @@ use std::convert::TryFrom;
 use tokio::macros::support::Pin;
 
 pub fn scope() -> Scope {
-    web::scope("/resources").service(camo)
+    web::scope("/resources").service(camo).service(archived)
 }
 
 #[derive(Deserialize)]
@@ -87,12 +87,14 @@ pub async fn get_camo_response(request: &web::HttpRequest, query: &CamoRequest)
             return HttpResponse::BadRequest().body("bad url");
         }
     };
-    if let Err(reason) = request_fetch_permission(&url, |_| {}) {
-        return HttpResponse::BadRequest().body(format!("bad url: {}", reason));
-    }
+    let addr = match request_fetch_permission(&url) {
+        Ok(addr) => addr,
+        Err(reason) => return HttpResponse::BadRequest().body(format!("bad url: {}", reason)),
+    };
 
     let client = awc::Client::new();
-    let mut req = client.get(&query.url);
+    // connect to the checked address instead of resolving the host again
+    let mut req = client.get(&query.url).address(addr);
     for (k, v) in request.headers() {
         let forward = FORWARDED_HEADER_WHITE_LIST
             .iter()
@@ -166,3 +168,54 @@ async fn camo(
         Err(SessionError::NoSession) => HttpResponse::NotFound().body("no session"),
     }
 }
+
+#[get("/archived/{hash}")]
+async fn archived(
+    data: web::Data<State>,
+    session: Session,
+    hash: web::Path<String>,
+) -> impl Responder {
+    match get_user_session(&data, &session.get()) {
+        Ok(_) => (),
+        Err(SessionError::InternalError) => {
+            return HttpResponse::InternalServerError().body("internal server error")
+        }
+        Err(SessionError::NoSession) => return HttpResponse::NotFound().body("no session"),
+    }
+
+    let resource = match data.data().lock().source_resource_by_hash(&hash) {
+        Ok(Some(resource)) => resource,
+        Ok(None) => return HttpResponse::NotFound().body("not found"),
+        Err(err) => {
+            error!("Failed to get archived resource {}: {}", hash, err);
+            return HttpResponse::InternalServerError().body("internal server error");
+        }
+    };
+    let metadata = match resource.metadata() {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            error!(
+                "Failed to decode archived resource metadata {}: {}",
+                hash, err
+            );
+            return HttpResponse::InternalServerError().body("internal server error");
+        }
+    };
+
+    // never serve arbitrary content types from this origin
+    let content_type = metadata
+        .content_type
+        .filter(|t| t.starts_with("image/") || t.starts_with("text/css"))
+        .unwrap_or_else(|| "application/octet-stream".into());
+
+    let mut res = HttpResponse::Ok();
+    res.header(header::CONTENT_TYPE, content_type);
+    // resources are content-addressed and will never change
+    res.header(
+        header::CACHE_CONTROL,
+        "private, max-age=31536000, immutable",
+    )
+    .header("X-Content-Type-Options", "nosniff")
+    .header("Content-Security-Policy", "default-src 'none'; sandbox")
+    .body(resource.data().to_vec())
+}
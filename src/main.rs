@@ -158,6 +158,7 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    fetcher::init_worker_pool();
     start_gc(state.clone());
     auto_fetcher::start((*state).clone());
 
@@ -295,7 +296,11 @@ fn start_gc(state: web::Data<State>) {
     thread::Builder::new()
         .name("gc-sources".into())
         .spawn(move || loop {
-            let res = state.data().lock().garbage_collect_sources();
+            let res = {
+                let data = state.data().lock();
+                data.garbage_collect_sources()
+                    .and_then(|_| fetcher::garbage_collect_fetch_history(&data))
+            };
             if let Err(err) = res {
                 error!(target: "gc", "Error during garbage collection: {}", err);
             } else {
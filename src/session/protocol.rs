@@ -1,6 +1,10 @@
+use crate::config::ScriptLimitValues;
+use crate::data::domain_options::{DomainOption, DomainOptionValues};
+use crate::data::domains::{CumulativeSettings, DomainScriptLimits};
 use crate::data::sources::SourceMetaItem;
-use crate::fetcher::{FetchMsg, FetchTime};
+use crate::fetcher::{FetchMsg, FetchTime, ResolvedUrl, TestRunKind};
 use aof_script::console::{MessageType, MsgFrag};
+use aof_script::{FetchMode, SearchResult};
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 use serde::{Deserialize, Serialize, Serializer};
 use std::collections::BTreeMap;
@@ -157,6 +161,7 @@ def_requests! {
     "user_tokens" => UserTokens,
     "user_domains" => UserDomains,
     "public_domains" => PublicDomains,
+    "script_limits" => ScriptLimits,
     "user_rss_auth_keys" => UserRssAuthKeys,
     "user_regen_client_key" => UserRegenClientKey,
     "user_enumerate_objects" => UserEnumerateObjects;
@@ -171,6 +176,7 @@ def_requests! {
     "source_item_data" => SourceItemData { uri: String },
     "source_user_data" => SourceUserData { uri: String },
     "source_item_user_data" => SourceItemUserData { uri: String },
+    "source_fetch_history" => SourceFetchHistory { uri: String, before: Option<i32>, limit: u32 },
 
     "user_subscribe_source" => UserSubscribeSource { uri: String },
     "user_unsubscribe_source" => UserUnsubscribeSource { uri: String },
@@ -195,13 +201,67 @@ def_requests! {
         name: String,
         description: String,
         is_public: bool,
+        /// If None, library status is left unchanged.
+        is_library: Option<bool>,
         script: String,
+        /// Message of the script revision, if the script changed.
+        #[serde(default)]
+        message: String,
     },
     "user_delete_domain" => UserDeleteDomain { id: String },
+    "user_clear_domain_cookies" => UserClearDomainCookies { id: String },
+    "user_set_domain_script_limits" => UserSetDomainScriptLimits {
+        id: String,
+        limits: DomainScriptLimits,
+    },
+    "user_set_domain_fetch_mode" => UserSetDomainFetchMode { id: String, mode: FetchMode },
+    "user_set_domain_cumulative" => UserSetDomainCumulative {
+        id: String,
+        cumulative: Option<CumulativeSettings>,
+    },
+    "user_set_domain_allowed_hosts" => UserSetDomainAllowedHosts {
+        id: String,
+        allowed_hosts: Option<Vec<String>>,
+    },
+    "user_set_domain_cassette" => UserSetDomainCassette { id: String, cassette: Option<String> },
+    "domain_cassette" => DomainCassette { id: String },
+    "user_set_domain_options" => UserSetDomainOptions { id: String, options: Vec<DomainOption> },
+    "domain_options" => DomainOptions { id: String },
+    "user_set_domain_option_values" => UserSetDomainOptionValues {
+        id: String,
+        values: DomainOptionValues,
+    },
+    "user_domain_secrets" => UserDomainSecrets { id: String },
+    "user_set_domain_secret" => UserSetDomainSecret {
+        id: String,
+        name: String,
+        value: Option<String>,
+    },
+    "user_domain_script_revisions" => UserDomainScriptRevisions {
+        id: String,
+        before: Option<i32>,
+        limit: u32,
+    },
+    "user_domain_script_revision" => UserDomainScriptRevision { id: String, revision: i32 },
+    "user_diff_domain_script_revisions" => UserDiffDomainScriptRevisions {
+        id: String,
+        from: i32,
+        to: i32,
+    },
+    "user_rollback_domain_script" => UserRollbackDomainScript { id: String, revision: i32 },
+    "domain_test_run" => DomainTestRun {
+        id: String,
+        script: String,
+        kind: TestRunKind,
+        path: String,
+    },
+    "domain_search" => DomainSearch { id: String, query: String },
     "domain" => Domain { id: String },
+    "resolve_url" => ResolveUrl { url: String },
     "domain_script" => DomainScript { id: String },
     "user_subscribe_domain" => UserSubscribeDomain { id: String },
     "user_unsubscribe_domain" => UserUnsubscribeDomain { id: String },
+    "user_confirm_domain_allowed_hosts" => UserConfirmDomainAllowedHosts { id: String },
 
     "user_create_rss_auth_key" => UserCreateRssAuthKey { label: Option<String> },
     "user_delete_rss_auth_key" => UserDeleteRssAuthKey { key: String },
@@ -251,9 +311,21 @@ pub struct ResponseDomain {
     pub name: String,
     pub description: String,
     pub is_public: bool,
+    pub is_library: bool,
+    pub script_limits: DomainScriptLimits,
+    pub fetch_mode: FetchMode,
+    pub cumulative: Option<CumulativeSettings>,
+    pub allowed_hosts: Option<Vec<String>>,
+    pub allowed_hosts_confirmed: bool,
     pub editable: bool,
 }
 
+#[derive(Serialize)]
+pub struct ResponseScriptLimits {
+    pub default: ScriptLimitValues,
+    pub max: ScriptLimitValues,
+}
+
 #[derive(Serialize)]
 pub struct DomainScriptResult {
     pub success: bool,
@@ -261,6 +333,93 @@ pub struct DomainScriptResult {
     pub error: Option<&'static str>,
 }
 
+#[derive(Serialize)]
+pub struct DomainCassetteResult {
+    pub success: bool,
+    pub cassette: Option<String>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainOptionsResult {
+    pub success: bool,
+    pub options: Option<Vec<DomainOption>>,
+    pub values: Option<DomainOptionValues>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct UserDomainSecretsResult {
+    pub success: bool,
+    pub names: Option<Vec<String>>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseScriptRevision {
+    pub id: i32,
+    pub date: String,
+    /// Name of the user who saved the revision, if they still exist.
+    pub author: Option<String>,
+    pub message: String,
+    /// Whether this revision is the current script.
+    pub current: bool,
+}
+
+#[derive(Serialize)]
+pub struct DomainScriptRevisionsResult {
+    pub success: bool,
+    pub revisions: Option<Vec<ResponseScriptRevision>>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainScriptRevisionResult {
+    pub success: bool,
+    pub revision: Option<ResponseScriptRevision>,
+    pub script: Option<String>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainScriptDiffResult {
+    pub success: bool,
+    /// Unified diff, which is empty if the scripts are the same.
+    pub diff: Option<String>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainScriptRollbackResult {
+    pub success: bool,
+    /// Id of the new revision.
+    pub revision: Option<i32>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainTestRunResult {
+    pub success: bool,
+    pub data: Option<serde_json::Value>,
+    pub log: Vec<FetchLogItem>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct DomainSearchResult {
+    pub success: bool,
+    pub results: Option<Vec<SearchResult>>,
+    pub log: Vec<FetchLogItem>,
+    pub error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct ResolveUrlResult {
+    pub success: bool,
+    pub results: Option<Vec<ResolvedUrl>>,
+    pub error: Option<&'static str>,
+}
+
 pub type ResponseSourceItem = BTreeMap<String, serde_json::Value>;
 
 #[derive(Serialize)]
@@ -287,6 +446,39 @@ pub struct SourceResultData {
     pub last_updated: Option<String>,
     pub data: BTreeMap<String, serde_json::Value>,
     pub items: Vec<SourceMetaItem>,
+    /// Id of the domain script revision that produced this version, if known.
+    pub script_revision: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct SourceFetchHistoryResult {
+    pub success: bool,
+    pub entries: Option<Vec<ResponseFetchHistoryEntry>>,
+    pub error: Option<&'static str>,
+}
+
+/// Who initiated a fetch, relative to the user requesting the fetch history.
+#[derive(Serialize)]
+pub enum FetchInitiator {
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "self")]
+    User,
+    #[serde(rename = "other")]
+    Other,
+}
+
+#[derive(Serialize)]
+pub struct ResponseFetchHistoryEntry {
+    pub id: i32,
+    pub date: String,
+    pub initiator: FetchInitiator,
+    pub success: bool,
+    /// Duration in seconds.
+    pub duration: f64,
+    pub version_hash: Option<String>,
+    /// None if the log is hidden from the user.
+    pub log: Option<Vec<FetchLogItem>>,
 }
 
 #[derive(Serialize)]
@@ -319,17 +511,39 @@ pub enum Response {
     PublicDomains(Vec<String>),
     Domain(Option<ResponseDomain>),
     DomainScript(DomainScriptResult),
+    DomainTestRun(DomainTestRunResult),
+    DomainSearch(DomainSearchResult),
+    ResolveUrl(ResolveUrlResult),
     UserCreateDomain(UserCreateDomainResult),
     UserUpdateDomain(SimpleResult),
     UserDeleteDomain(SimpleResult),
+    UserClearDomainCookies(SimpleResult),
+    UserSetDomainScriptLimits(SimpleResult),
+    UserSetDomainFetchMode(SimpleResult),
+    UserSetDomainCumulative(SimpleResult),
+    UserSetDomainAllowedHosts(SimpleResult),
+    UserSetDomainCassette(SimpleResult),
+    DomainCassette(DomainCassetteResult),
+    UserSetDomainOptions(SimpleResult),
+    DomainOptions(DomainOptionsResult),
+    UserSetDomainOptionValues(SimpleResult),
+    UserDomainSecrets(UserDomainSecretsResult),
+    UserSetDomainSecret(SimpleResult),
+    UserDomainScriptRevisions(DomainScriptRevisionsResult),
+    UserDomainScriptRevision(DomainScriptRevisionResult),
+    UserDiffDomainScriptRevisions(DomainScriptDiffResult),
+    UserRollbackDomainScript(DomainScriptRollbackResult),
+    ScriptLimits(ResponseScriptLimits),
     UserSubscribeDomain(SimpleResult),
     UserUnsubscribeDomain(SimpleResult),
+    UserConfirmDomainAllowedHosts(SimpleResult),
 
     Source(SourceResult),
     SourceItem(SourceItemResult),
     SourceItemData(Option<ResponseSourceItem>),
     SourceUserData(Option<Vec<u8>>),
     SourceItemUserData(Option<Vec<u8>>),
+    SourceFetchHistory(SourceFetchHistoryResult),
     UserSubscribeSource(SimpleResult),
     UserUnsubscribeSource(SimpleResult),
     UserDeleteSource(SimpleResult),
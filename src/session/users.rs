@@ -1,9 +1,15 @@
 use crate::data;
-use crate::data::domains::UpdateDomainError;
+use crate::data::domain_options::DomainOptionsError;
+use crate::data::domains::{UpdateDomainError, SCRIPT_MAX_LEN};
+use crate::data::script_revisions::diff_scripts;
 use crate::data::sources::{canonicalize_uri, SubscribeError};
+use crate::data::user_secrets::UserSecretError;
 use crate::data::users::{ModifyUserError, UserAuthError, UserId};
 use crate::data::DataError;
-use crate::fetcher::FetchRequest;
+use crate::fetcher::limits::{
+    default_script_limits, max_script_limits, validate_domain_script_limits, InvalidScriptLimits,
+};
+use crate::fetcher::{self, FetchRequest, Fetcher, ScriptError};
 use crate::session::protocol::{
     self, ClientMsg, Request, RequestId, Response, ResponseRssAuthKey, SimpleResult,
     UserCreateDomainResult, UserCreateRssAuthKeyResult,
@@ -11,12 +17,28 @@ use crate::session::protocol::{
 use crate::session::{UserConn, UserConnMsg};
 use crate::state::State;
 use actix::prelude::*;
+use actix_web::web;
+use aof_script::{is_valid_host_pattern, Cassette, ALLOWED_HOSTS_MAX};
 use rand::Rng;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Instant;
 use thiserror::Error;
 
+/// Max len of a domain search query in bytes.
+const SEARCH_QUERY_MAX_LEN: usize = 256;
+
+/// Max number of entries returned by `source_fetch_history`.
+const FETCH_HISTORY_MAX_LIMIT: u32 = 20;
+
+/// Max number of revisions returned by `user_domain_script_revisions`.
+const SCRIPT_REVISIONS_MAX_LIMIT: u32 = 100;
+
+/// Max number of test runs and searches a user may have running at the same time.
+const MAX_RUNNING_SCRIPTS: usize = 2;
+
 /// Manages user actors.
 pub struct UserManager {
     users: HashMap<UserId, Addr<User>>,
@@ -96,6 +118,17 @@ pub struct User {
     user_id: UserId,
     user_mgr: Addr<UserManager>,
     conns: HashMap<Addr<UserConn>, Instant>,
+    /// Number of test runs and searches currently running for this user.
+    running_scripts: Rc<Cell<usize>>,
+}
+
+/// Counts a running script of a user until dropped.
+struct RunningScript(Rc<Cell<usize>>);
+
+impl Drop for RunningScript {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
 }
 
 impl User {
@@ -105,7 +138,19 @@ impl User {
             user_id,
             user_mgr,
             conns: HashMap::new(),
+            running_scripts: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Reserves a slot for running a script on behalf of the user.
+    /// Returns None if the user already has too many scripts running.
+    fn start_running_script(&self) -> Option<RunningScript> {
+        let count = self.running_scripts.get();
+        if count >= MAX_RUNNING_SCRIPTS {
+            return None;
         }
+        self.running_scripts.set(count + 1);
+        Some(RunningScript(Rc::clone(&self.running_scripts)))
     }
 
     fn handle_client_message(
@@ -127,7 +172,7 @@ impl User {
     fn handle_client_request(
         &self,
         conn: Addr<UserConn>,
-        _ctx: &mut Context<Self>,
+        ctx: &mut Context<Self>,
         id: RequestId,
         req: Request,
     ) -> Result<(), RequestError> {
@@ -288,6 +333,13 @@ impl User {
                         name: domain.name().into(),
                         description: domain.description().into(),
                         is_public: domain.is_public().into(),
+                        is_library: domain.is_library(),
+                        script_limits: domain.script_limits(),
+                        fetch_mode: domain.fetch_mode(),
+                        cumulative: domain.cumulative(),
+                        allowed_hosts: domain.allowed_hosts(),
+                        allowed_hosts_confirmed: data
+                            .user_domain_allowed_hosts_confirmed(user.id(), &domain)?,
                         editable: domain.owner_id() == user.id(),
                     })
                 } else {
@@ -319,6 +371,213 @@ impl User {
                 });
                 Ok(())
             }
+            Request::DomainTestRun {
+                id: d_id,
+                script,
+                kind,
+                path,
+            } => {
+                let error = match data.domain_by_domain_id(&d_id)? {
+                    Some(domain) if domain.owner_id() != user.id() => Err("forbidden"),
+                    Some(_) if script.len() > SCRIPT_MAX_LEN => Err("script_too_long"),
+                    Some(domain) => match self.start_running_script() {
+                        Some(running) => Ok((domain, running)),
+                        None => Err("rate_limited"),
+                    },
+                    None => Err("not_found"),
+                };
+                let (domain, running) = match error {
+                    Ok(res) => res,
+                    Err(error) => {
+                        conn.do_send(UserConnMsg::Response {
+                            id,
+                            data: Response::DomainTestRun(protocol::DomainTestRunResult {
+                                success: false,
+                                data: None,
+                                log: Vec::new(),
+                                error: Some(error),
+                            }),
+                        });
+                        return Ok(());
+                    }
+                };
+
+                let shared_data = self.state.data().clone();
+                let user_id = user.id();
+                ctx.spawn(
+                    async move {
+                        let res = web::block(move || {
+                            Ok::<_, ()>(Fetcher::test_run(
+                                &shared_data,
+                                user_id,
+                                &domain,
+                                &script,
+                                kind,
+                                &path,
+                            ))
+                        })
+                        .await;
+                        drop(running);
+
+                        let (log, res) = match res {
+                            Ok(res) => res,
+                            Err(_) => {
+                                error!("Failed to run domain test: canceled");
+                                conn.do_send(UserConnMsg::ErrorResponse { id });
+                                return;
+                            }
+                        };
+                        let log = log.into_iter().map(|x| x.into()).collect();
+                        let res = match res {
+                            Ok(data) => protocol::DomainTestRunResult {
+                                success: true,
+                                data: Some(data),
+                                log,
+                                error: None,
+                            },
+                            Err(err) => protocol::DomainTestRunResult {
+                                success: false,
+                                data: None,
+                                log,
+                                error: Some(match err {
+                                    ScriptError::Parse(_) | ScriptError::Invalid => {
+                                        "invalid_result"
+                                    }
+                                    _ => "script_failed",
+                                }),
+                            },
+                        };
+                        conn.do_send(UserConnMsg::Response {
+                            id,
+                            data: Response::DomainTestRun(res),
+                        });
+                    }
+                    .into_actor(self),
+                );
+                Ok(())
+            }
+            Request::DomainSearch { id: d_id, query } => {
+                let domain = match data.domain_by_domain_id(&d_id)? {
+                    Some(domain)
+                        if !domain.is_public()
+                            && domain.owner_id() != user.id()
+                            && !data.is_user_subscribed(user.id(), &domain)? =>
+                    {
+                        // don't reveal that the domain exists
+                        Err("not_found")
+                    }
+                    Some(_) if query.len() > SEARCH_QUERY_MAX_LEN => Err("query_too_long"),
+                    Some(domain) => match self.start_running_script() {
+                        Some(running) => Ok((domain, running)),
+                        None => Err("rate_limited"),
+                    },
+                    None => Err("not_found"),
+                };
+                let (domain, running) = match domain {
+                    Ok(res) => res,
+                    Err(error) => {
+                        conn.do_send(UserConnMsg::Response {
+                            id,
+                            data: Response::DomainSearch(protocol::DomainSearchResult {
+                                success: false,
+                                results: None,
+                                log: Vec::new(),
+                                error: Some(error),
+                            }),
+                        });
+                        return Ok(());
+                    }
+                };
+
+                let shared_data = self.state.data().clone();
+                ctx.spawn(
+                    async move {
+                        let res = web::block(move || {
+                            Ok::<_, ()>(Fetcher::search(&shared_data, &domain, &query))
+                        })
+                        .await;
+                        drop(running);
+
+                        let (log, res) = match res {
+                            Ok(res) => res,
+                            Err(_) => {
+                                error!("Failed to search domain: canceled");
+                                conn.do_send(UserConnMsg::ErrorResponse { id });
+                                return;
+                            }
+                        };
+                        let log = log.into_iter().map(|x| x.into()).collect();
+                        let res = match res {
+                            Ok(results) => protocol::DomainSearchResult {
+                                success: true,
+                                results: Some(results),
+                                log,
+                                error: None,
+                            },
+                            Err(err) => protocol::DomainSearchResult {
+                                success: false,
+                                results: None,
+                                log,
+                                error: Some(match err {
+                                    ScriptError::Parse(_) | ScriptError::Invalid => {
+                                        "invalid_result"
+                                    }
+                                    _ => "script_failed",
+                                }),
+                            },
+                        };
+                        conn.do_send(UserConnMsg::Response {
+                            id,
+                            data: Response::DomainSearch(res),
+                        });
+                    }
+                    .into_actor(self),
+                );
+                Ok(())
+            }
+            Request::ResolveUrl { url } => {
+                let is_valid = match aof_script::url::Url::parse(&url) {
+                    Ok(url) => url.scheme() == "http" || url.scheme() == "https",
+                    Err(_) => false,
+                };
+                if !is_valid {
+                    conn.do_send(UserConnMsg::Response {
+                        id,
+                        data: Response::ResolveUrl(protocol::ResolveUrlResult {
+                            success: false,
+                            results: None,
+                            error: Some("invalid_url"),
+                        }),
+                    });
+                    return Ok(());
+                }
+
+                let shared_data = self.state.data().clone();
+                let user_id = user.id();
+                ctx.spawn(
+                    async move {
+                        let res =
+                            web::block(move || fetcher::resolve_url(&shared_data, user_id, &url))
+                                .await;
+                        match res {
+                            Ok(results) => conn.do_send(UserConnMsg::Response {
+                                id,
+                                data: Response::ResolveUrl(protocol::ResolveUrlResult {
+                                    success: true,
+                                    results: Some(results),
+                                    error: None,
+                                }),
+                            }),
+                            Err(err) => {
+                                error!("Failed to resolve URL: {}", err);
+                                conn.do_send(UserConnMsg::ErrorResponse { id });
+                            }
+                        }
+                    }
+                    .into_actor(self),
+                );
+                Ok(())
+            }
             Request::UserCreateDomain { abbrev, name } => {
                 let res = match data.create_domain(user.id(), &abbrev, &name) {
                     Ok(id) => UserCreateDomainResult {
@@ -361,11 +620,23 @@ impl User {
                 name,
                 description,
                 is_public,
+                is_library,
                 script,
+                message,
             } => {
                 let res = if let Some(mut domain) = data.domain_by_domain_id(&d_id)? {
                     if domain.owner_id() == user.id() {
-                        match domain.update(&*data, abbrev, name, description, is_public, script) {
+                        match domain.update(
+                            &*data,
+                            abbrev,
+                            name,
+                            description,
+                            is_public,
+                            is_library,
+                            script,
+                            user.id(),
+                            &message,
+                        ) {
                             Ok(()) => SimpleResult::Ok,
                             Err(UpdateDomainError::AbbrevTooShort) => SimpleResult::Err {
                                 error: "abbrev_too_short",
@@ -385,6 +656,9 @@ impl User {
                             Err(UpdateDomainError::ScriptTooLong) => SimpleResult::Err {
                                 error: "script_too_long",
                             },
+                            Err(UpdateDomainError::MessageTooLong) => SimpleResult::Err {
+                                error: "message_too_long",
+                            },
                             Err(UpdateDomainError::Data(err)) => Err(err)?,
                         }
                     } else {
@@ -418,6 +692,503 @@ impl User {
                 // TODO: emit events
                 Ok(())
             }
+            Request::UserClearDomainCookies { id: d_id } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() == user.id() {
+                        data.domain_cookie_jar_clear(domain.id())?;
+                        SimpleResult::Ok
+                    } else {
+                        SimpleResult::Err { error: "forbidden" }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserClearDomainCookies(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainScriptLimits { id: d_id, limits } => {
+                let res = if let Some(mut domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() == user.id() {
+                        match validate_domain_script_limits(&limits) {
+                            Ok(()) => {
+                                domain.set_script_limits(&*data, limits)?;
+                                SimpleResult::Ok
+                            }
+                            Err(InvalidScriptLimits::Zero) => SimpleResult::Err {
+                                error: "limit_is_zero",
+                            },
+                            Err(InvalidScriptLimits::TooHigh) => SimpleResult::Err {
+                                error: "limit_too_high",
+                            },
+                        }
+                    } else {
+                        SimpleResult::Err { error: "forbidden" }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainScriptLimits(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainFetchMode { id: d_id, mode } => {
+                let res = if let Some(mut domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() == user.id() {
+                        domain.set_fetch_mode(&*data, mode)?;
+                        SimpleResult::Ok
+                    } else {
+                        SimpleResult::Err { error: "forbidden" }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainFetchMode(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainCumulative {
+                id: d_id,
+                cumulative,
+            } => {
+                let res = if let Some(mut domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() != user.id() {
+                        SimpleResult::Err { error: "forbidden" }
+                    } else if cumulative.map_or(false, |c| c.max_items == Some(0)) {
+                        SimpleResult::Err {
+                            error: "limit_is_zero",
+                        }
+                    } else {
+                        domain.set_cumulative(&*data, cumulative)?;
+                        SimpleResult::Ok
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainCumulative(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainAllowedHosts {
+                id: d_id,
+                allowed_hosts,
+            } => {
+                let res = if let Some(mut domain) = data.domain_by_domain_id(&d_id)? {
+                    let allowed_hosts = allowed_hosts.map(|hosts| {
+                        let mut hosts: Vec<_> =
+                            hosts.iter().map(|host| host.to_ascii_lowercase()).collect();
+                        hosts.sort();
+                        hosts.dedup();
+                        hosts
+                    });
+                    let hosts = allowed_hosts.as_ref().map_or(&[][..], |hosts| &hosts[..]);
+                    if domain.owner_id() != user.id() {
+                        SimpleResult::Err { error: "forbidden" }
+                    } else if hosts.len() > ALLOWED_HOSTS_MAX {
+                        SimpleResult::Err {
+                            error: "too_many_hosts",
+                        }
+                    } else if !hosts.iter().all(|host| is_valid_host_pattern(host)) {
+                        SimpleResult::Err {
+                            error: "invalid_host_pattern",
+                        }
+                    } else {
+                        domain.set_allowed_hosts(&*data, allowed_hosts)?;
+                        SimpleResult::Ok
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainAllowedHosts(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainCassette { id: d_id, cassette } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() != user.id() {
+                        SimpleResult::Err { error: "forbidden" }
+                    } else if let Some(cassette) = cassette {
+                        if serde_json::from_str::<Cassette>(&cassette).is_err() {
+                            SimpleResult::Err {
+                                error: "invalid_cassette",
+                            }
+                        } else if data.domain_cassette_set(domain.id(), &cassette)? {
+                            SimpleResult::Ok
+                        } else {
+                            SimpleResult::Err {
+                                error: "cassette_too_large",
+                            }
+                        }
+                    } else {
+                        data.domain_cassette_clear(domain.id())?;
+                        SimpleResult::Ok
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainCassette(res),
+                });
+                Ok(())
+            }
+            Request::DomainCassette { id: d_id } => {
+                let res = match data.domain_by_domain_id(&d_id)? {
+                    Some(domain) if domain.owner_id() == user.id() => {
+                        protocol::DomainCassetteResult {
+                            success: true,
+                            cassette: data.domain_cassette(domain.id())?,
+                            error: None,
+                        }
+                    }
+                    Some(_) => protocol::DomainCassetteResult {
+                        success: false,
+                        cassette: None,
+                        error: Some("forbidden"),
+                    },
+                    None => protocol::DomainCassetteResult {
+                        success: false,
+                        cassette: None,
+                        error: Some("not_found"),
+                    },
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::DomainCassette(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainOptions { id: d_id, options } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    if domain.owner_id() != user.id() {
+                        SimpleResult::Err { error: "forbidden" }
+                    } else {
+                        match data.domain_options_set(domain.id(), &options) {
+                            Ok(()) => SimpleResult::Ok,
+                            Err(DomainOptionsError::TooMany) => SimpleResult::Err {
+                                error: "too_many_options",
+                            },
+                            Err(DomainOptionsError::InvalidName) => SimpleResult::Err {
+                                error: "invalid_option_name",
+                            },
+                            Err(DomainOptionsError::DuplicateName) => SimpleResult::Err {
+                                error: "duplicate_option_name",
+                            },
+                            Err(DomainOptionsError::InvalidDefault) => SimpleResult::Err {
+                                error: "invalid_default",
+                            },
+                            Err(err) => {
+                                error!("Error setting domain options: {}", err);
+                                SimpleResult::Err {
+                                    error: "internal_error",
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainOptions(res),
+                });
+                Ok(())
+            }
+            Request::DomainOptions { id: d_id } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    protocol::DomainOptionsResult {
+                        success: true,
+                        options: Some(data.domain_options(domain.id())?),
+                        values: Some(data.effective_domain_options(Some(user.id()), domain.id())?),
+                        error: None,
+                    }
+                } else {
+                    protocol::DomainOptionsResult {
+                        success: false,
+                        options: None,
+                        values: None,
+                        error: Some("not_found"),
+                    }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::DomainOptions(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainOptionValues { id: d_id, values } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    match data.user_domain_option_values_set(user.id(), domain.id(), &values) {
+                        Ok(()) => SimpleResult::Ok,
+                        Err(DomainOptionsError::UnknownOption) => SimpleResult::Err {
+                            error: "unknown_option",
+                        },
+                        Err(DomainOptionsError::InvalidValue) => SimpleResult::Err {
+                            error: "invalid_value",
+                        },
+                        Err(err) => {
+                            error!("Error setting domain option values: {}", err);
+                            SimpleResult::Err {
+                                error: "internal_error",
+                            }
+                        }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainOptionValues(res),
+                });
+                Ok(())
+            }
+            Request::UserDomainSecrets { id: d_id } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    protocol::UserDomainSecretsResult {
+                        success: true,
+                        names: Some(data.user_domain_secret_names(user.id(), domain.id())?),
+                        error: None,
+                    }
+                } else {
+                    protocol::UserDomainSecretsResult {
+                        success: false,
+                        names: None,
+                        error: Some("not_found"),
+                    }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserDomainSecrets(res),
+                });
+                Ok(())
+            }
+            Request::UserSetDomainSecret {
+                id: d_id,
+                name,
+                value,
+            } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&d_id)? {
+                    if let Some(value) = value {
+                        match data.user_domain_secret_set(user.id(), domain.id(), &name, &value) {
+                            Ok(()) => SimpleResult::Ok,
+                            Err(UserSecretError::NameEmpty) => SimpleResult::Err {
+                                error: "name_empty",
+                            },
+                            Err(UserSecretError::NameTooLong) => SimpleResult::Err {
+                                error: "name_too_long",
+                            },
+                            Err(UserSecretError::ValueTooLarge) => SimpleResult::Err {
+                                error: "value_too_large",
+                            },
+                            Err(UserSecretError::TooMany) => SimpleResult::Err {
+                                error: "too_many_secrets",
+                            },
+                            Err(err) => {
+                                error!("Error setting user domain secret: {}", err);
+                                SimpleResult::Err {
+                                    error: "internal_error",
+                                }
+                            }
+                        }
+                    } else {
+                        data.user_domain_secret_delete(user.id(), domain.id(), &name)?;
+                        SimpleResult::Ok
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserSetDomainSecret(res),
+                });
+                Ok(())
+            }
+            Request::UserDomainScriptRevisions {
+                id: d_id,
+                before,
+                limit,
+            } => {
+                let res = match data.domain_by_domain_id(&d_id)? {
+                    // old revisions may contain things that were removed from the script on
+                    // purpose, so only the owner can see them
+                    Some(domain) if domain.owner_id() == user.id() => {
+                        let current = data.current_script_revision(domain.id())?;
+                        let limit = limit.min(SCRIPT_REVISIONS_MAX_LIMIT);
+                        let mut revisions = Vec::new();
+                        for revision in data.script_revisions(domain.id(), before, limit)? {
+                            revisions.push(protocol::ResponseScriptRevision {
+                                id: revision.id,
+                                date: revision.date,
+                                author: data
+                                    .user(revision.author_id)?
+                                    .map(|author| author.name().to_string()),
+                                message: revision.message,
+                                current: current == Some(revision.id),
+                            });
+                        }
+                        protocol::DomainScriptRevisionsResult {
+                            success: true,
+                            revisions: Some(revisions),
+                            error: None,
+                        }
+                    }
+                    Some(_) => protocol::DomainScriptRevisionsResult {
+                        success: false,
+                        revisions: None,
+                        error: Some("forbidden"),
+                    },
+                    None => protocol::DomainScriptRevisionsResult {
+                        success: false,
+                        revisions: None,
+                        error: Some("not_found"),
+                    },
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserDomainScriptRevisions(res),
+                });
+                Ok(())
+            }
+            Request::UserDomainScriptRevision { id: d_id, revision } => {
+                let res = match data.domain_by_domain_id(&d_id)? {
+                    Some(domain) if domain.owner_id() == user.id() => {
+                        match data.script_revision(domain.id(), revision)? {
+                            Some(revision) => {
+                                let current = data.current_script_revision(domain.id())?;
+                                protocol::DomainScriptRevisionResult {
+                                    success: true,
+                                    revision: Some(protocol::ResponseScriptRevision {
+                                        id: revision.id(),
+                                        date: revision.date().into(),
+                                        author: data
+                                            .user(revision.author_id())?
+                                            .map(|author| author.name().to_string()),
+                                        message: revision.message().into(),
+                                        current: current == Some(revision.id()),
+                                    }),
+                                    script: Some(revision.script().into()),
+                                    error: None,
+                                }
+                            }
+                            None => protocol::DomainScriptRevisionResult {
+                                success: false,
+                                revision: None,
+                                script: None,
+                                error: Some("revision_not_found"),
+                            },
+                        }
+                    }
+                    Some(_) => protocol::DomainScriptRevisionResult {
+                        success: false,
+                        revision: None,
+                        script: None,
+                        error: Some("forbidden"),
+                    },
+                    None => protocol::DomainScriptRevisionResult {
+                        success: false,
+                        revision: None,
+                        script: None,
+                        error: Some("not_found"),
+                    },
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserDomainScriptRevision(res),
+                });
+                Ok(())
+            }
+            Request::UserDiffDomainScriptRevisions { id: d_id, from, to } => {
+                let res = match data.domain_by_domain_id(&d_id)? {
+                    Some(domain) if domain.owner_id() == user.id() => match (
+                        data.script_revision(domain.id(), from)?,
+                        data.script_revision(domain.id(), to)?,
+                    ) {
+                        (Some(from), Some(to)) => protocol::DomainScriptDiffResult {
+                            success: true,
+                            diff: Some(diff_scripts(
+                                from.script(),
+                                to.script(),
+                                &format!("revision {}", from.id()),
+                                &format!("revision {}", to.id()),
+                            )),
+                            error: None,
+                        },
+                        _ => protocol::DomainScriptDiffResult {
+                            success: false,
+                            diff: None,
+                            error: Some("revision_not_found"),
+                        },
+                    },
+                    Some(_) => protocol::DomainScriptDiffResult {
+                        success: false,
+                        diff: None,
+                        error: Some("forbidden"),
+                    },
+                    None => protocol::DomainScriptDiffResult {
+                        success: false,
+                        diff: None,
+                        error: Some("not_found"),
+                    },
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserDiffDomainScriptRevisions(res),
+                });
+                Ok(())
+            }
+            Request::UserRollbackDomainScript { id: d_id, revision } => {
+                let res = match data.domain_by_domain_id(&d_id)? {
+                    Some(mut domain) if domain.owner_id() == user.id() => {
+                        match domain.rollback_script(&*data, user.id(), revision)? {
+                            Some(revision) => protocol::DomainScriptRollbackResult {
+                                success: true,
+                                revision: Some(revision),
+                                error: None,
+                            },
+                            None => protocol::DomainScriptRollbackResult {
+                                success: false,
+                                revision: None,
+                                error: Some("revision_not_found"),
+                            },
+                        }
+                    }
+                    Some(_) => protocol::DomainScriptRollbackResult {
+                        success: false,
+                        revision: None,
+                        error: Some("forbidden"),
+                    },
+                    None => protocol::DomainScriptRollbackResult {
+                        success: false,
+                        revision: None,
+                        error: Some("not_found"),
+                    },
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserRollbackDomainScript(res),
+                });
+                Ok(())
+            }
+            Request::ScriptLimits => {
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::ScriptLimits(protocol::ResponseScriptLimits {
+                        default: default_script_limits(),
+                        max: max_script_limits(),
+                    }),
+                });
+                Ok(())
+            }
             Request::UserSubscribeDomain { id: domain_id } => {
                 let res = if let Some(domain) = data.domain_by_domain_id(&domain_id)? {
                     if domain.owner_id() == user.id() {
@@ -462,6 +1233,25 @@ impl User {
                 // TODO: emit events
                 Ok(())
             }
+            Request::UserConfirmDomainAllowedHosts { id: domain_id } => {
+                let res = if let Some(domain) = data.domain_by_domain_id(&domain_id)? {
+                    if data.is_user_subscribed(user.id(), &domain)? {
+                        data.user_confirm_domain_allowed_hosts(user.id(), &domain)?;
+                        SimpleResult::Ok
+                    } else {
+                        SimpleResult::Err {
+                            error: "not_subscribed",
+                        }
+                    }
+                } else {
+                    SimpleResult::Err { error: "not_found" }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::UserConfirmDomainAllowedHosts(res),
+                });
+                Ok(())
+            }
             Request::UserSources => {
                 conn.do_send(UserConnMsg::Response {
                     id,
@@ -550,6 +1340,7 @@ impl User {
                                 last_updated: source.date_updated().map(|s| s.to_string()),
                                 data: source.tags().map_err(DataError::from)?,
                                 items: source.items().map_err(DataError::from)?,
+                                script_revision: source.script_revision(),
                             })
                         } else {
                             None
@@ -633,6 +1424,78 @@ impl User {
                 });
                 Ok(())
             }
+            Request::SourceFetchHistory { uri, before, limit } => {
+                let uri = match canonicalize_uri(&uri) {
+                    Ok(uri) => uri,
+                    Err(()) => {
+                        conn.do_send(UserConnMsg::Response {
+                            id,
+                            data: Response::SourceFetchHistory(
+                                protocol::SourceFetchHistoryResult {
+                                    success: false,
+                                    entries: None,
+                                    error: Some("invalid_uri"),
+                                },
+                            ),
+                        });
+                        return Ok(());
+                    }
+                };
+                let is_domain_owner = data
+                    .domain_by_domain_id(uri.scheme())?
+                    .map_or(false, |domain| domain.owner_id() == user.id());
+                let uri = uri.to_string();
+
+                // domain owners can see all fetches, other users only see their own and those of
+                // the auto fetcher. Logs of fetches that read another user's secrets are hidden,
+                // since they may contain data derived from them
+                let res = if is_domain_owner || data.user_source(user.id(), &uri)?.is_some() {
+                    let visible_to = if is_domain_owner {
+                        None
+                    } else {
+                        Some(user.id())
+                    };
+                    let limit = limit.min(FETCH_HISTORY_MAX_LIMIT);
+                    let mut entries = Vec::new();
+                    for entry in data.fetch_history(&uri, visible_to, before, limit)? {
+                        entries.push(protocol::ResponseFetchHistoryEntry {
+                            id: entry.id(),
+                            date: entry.date().into(),
+                            initiator: match entry.initiator() {
+                                None => protocol::FetchInitiator::Auto,
+                                Some(initiator) if initiator == user.id() => {
+                                    protocol::FetchInitiator::User
+                                }
+                                Some(_) => protocol::FetchInitiator::Other,
+                            },
+                            success: entry.success(),
+                            duration: entry.duration().as_secs_f64(),
+                            version_hash: entry.version_hash().map(|s| s.to_string()),
+                            log: if entry.read_secrets() && entry.initiator() != Some(user.id()) {
+                                None
+                            } else {
+                                Some(entry.log()?.into_iter().map(|x| x.into()).collect())
+                            },
+                        });
+                    }
+                    protocol::SourceFetchHistoryResult {
+                        success: true,
+                        entries: Some(entries),
+                        error: None,
+                    }
+                } else {
+                    protocol::SourceFetchHistoryResult {
+                        success: false,
+                        entries: None,
+                        error: Some("not_found"),
+                    }
+                };
+                conn.do_send(UserConnMsg::Response {
+                    id,
+                    data: Response::SourceFetchHistory(res),
+                });
+                Ok(())
+            }
             Request::SetSourceUserData {
                 uri,
                 data: user_data,